@@ -1,10 +1,22 @@
+use crate::compile::literals::{alternation_of_literals, Literals};
+use crate::compile::Gex;
+use crate::engines::aho_corasick::AhoCorasick;
+use crate::engines::backtrack::{DEFAULT_STEP_LIMIT, DEFAULT_VISITED_CAPACITY};
+use crate::engines::shift_and::{Fragment, ShiftAnd, ShiftAndBuilder};
+use crate::gex::classes::DEFAULT_MAX_DENSE_PAIRS;
 use crate::gex::simple_machines::{
-    digit_char_machine, machine_for, machine_for_character, manual_character_class_machine,
-    whitespace_char_machine, wildcard_machine, word_char_machine,
+    atomic_machine, backreference_machine, call_machine, digit_char_machine, lookaround_machine,
+    machine_for, machine_for_character, manual_character_class_machine, whitespace_char_machine,
+    wildcard_machine, word_boundary_machine, word_char_machine,
 };
-use crate::gex::GexMachine;
+use crate::gex::{GexMachine, LookKind};
+use crate::matcher::MatchKind;
 use crate::railroad::{Ast, AstNode, SyntaxError};
-use crate::tokenize::{tokenize, CharacterClassType, LiteralType, QuantifierType, TokenizeError};
+use crate::tokenize::{
+    tokenize, CharacterClassType, GroupType, LiteralType, QuantifierType, Token, TokenType,
+    TokenizeError,
+};
+use std::collections::HashMap;
 use std::io;
 
 use std::error;
@@ -17,6 +29,15 @@ pub enum CompilerError {
     LexicalError(TokenizeError),
     SyntaxError(SyntaxError),
     MissingOperand(String),
+    InvalidBackreference(String),
+    InvalidSubroutine(String),
+    /// Two groups of the pattern share a name, like the `n` of `(?<n>a)(?<n>b)`.
+    DuplicateGroupName(String),
+    Unsupported(String),
+    /// The pattern goes past one of the compile options' limits.
+    LimitExceeded(Limit, String),
+    /// An error in one of the patterns of a set, by its index.
+    InPattern(usize, Box<CompilerError>),
     Catastrophic(String),
 }
 
@@ -28,6 +49,16 @@ impl fmt::Display for CompilerError {
             CompilerError::LexicalError(terror) => write!(f, "Invalid Token: {}", terror),
             CompilerError::SyntaxError(serror) => write!(f, "Invalid Syntax: {}", serror),
             CompilerError::MissingOperand(msg) => write!(f, "Operand Missing: {}", msg),
+            CompilerError::InvalidBackreference(msg) => {
+                write!(f, "Invalid Backreference: {}", msg)
+            }
+            CompilerError::InvalidSubroutine(msg) => write!(f, "Invalid Subroutine: {}", msg),
+            CompilerError::DuplicateGroupName(msg) => {
+                write!(f, "Duplicate Group Name: {}", msg)
+            }
+            CompilerError::Unsupported(msg) => write!(f, "Unsupported: {}", msg),
+            CompilerError::LimitExceeded(_, msg) => write!(f, "Limit Exceeded: {}", msg),
+            CompilerError::InPattern(idx, error) => write!(f, "Pattern {}: {}", idx, error),
             CompilerError::Catastrophic(msg) => write!(f, "Catastrophic Error: {}", msg),
         }
     }
@@ -35,7 +66,7 @@ impl fmt::Display for CompilerError {
 
 impl From<CompilerError> for io::Error {
     fn from(err: CompilerError) -> io::Error {
-        io::Error::new(io::ErrorKind::Other, err)
+        io::Error::other(err)
     }
 }
 
@@ -45,42 +76,440 @@ fn get_operand(info: &str, combination_stack: &mut Vec<GexMachine>) -> Result<Ge
         .ok_or_else(|| CompilerError::MissingOperand(info.to_string()))
 }
 
+/// Settings that change how a pattern is compiled.
+#[derive(Debug, Default, Clone)]
+pub struct CompileOptions {
+    pub match_kind: MatchKind,
+    pub limits: Limits,
+}
+
+/// Bounds on what compiling a pattern, and searching with it, may take, for patterns from
+/// sources that can't be trusted not to exhaust memory.
+///
+/// By default the pattern's length and its repetition counts are unbounded: the memory either
+/// can take is already bounded by `max_states`, which every copy a count unrolls into counts
+/// against. Set them to reject such patterns before any of their states are built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Longest pattern, in bytes; `usize::MAX`, no limit, by default.
+    pub max_pattern_length: usize,
+    /// Most states the pattern's NFA may have, counting the copies counted repetitions unroll
+    /// into and the machines of lookarounds.
+    pub max_states: usize,
+    /// Most groups that may be open at once.
+    pub max_nesting_depth: usize,
+    /// Largest count a counted repetition may give, like the 1000 of `a{2,1000}`; `u32::MAX`,
+    /// no limit, by default.
+    pub max_repetition: u32,
+    /// Most (state, character class) pairs the NFA's table of transitions by class may hold.
+    /// Past it the table isn't built, and searches test each character against the rules.
+    pub max_dense_table_pairs: usize,
+    /// Most memory, in bytes, a search may use to remember which states the backtracker has
+    /// tried at which positions. Inputs too long for it are searched by the automaton instead.
+    pub search_cache_bytes: usize,
+    /// Most states the backtracker may explore from any one start position in a search of a
+    /// pattern with backreferences, atomic groups or calls, which it can't memoize. Past it,
+    /// searches that can report an error, like `Gex::try_find_at` and `find_all_until`, give
+    /// up with one; `find_at` and `captures_at` search on.
+    pub max_backtrack_steps: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_pattern_length: usize::MAX,
+            max_states: 1 << 18,
+            max_nesting_depth: 250,
+            max_repetition: u32::MAX,
+            max_dense_table_pairs: DEFAULT_MAX_DENSE_PAIRS,
+            search_cache_bytes: DEFAULT_VISITED_CAPACITY / 8,
+            max_backtrack_steps: DEFAULT_STEP_LIMIT,
+        }
+    }
+}
+
+/// Which of the `Limits` a pattern went past.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    PatternLength,
+    States,
+    NestingDepth,
+    Repetition,
+}
+
+pub fn compile(input: &str) -> Result<Gex> {
+    compile_with(input, &CompileOptions::default())
+}
+
 // NOTE: maybe it would have been easier to figure out token/astnode type layout by writing this
 // first??
-pub fn compile(input: &str) -> Result<GexMachine> {
-    let tokens = tokenize(input).or_else(|err| Err(CompilerError::LexicalError(err)))?;
-    // TODO: error handling
+pub fn compile_with(input: &str, options: &CompileOptions) -> Result<Gex> {
+    let ast = parse(input, &options.limits)?;
+
+    // Large keyword alternations are searched without ever building the alternation NFA
+    if let Some(gex) = automaton_for(&ast, input, options) {
+        return Ok(gex);
+    }
+    compile_machine_with(&ast, input, &options.limits)
+        .map(|machine| machine.with_match_kind(options.match_kind))
+        .map(|machine| gex_for(machine, options))
+}
+
+/// Like `compile_with`, along with the pattern's NFA, which is built only once for both.
+pub(crate) fn compile_with_nfa(input: &str, options: &CompileOptions) -> Result<(Gex, GexMachine)> {
+    let ast = parse(input, &options.limits)?;
+    let machine =
+        compile_machine_with(&ast, input, &options.limits)?.with_match_kind(options.match_kind);
+    let gex = match automaton_for(&ast, input, options) {
+        Some(gex) => gex,
+        None => gex_for(machine.clone(), options),
+    };
+    Ok((gex, machine))
+}
+
+/// A search built straight from the parsed pattern, for patterns simple enough for one.
+fn automaton_for(ast: &Ast, input: &str, options: &CompileOptions) -> Option<Gex> {
+    if let Some(alternatives) = alternation_of_literals(ast, input) {
+        return Some(AhoCorasick::new(&alternatives, options.match_kind).into());
+    }
+    compile_shift_and(ast, input, options.match_kind).map(Gex::from)
+}
+
+/// A search with the pattern's NFA, within the limits on searching.
+fn gex_for(machine: GexMachine, options: &CompileOptions) -> Gex {
+    Gex::from(machine)
+        .with_search_cache_bytes(options.limits.search_cache_bytes)
+        .with_backtrack_step_limit(options.limits.max_backtrack_steps)
+}
+
+/// The NFA for a pattern, even when `compile_with` would search it with another engine.
+pub fn compile_nfa(input: &str, options: &CompileOptions) -> Result<GexMachine> {
+    let ast = parse(input, &options.limits)?;
+    compile_machine_with(&ast, input, &options.limits)
+        .map(|machine| machine.with_match_kind(options.match_kind))
+}
+
+/// Tokenize and parse the pattern, within the limits on its length and nesting.
+fn parse(input: &str, limits: &Limits) -> Result<Ast> {
+    if input.len() > limits.max_pattern_length {
+        return Err(CompilerError::LimitExceeded(
+            Limit::PatternLength,
+            format!(
+                "the pattern is {} bytes long, past the limit of {}",
+                input.len(),
+                limits.max_pattern_length
+            ),
+        ));
+    }
+    let tokens = tokenize(input).map_err(CompilerError::LexicalError)?;
+
+    let mut depth = 0usize;
+    for token in tokens.iter() {
+        match token.kind {
+            TokenType::OpenGroup(_) => depth += 1,
+            TokenType::CloseGroup => depth = depth.saturating_sub(1),
+            _ => continue,
+        }
+        if depth > limits.max_nesting_depth {
+            return Err(CompilerError::LimitExceeded(
+                Limit::NestingDepth,
+                format!(
+                    "the group at {} is nested past the limit of {}",
+                    token.start(),
+                    limits.max_nesting_depth
+                ),
+            ));
+        }
+    }
+
+    let ast = Ast::from_tokens(tokens).map_err(CompilerError::SyntaxError)?;
+    // Caught before any engine is picked, as the automata don't number groups themselves
+    GroupTable::from_ast(&ast, input)?;
+    Ok(ast)
+}
+
+/// Capturing groups of a pattern, numbered in the order their groups open.
+struct GroupTable<'a> {
+    count: usize,
+    names: HashMap<&'a str, u16>,
+    /// Where each group opens, in group number order.
+    starts: Vec<usize>,
+}
+
+impl<'a> GroupTable<'a> {
+    /// The groups of the pattern, failing when two of them share a name.
+    fn from_ast(ast: &Ast, input: &'a str) -> Result<Self> {
+        let mut open_tokens: Vec<&Token> = ast
+            .0
+            .iter()
+            .filter_map(|ast_node| match ast_node {
+                AstNode::Group(group_type, token, _) if group_type.is_capturing() => Some(token),
+                _ => None,
+            })
+            .collect();
+        open_tokens.sort_by_key(|token| token.start());
+
+        let mut names = HashMap::new();
+        for (idx, token) in open_tokens.iter().enumerate() {
+            let Some(name) = token.group_name(input) else {
+                continue;
+            };
+            if names.insert(name, idx as u16 + 1).is_some() {
+                return Err(CompilerError::DuplicateGroupName(format!(
+                    "the group at {} is named {:?}, like an earlier group",
+                    token.start(),
+                    name
+                )));
+            }
+        }
+
+        Ok(GroupTable {
+            count: open_tokens.len(),
+            names,
+            starts: open_tokens.iter().map(|token| token.start()).collect(),
+        })
+    }
+
+    /// The number of the capturing group opened by the token.
+    fn number(&self, token: &Token) -> Option<u16> {
+        self.starts
+            .binary_search(&token.start())
+            .ok()
+            .map(|idx| idx as u16 + 1)
+    }
+
+    /// The group number a subroutine call token refers to, 0 for the whole pattern.
+    fn resolve_call(&self, token: &Token, input: &str) -> Result<u16> {
+        let text = &input[token.input_range()];
+        let target = &text[2..text.len() - 1];
+        let group_number = match target {
+            "R" => Some(0),
+            _ => target
+                .parse::<u16>()
+                .ok()
+                .filter(|&group_number| group_number as usize <= self.count),
+        };
+        group_number.ok_or_else(|| {
+            CompilerError::InvalidSubroutine(format!(
+                "{} at {} calls no group",
+                text,
+                token.start()
+            ))
+        })
+    }
+
+    /// The group number a backreference token refers to.
+    fn resolve(&self, token: &Token, input: &str) -> Result<u16> {
+        let text = &input[token.input_range()];
+        let group_number = match text.strip_prefix(r"\k<") {
+            Some(name) => self.names.get(name.trim_end_matches('>')).copied(),
+            None => text[1..]
+                .parse::<u16>()
+                .ok()
+                .filter(|&group_number| group_number as usize <= self.count),
+        };
+        group_number.ok_or_else(|| {
+            CompilerError::InvalidBackreference(format!(
+                "{} at {} refers to no group",
+                text,
+                token.start()
+            ))
+        })
+    }
+}
+
+fn literal_machine(
+    ltype: &LiteralType,
+    token: &Token,
+    input: &str,
+    groups: &GroupTable,
+) -> Result<GexMachine> {
+    Ok(match ltype {
+        LiteralType::Wildcard => wildcard_machine(),
+        LiteralType::Character => machine_for(*token, input),
+        LiteralType::EscapedCharacter => {
+            let escaped = input[token.input_range()]
+                .chars()
+                .nth(1)
+                .expect("Unfinished escape sequence during compilation");
+            machine_for_character(escaped)
+        }
+        LiteralType::CharacterClass(class_type, positive) => match class_type {
+            CharacterClassType::Word => word_char_machine(*positive),
+            CharacterClassType::Digit => digit_char_machine(*positive),
+            CharacterClassType::Whitespace => whitespace_char_machine(*positive),
+            CharacterClassType::Manual => {
+                manual_character_class_machine(*positive, &input[token.input_range()])
+            }
+        },
+        LiteralType::WordBoundary(positive) => word_boundary_machine(*positive),
+        LiteralType::Backreference => backreference_machine(groups.resolve(token, input)?),
+        LiteralType::Subroutine => call_machine(groups.resolve_call(token, input)?),
+        // TODO: determine if this panic is necessary
+        LiteralType::EmptyString => panic!("Empty string not implemented"),
+    })
+}
+
+/// Build the bit-parallel automaton for a parsed pattern, if the pattern is short enough, has a
+/// bounded match length, and has no groups to capture.
+pub fn compile_shift_and(ast: &Ast, input: &str, match_kind: MatchKind) -> Option<ShiftAnd> {
+    let mut builder = ShiftAndBuilder::new();
+    let mut stack: Vec<Fragment> = Vec::with_capacity(2);
+    let groups = GroupTable::from_ast(ast, input).ok()?;
+
+    for ast_node in ast.0.iter() {
+        let fragment = match ast_node {
+            AstNode::Literal(
+                LiteralType::EmptyString
+                | LiteralType::WordBoundary(_)
+                | LiteralType::Backreference
+                | LiteralType::Subroutine,
+                _,
+            ) => return None,
+            AstNode::Literal(ltype, token) => {
+                // Literal machines are a start state, the class state, then the accept, unless
+                // they match the empty string
+                match literal_machine(ltype, token, input, &groups)
+                    .ok()?
+                    .states
+                    .as_slice()
+                {
+                    [_, class, _] => builder.position(class.clone())?,
+                    _ => builder.empty(),
+                }
+            }
+            AstNode::Quantifier(QuantifierType::ZeroOrOne, _) => builder.zero_or_one(stack.pop()?),
+            AstNode::Cons(_, _) => {
+                let right = stack.pop()?;
+                let left = stack.pop()?;
+                builder.cons(left, right)
+            }
+            AstNode::Alternation(_, _) => {
+                let right = stack.pop()?;
+                let left = stack.pop()?;
+                builder.or(left, right)
+            }
+            AstNode::Group(GroupType::NonCapturing, _, _) => stack.pop()?,
+            AstNode::Quantifier(_, _) | AstNode::Group(..) => return None,
+        };
+        stack.push(fragment);
+    }
+
+    let pattern = stack.pop()?;
+    if !stack.is_empty() {
+        return None;
+    }
+    builder.build(pattern, match_kind)
+}
+
+/// Repeat the operand of a counted repetition, adding the states it unrolls into to `unrolled`.
+///
+/// The size is checked before anything is built, so a pathological count is an error rather
+/// than an attempt to allocate the machine.
+fn counted(
+    operand: GexMachine,
+    min: u32,
+    max: Option<u32>,
+    unrolled: &mut usize,
+    limits: &Limits,
+) -> Result<GexMachine> {
+    let bounds = || match max {
+        Some(max) if max == min => format!("{{{}}}", min),
+        Some(max) => format!("{{{},{}}}", min, max),
+        None => format!("{{{},}}", min),
+    };
+    let count = max.unwrap_or(min);
+    if count > limits.max_repetition {
+        return Err(CompilerError::LimitExceeded(
+            Limit::Repetition,
+            format!(
+                "{} repeats past the limit of {}",
+                bounds(),
+                limits.max_repetition
+            ),
+        ));
+    }
+    // Every copy may need a state of its own to enter it by
+    let size = (count.max(1) as usize).saturating_mul(operand.total_size() + 1);
+    *unrolled = unrolled.saturating_add(size);
+    if *unrolled > limits.max_states {
+        return Err(CompilerError::LimitExceeded(
+            Limit::States,
+            format!(
+                "repeating a {} state pattern {} unrolls past the limit of {} states",
+                operand.total_size(),
+                bounds(),
+                limits.max_states
+            ),
+        ));
+    }
+    Ok(operand.repeat(min as usize, max.map(|max| max as usize)))
+}
+
+/// Wrap the machine for a lookaround's body in an assertion.
+///
+/// Bodies are matched on their own, apart from the rest of the pattern, so they can neither
+/// capture groups for it nor refer back to its groups.
+fn lookaround(kind: LookKind, body: GexMachine, token: &Token) -> Result<GexMachine> {
+    if body.group_count() > 0 {
+        return Err(CompilerError::Unsupported(format!(
+            "capturing group inside the lookaround at {}; use (?:...) instead",
+            token.start()
+        )));
+    }
+    if body.needs_backtracking() {
+        return Err(CompilerError::Unsupported(format!(
+            "backreference or atomic group inside the lookaround at {}",
+            token.start()
+        )));
+    }
+    Ok(lookaround_machine(kind, body))
+}
+
+/// Build the NFA for a parsed pattern.
+pub fn compile_machine(ast: &Ast, input: &str) -> Result<GexMachine> {
+    compile_machine_with(ast, input, &Limits::default())
+}
 
-    let ast = Ast::from_tokens(tokens).or_else(|err| Err(CompilerError::SyntaxError(err)))?;
+/// Fail once the machine has more states than the limit allows.
+fn check_states(machine: &GexMachine, limits: &Limits) -> Result<()> {
+    if machine.total_size() > limits.max_states {
+        return Err(CompilerError::LimitExceeded(
+            Limit::States,
+            format!(
+                "the pattern needs {} states, past the limit of {}",
+                machine.total_size(),
+                limits.max_states
+            ),
+        ));
+    }
+    Ok(())
+}
 
+/// Build the NFA for a parsed pattern, failing as soon as it grows past the limit on states.
+pub fn compile_machine_with(ast: &Ast, input: &str, limits: &Limits) -> Result<GexMachine> {
     let mut combination_stack: Vec<GexMachine> = Vec::with_capacity(2);
+    let groups = GroupTable::from_ast(ast, input)?;
+    // Groups called as subroutines keep a copy of their machine, appended once the pattern is
+    // built so calls made before the group closes can reach it
+    let called: Vec<u16> = ast
+        .0
+        .iter()
+        .filter_map(|ast_node| match ast_node {
+            AstNode::Literal(LiteralType::Subroutine, token) => {
+                groups.resolve_call(token, input).ok()
+            }
+            _ => None,
+        })
+        .collect();
+    let mut subroutines: Vec<(u16, GexMachine)> = Vec::new();
+    let mut unrolled = 0;
 
     for ast_node in ast.0.iter() {
         match ast_node {
-            AstNode::Literal(ltype, token) => match ltype {
-                LiteralType::Wildcard => combination_stack.push(wildcard_machine()),
-                LiteralType::Character => combination_stack.push(machine_for(*token, input)),
-                LiteralType::EscapedCharacter => {
-                    let escaped = input[token.input_range()]
-                        .chars()
-                        .nth(1)
-                        .expect("Unfinished escape sequence during compilation");
-                    combination_stack.push(machine_for_character(escaped));
-                }
-                LiteralType::CharacterClass(class_type, positive) => {
-                    let class_machine = match class_type {
-                        CharacterClassType::Word => word_char_machine(*positive),
-                        CharacterClassType::Digit => digit_char_machine(*positive),
-                        CharacterClassType::Whitespace => whitespace_char_machine(*positive),
-                        CharacterClassType::Manual => {
-                            manual_character_class_machine(*positive, &input[token.input_range()])
-                        }
-                    };
-                    combination_stack.push(class_machine);
-                }
-                // TODO: determine if this panic is necessary
-                LiteralType::EmptyString => panic!("Empty string not implemented"),
-            },
+            AstNode::Literal(ltype, token) => {
+                combination_stack.push(literal_machine(ltype, token, input, &groups)?)
+            }
             AstNode::Quantifier(qtype, _) => match qtype {
                 QuantifierType::ZeroOrMore => {
                     let operand = get_operand("'*' (zero or more)", &mut combination_stack)?;
@@ -94,6 +523,10 @@ pub fn compile(input: &str) -> Result<GexMachine> {
                     let operand = get_operand("'?' (zero or one)", &mut combination_stack)?;
                     combination_stack.push(operand.zero_or_one());
                 }
+                QuantifierType::Counted(min, max) => {
+                    let operand = get_operand("'{m,n}' (counted)", &mut combination_stack)?;
+                    combination_stack.push(counted(operand, *min, *max, &mut unrolled, limits)?);
+                }
             },
             AstNode::Cons(_, _) => {
                 let right = get_operand("'cons' (right hand side)", &mut combination_stack)?;
@@ -106,20 +539,56 @@ pub fn compile(input: &str) -> Result<GexMachine> {
                 let left = get_operand("'|' (alternation left hand side)", &mut combination_stack)?;
                 combination_stack.push(left.or(right));
             }
-            AstNode::Group(_) => {
+            AstNode::Group(group_type, token, _) => {
                 let operand = get_operand("'()' (grouping)", &mut combination_stack)?;
-                combination_stack.push(operand.group());
+                let grouped = match group_type {
+                    GroupType::Capturing | GroupType::Named => {
+                        if let Some(group_number) = groups
+                            .number(token)
+                            .filter(|group_number| called.contains(group_number))
+                        {
+                            subroutines.push((group_number, operand.clone()));
+                        }
+                        operand.group()
+                    }
+                    GroupType::NonCapturing => operand,
+                    GroupType::Atomic => atomic_machine(operand),
+                    GroupType::Lookahead(positive) => {
+                        lookaround(LookKind::Ahead(*positive), operand, token)?
+                    }
+                    GroupType::Lookbehind(positive) => {
+                        lookaround(LookKind::Behind(*positive), operand, token)?
+                    }
+                };
+                combination_stack.push(grouped);
             }
         }
+        if let Some(machine) = combination_stack.last() {
+            check_states(machine, limits)?;
+        }
     }
-    combination_stack
+    let prefilter = Literals::from_ast(ast, input).prefilter();
+
+    let machine = combination_stack
         .pop()
-        .ok_or_else(|| CompilerError::Catastrophic("No NFA created".to_string()))
+        .map(|machine| machine.with_subroutines(subroutines))
+        .ok_or_else(|| CompilerError::Catastrophic("No NFA created".to_string()))?;
+    check_states(&machine, limits)?;
+    let names = groups
+        .names
+        .iter()
+        .map(|(&name, &group_number)| (name.to_string(), group_number))
+        .collect();
+    Ok(machine
+        .with_prefilter(prefilter)
+        .with_dense_table_within(limits.max_dense_table_pairs)
+        .with_group_names(names))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::engines::backtrack::BacktrackError;
     use crate::matcher::{Match, Matcher};
 
     macro_rules! assert_match {
@@ -151,7 +620,7 @@ mod tests {
     macro_rules! assert_capture_match {
         ($captures:expr, $idx:expr, $start:expr, $end:expr) => {
             assert_eq!(
-                *$captures.get(&$idx).unwrap(),
+                $captures.get($idx).unwrap(),
                 Match {
                     start: $start,
                     end: $end
@@ -171,7 +640,8 @@ mod tests {
                 assert_capture_match!(captures, $idx, $start, $end);
             )*
 
-            assert_eq!(captures.len(), [$( $idx ),*].len());
+            // Every other group took no part in the match
+            assert_eq!(captures.iter().flatten().count(), [$( $idx ),*].len());
         };
     }
 
@@ -272,12 +742,57 @@ mod tests {
     #[test]
     fn test_wildcard_matches() {
         assert_match!(r".*d", "mod", "mod");
-        assert_match!(r".*d", "my mod in rust", "mod");
+        assert_match!(r".*d", "my mod in rust", "my mod");
+    }
+
+    #[test]
+    fn test_prefiltered_matches() {
+        assert_match!(r"ERROR: \w+", "12:00 ERROR: disk full", "ERROR: disk");
+        assert_match!(r"(fn|let) \w+", "pub fn main", "fn main");
+        assert_match!(r"\d+-id-\d+", "ref 12-id-34 end", "12-id-34");
+        assert_no_match!(r"ERROR: \w+", "12:00 WARN: disk full");
+        assert_no_match!(r"\d+-id-\d+", "ref 12-ID-34 end");
+
+        let gex_machine = compile(r"TODO\(").unwrap();
+        assert_eq!(
+            gex_machine.find_at("TODO( and TODO(", 1),
+            Some(Match { start: 10, end: 15 })
+        );
+    }
+
+    #[test]
+    fn test_literal_alternation_engine() {
+        let keywords = (0..3000)
+            .map(|idx| format!("deprecated_call_{}", idx))
+            .collect::<Vec<String>>()
+            .join("|");
+        let searcher = compile(&keywords).unwrap();
+
+        assert_eq!(searcher.engine_name(), "aho-corasick");
+        assert_match!(
+            &keywords,
+            "x = deprecated_call_2999(deprecated_call_1)",
+            "deprecated_call_2999"
+        );
+        assert_eq!(compile(r"a\.b|ab").unwrap().engine_name(), "aho-corasick");
+        assert_match!(r"a|ab", "xab", "ab");
+        assert_eq!(compile(r"a+|ab").unwrap().engine_name(), "nfa");
+    }
+
+    #[test]
+    fn test_shift_and_engine() {
+        assert_eq!(compile(r"\d\d-[a-z]?x").unwrap().engine_name(), "shift-and");
+        assert_match!(r"\d\d-[a-z]?x", "ref 12-x and 34-yx", "12-x");
+        assert_eq!(compile(r"\d+-x").unwrap().engine_name(), "nfa");
+        assert_eq!(compile(r"(\d)-x").unwrap().engine_name(), "nfa");
+        assert_eq!(
+            compile_kind(r"ab?", MatchKind::LeftmostFirst).engine_name(),
+            "nfa"
+        );
     }
 
     #[test]
     fn test_simple_capturing_group() {
-        println!("{:?}", compile(r"(abc)").unwrap().captures(r"123abc456"));
         assert_captures!(r"(abc)", r"cdeabcdef", (0, 3, 6), (1, 3, 6));
     }
 
@@ -343,6 +858,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_named_captures() {
+        let input = "on 2024-06 at noon";
+        for pattern in [
+            r"(?<year>\d{4})-(?<month>\d\d)|(?<word>x+)",
+            r"(?<year>\d{4})-(?<month>\d\d)|(?<word>x+)\k<word>",
+        ] {
+            let gex = compile(pattern).unwrap();
+            let captures = gex.captures(input).unwrap();
+
+            assert_eq!(captures.len(), 4, "{pattern}");
+            assert_eq!(captures.name_substr("year", input), Some("2024"));
+            assert_eq!(captures.name("month"), captures.get(2));
+            assert_eq!(captures.substr(2, input), Some("06"));
+            // The group exists but took no part, unlike the name and group that don't exist
+            assert_eq!((captures.name("word"), captures.get(3)), (None, None));
+            assert_eq!((captures.name("day"), captures.get(4)), (None, None));
+            assert_eq!(
+                captures.substrs(input).collect::<Vec<_>>(),
+                [Some("2024-06"), Some("2024"), Some("06"), None]
+            );
+            assert_eq!(
+                gex.captures_in(&["on 20", "24-0", "6 at"][..]),
+                Some(captures)
+            );
+        }
+
+        let unnamed = compile("(a)|b").unwrap().captures("b").unwrap();
+        assert_eq!(
+            unnamed.iter().collect::<Vec<_>>(),
+            [Some(Match { start: 0, end: 1 }), None]
+        );
+        assert_eq!(unnamed.name("a"), None);
+    }
+
+    #[test]
+    fn test_repeated_group_captures() {
+        // A group keeps its last capture through iterations that skip it
+        assert_captures!(r"(?:(a)|b)+", "ab", (0, 0, 2), (1, 0, 1));
+        assert_captures!(r"(?:(a)|(b))+", "ab", (0, 0, 2), (1, 0, 1), (2, 1, 2));
+        assert_captures!(r"(a|b)+", "ab", (0, 0, 2), (1, 1, 2));
+        assert_captures!(r"((a)|b)*c", "abc", (0, 0, 3), (1, 1, 2), (2, 0, 1));
+    }
+
     #[test]
     fn test_empty_group() {
         assert_captures!(
@@ -356,4 +915,750 @@ mod tests {
             (5, 4, 4)
         );
     }
+
+    fn compile_kind(pattern: &str, match_kind: MatchKind) -> Gex {
+        let options = CompileOptions {
+            match_kind,
+            ..CompileOptions::default()
+        };
+        compile_with(pattern, &options).unwrap()
+    }
+
+    #[test]
+    fn test_leftmost_first_matches() {
+        let first = |pattern: &str, input: &str| {
+            compile_kind(pattern, MatchKind::LeftmostFirst)
+                .find(input)
+                .map(|found| found.substr(input).to_string())
+        };
+
+        assert_eq!(first(r"a|ab", "xab").as_deref(), Some("a"));
+        assert_eq!(first(r"ab|a", "xab").as_deref(), Some("ab"));
+        assert_eq!(first(r"\w|\w+", "abc").as_deref(), Some("a"));
+        assert_eq!(first(r"\w+|\w", "abc").as_deref(), Some("abc"));
+        assert_eq!(first(r"a*", "aaab").as_deref(), Some("aaa"));
+        assert_eq!(first(r"(ab|a)(c|bcd)", "abcd").as_deref(), Some("abc"));
+        assert_eq!(first(r"x(a|ab)", "zxab").as_deref(), Some("xa"));
+    }
+
+    #[test]
+    fn test_leftmost_longest_matches() {
+        let longest = |pattern: &str, input: &str| {
+            compile_kind(pattern, MatchKind::LeftmostLongest)
+                .find(input)
+                .map(|found| found.substr(input).to_string())
+        };
+
+        assert_eq!(longest(r"a|ab", "xab").as_deref(), Some("ab"));
+        assert_eq!(longest(r"ab|a", "xab").as_deref(), Some("ab"));
+        assert_eq!(longest(r"\w|\w+", "abc").as_deref(), Some("abc"));
+        assert_eq!(longest(r"(ab|a)(c|bcd)", "abcd").as_deref(), Some("abcd"));
+        assert_eq!(longest(r"x(a|ab)", "zxab").as_deref(), Some("xab"));
+    }
+
+    #[test]
+    fn test_match_kind_submatches() {
+        let captures = compile_kind(r"(a|ab)(bc|c)", MatchKind::LeftmostFirst)
+            .captures("abc")
+            .unwrap();
+        assert_capture_match!(captures, 0, 0, 3);
+        assert_capture_match!(captures, 1, 0, 1);
+        assert_capture_match!(captures, 2, 1, 3);
+
+        // POSIX gives the earlier group the longest span it can have
+        let captures = compile_kind(r"(a|ab)(bc|c)", MatchKind::LeftmostLongest)
+            .captures("abc")
+            .unwrap();
+        assert_capture_match!(captures, 0, 0, 3);
+        assert_capture_match!(captures, 1, 0, 2);
+        assert_capture_match!(captures, 2, 2, 3);
+
+        let captures = compile_kind(r"(a|ab)(c|bcd)(d*)", MatchKind::LeftmostFirst)
+            .captures("abcd")
+            .unwrap();
+        assert_capture_match!(captures, 1, 0, 1);
+        assert_capture_match!(captures, 2, 1, 4);
+        assert_capture_match!(captures, 3, 4, 4);
+
+        let captures = compile_kind(r"(a|ab)(c|bcd)(d*)", MatchKind::LeftmostLongest)
+            .captures("abcd")
+            .unwrap();
+        assert_capture_match!(captures, 1, 0, 2);
+        assert_capture_match!(captures, 2, 2, 3);
+        assert_capture_match!(captures, 3, 3, 4);
+    }
+
+    #[test]
+    fn test_match_kind_option() {
+        assert_eq!(
+            compile("a|b").unwrap().match_kind(),
+            MatchKind::LeftmostLongest
+        );
+        assert_eq!(
+            compile_kind(r"a|ab", MatchKind::LeftmostFirst).match_kind(),
+            MatchKind::LeftmostFirst
+        );
+        assert_eq!(
+            compile_kind(r"a\w|b", MatchKind::LeftmostFirst).match_kind(),
+            MatchKind::LeftmostFirst
+        );
+    }
+
+    #[test]
+    fn test_backreferences() {
+        let doubled = compile(r"\b(\w+)\s+\1\b").unwrap();
+        assert_eq!(doubled.engine_name(), "backtrack");
+        assert_match!(r"\b(\w+)\s+\1\b", "this is is a test", "is is");
+        assert_match!(r"\b(\w+)\s+\1\b", "the the theory", "the the");
+        assert_no_match!(r"\b(\w+)\s+\1\b", "is island");
+
+        assert_captures!(
+            r"<(?<tag>\w+)>[^<]*</\k<tag>>",
+            "<a><b>bold</b></a>",
+            (0, 3, 14),
+            (1, 4, 5)
+        );
+        assert_no_match!(r"<(\w+)>[^<]*</\1>", "<b>bold</i>");
+        assert_match!(r"(a|b)\1+", "abba", "bb");
+    }
+
+    #[test]
+    fn test_backreference_match_kinds() {
+        let longest = compile_kind(r"(a|ab)(c|bcd)\2?", MatchKind::LeftmostLongest);
+        assert_eq!(longest.find("abcdbcd"), Some(Match { start: 0, end: 7 }));
+        let first = compile_kind(r"(a|ab)(c|bcd)\2?", MatchKind::LeftmostFirst);
+        assert_eq!(first.find("abcdbcd"), Some(Match { start: 0, end: 7 }));
+        let first = compile_kind(r"(ab|a)(c|bcd)\2?", MatchKind::LeftmostFirst);
+        assert_eq!(first.find("abcdbcd"), Some(Match { start: 0, end: 3 }));
+    }
+
+    #[test]
+    fn test_invalid_backreferences() {
+        assert!(matches!(
+            compile(r"(a)\2"),
+            Err(CompilerError::InvalidBackreference(_))
+        ));
+        assert!(matches!(
+            compile(r"(?<x>a)\k<y>"),
+            Err(CompilerError::InvalidBackreference(_))
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_group_names() {
+        for pattern in [
+            r"(?<n>a)(?<n>b)\k<n>",
+            r"(?<n>a)|(?<n>b)",
+            r"(?<n>a(?<n>b))",
+            r"(?<n>a)(b)(?<n>c)",
+        ] {
+            assert!(
+                matches!(compile(pattern), Err(CompilerError::DuplicateGroupName(_))),
+                "{}",
+                pattern
+            );
+            assert!(matches!(
+                compile_nfa(pattern, &CompileOptions::default()),
+                Err(CompilerError::DuplicateGroupName(_))
+            ));
+        }
+        assert!(compile(r"(?<n>a)(?<m>b)\k<n>\k<m>").is_ok());
+    }
+
+    #[test]
+    fn test_word_boundaries() {
+        assert_eq!(compile(r"\bcat\b").unwrap().engine_name(), "nfa");
+        assert_match!(r"\bcat\b", "concat cat", "cat");
+        assert_eq!(
+            compile(r"\bcat\b").unwrap().find("concat cat"),
+            Some(Match { start: 7, end: 10 })
+        );
+        assert_no_match!(r"\bcat\b", "concat cats");
+        assert_match!(r"\Bcat\B", "cat concats", "cat");
+        assert_match!(r"\b\w", "  héllo", "h");
+    }
+
+    #[test]
+    fn test_lookahead() {
+        assert_match!(r"\w+(?=;)", "foo bar; baz", "bar");
+        assert_match!(r"foo(?!bar)\w*", "foobar foobaz", "foobaz");
+        assert_no_match!(r"a(?=b)", "ac ad");
+        assert_eq!(
+            compile(r"\d+(?= USD)")
+                .unwrap()
+                .find("pay 30 EUR or 25 USD"),
+            Some(Match { start: 14, end: 16 })
+        );
+    }
+
+    #[test]
+    fn test_lookbehind() {
+        assert_eq!(
+            compile(r"(?<=\$)\d+").unwrap().find("cost: 12 or $42"),
+            Some(Match { start: 13, end: 15 })
+        );
+        assert_match!(r"(?<!-)\b\d+", "-12 34", "34");
+        assert_match!(r"(?<=a+)b", "xaaab", "b");
+        assert_match!(r"(?<=ab|c)d", "abd", "d");
+        assert_no_match!(r"(?<=ab|c)d", "bd");
+        assert_match!(r"(?<=(?:ab)+)c", "ababc", "c");
+    }
+
+    #[test]
+    fn test_lookaround_captures() {
+        assert_captures!(r"(?<=<)(\w+)(?=>)", "x <tag>", (0, 3, 6), (1, 3, 6));
+        assert_captures!(r"(?:ab)+(c)", "ababc", (0, 0, 5), (1, 4, 5));
+    }
+
+    #[test]
+    fn test_unsupported_lookarounds() {
+        assert!(matches!(
+            compile(r"a(?=(b))"),
+            Err(CompilerError::Unsupported(_))
+        ));
+        assert!(matches!(
+            compile(r"(a)(?<=\1)"),
+            Err(CompilerError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_atomic_groups() {
+        assert_eq!(compile(r"(?>a|ab)c").unwrap().engine_name(), "backtrack");
+        assert_no_match!(r"(?>a|ab)c", "abc");
+        assert_match!(r"(?:a|ab)c", "abc", "abc");
+        assert_match!(r"(?>ab|a)c|abd", "abd", "abd");
+        assert_match!(r"(?>\d+)-", "id 123-4", "123-");
+        assert_captures!(r"(?>(a+))b", "xaab", (0, 1, 4), (1, 1, 3));
+        // Captures made inside a group that was committed to are undone when the path fails
+        assert_captures!(r"(?>(a))x|(a)y", "ay", (0, 0, 2), (2, 0, 1));
+    }
+
+    #[test]
+    fn test_possessive_quantifiers() {
+        assert_eq!(compile(r"a*+b").unwrap().engine_name(), "backtrack");
+        assert_no_match!(r"a++a", "aaaa");
+        assert_match!(r"a+a", "aaaa", "aaaa");
+        assert_no_match!(r"a?+a", "a");
+        assert_match!(r#""[^"]*+""#, r#"say "hi" now"#, r#""hi""#);
+        assert_match!(r"\w++\b", "--word--", "word");
+        assert_match!(r"x*+y?+z", "xxz", "xxz");
+    }
+
+    #[test]
+    fn test_atomic_match_kinds() {
+        // The body of an atomic group takes its first match by priority under either kind
+        let longest = compile_kind(r"x(?>a|ab)", MatchKind::LeftmostLongest);
+        assert_eq!(longest.find("xab"), Some(Match { start: 0, end: 2 }));
+        let first = compile_kind(r"x(?>ab|a)", MatchKind::LeftmostFirst);
+        assert_eq!(first.find("xab"), Some(Match { start: 0, end: 3 }));
+        assert_eq!(
+            compile_kind(r"(?>a|ab)c", MatchKind::LeftmostLongest).find("abc"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_atomic_group_in_lookaround() {
+        assert!(matches!(
+            compile(r"(?=(?>a+))a"),
+            Err(CompilerError::Unsupported(_))
+        ));
+        assert!(matches!(
+            compile(r"(?<=a++)b"),
+            Err(CompilerError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_recursive_patterns() {
+        let balanced = r"\((?:[^()]|(?R))*\)";
+        assert_eq!(compile(balanced).unwrap().engine_name(), "backtrack");
+        assert_match!(balanced, "f(a, (b, c), d) + (e", "(a, (b, c), d)");
+        assert_match!(balanced, "((x)", "(x)");
+        assert_no_match!(balanced, ")(");
+        assert_match!(r"<(?:\w|(?R))+>", "<a<b<c>>d>", "<a<b<c>>d>");
+    }
+
+    #[test]
+    fn test_subroutine_calls() {
+        assert_captures!(
+            r"\w+(\((?:[^()]|(?1))*\))",
+            "call f(x(y), z) now",
+            (0, 5, 15),
+            (1, 6, 15)
+        );
+        // Groups keep what they captured before a call
+        assert_captures!(r"(a|b)(?1)", "xab", (0, 1, 3), (1, 1, 2));
+        assert_captures!(r"(?1)-(\d+)", "1-23", (0, 0, 4), (1, 2, 4));
+        assert_match!(r"(?<pair>\[(?:\d|(?1))*\])", "x[1[2[]]]", "[1[2[]]]");
+        // Groups nested in a called group capture for the call, then are put back
+        assert_captures!(r"(a(b)?)-(?1)", "ab-a", (0, 0, 4), (1, 0, 2), (2, 1, 2));
+        assert_match!(r"((\w)\2)-(?1)", "aa-bb", "aa-bb");
+        assert_no_match!(r"((\w)\2)-(?1)", "aa-ba");
+    }
+
+    #[test]
+    fn test_counted_repetition() {
+        assert_match!(r"a{3}", "aaaaa", "aaa");
+        assert_no_match!(r"a{3}", "aa");
+        assert_match!(r"\d{2,4}", "x12345", "1234");
+        assert_match!(r"\d{2,4}", "x123-", "123");
+        assert_match!(r"ab{2,}c", "abbbbc", "abbbbc");
+        assert_no_match!(r"ab{2,}c", "abc");
+        assert_match!(r"x[a-z]{0,3}y", "xy xaby", "xy");
+        assert_match!(r"(?:ab){1,2}c", "abababc", "ababc");
+        assert_match!(r"\w{0,}", "word", "word");
+        assert_match!(r"a{0}b", "ab", "b");
+        assert_match!(r"a{2}+", "aaaa", "aa");
+        assert_match!(r"a{1,3}+a", "aaaa", "aaaa");
+        assert_no_match!(r"a{1,3}+a", "aaa");
+        // Braces that don't hold bounds match themselves
+        assert_match!(r"a{,2}", "xa{,2}", "a{,2}");
+        assert_match!(r"{x}", "{x}", "{x}");
+    }
+
+    #[test]
+    fn test_counted_repetition_captures() {
+        // Every copy captures into the same group, leaving the last copy's capture
+        assert_captures!(r"(\w){2,3}", "abcd", (0, 0, 3), (1, 2, 3));
+        assert_captures!(
+            r"(a)(b){2}(c)",
+            "abbc",
+            (0, 0, 4),
+            (1, 0, 1),
+            (2, 2, 3),
+            (3, 3, 4)
+        );
+        assert_captures!(r"(?:(a)|b){2,}", "abb", (0, 0, 3), (1, 0, 1));
+        assert_match!(r"(\w)\1{2}", "xabbbc", "bbb");
+    }
+
+    #[test]
+    fn test_counted_repetition_match_kinds() {
+        let longest = compile_kind(r"(?:a|ab){2}", MatchKind::LeftmostLongest);
+        assert_eq!(longest.find("abab"), Some(Match { start: 0, end: 4 }));
+        let first = compile_kind(r"(?:a|ab){2}", MatchKind::LeftmostFirst);
+        assert_eq!(first.find("abab"), Some(Match { start: 0, end: 3 }));
+        // Optional copies are tried before leaving under leftmost-first
+        assert_eq!(
+            compile_kind(r"a{1,3}", MatchKind::LeftmostFirst).find("aaaa"),
+            Some(Match { start: 0, end: 3 })
+        );
+    }
+
+    #[test]
+    fn test_large_counted_repetitions() {
+        let machine = compile_machine(
+            &Ast::from_tokens(tokenize(r"[a-z]{1,1000}").unwrap()).unwrap(),
+            r"[a-z]{1,1000}",
+        )
+        .unwrap();
+        // One state for each copy, each optional one leaving for the accept by itself
+        assert_eq!(machine.size(), 1_002);
+        let text = "z".repeat(1_200);
+        assert_eq!(
+            compile(r"[a-z]{1,1000}").unwrap().find(&text),
+            Some(Match {
+                start: 0,
+                end: 1_000
+            })
+        );
+        assert_eq!(
+            compile(r".{0,5000}x")
+                .unwrap()
+                .find(&text[..300].replace("zz", "zx")),
+            Some(Match { start: 0, end: 300 })
+        );
+
+        for pattern in [
+            r"a{1000000}",
+            r"a{99999999999}",
+            r"(?:a{1000}){1000}",
+            r"(a{0,70000}){4}",
+        ] {
+            assert!(
+                matches!(
+                    compile(pattern),
+                    Err(CompilerError::LimitExceeded(Limit::States, _))
+                ),
+                "{}",
+                pattern
+            );
+        }
+        assert!(matches!(
+            compile(r"a{3,2}"),
+            Err(CompilerError::LexicalError(
+                TokenizeError::InvalidRepetition(1)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_compile_limits() {
+        let limited = |limits: Limits| CompileOptions {
+            limits,
+            ..CompileOptions::default()
+        };
+        let exceeded = |pattern: &str, limits: Limits| match compile_with(pattern, &limited(limits))
+        {
+            Err(CompilerError::LimitExceeded(limit, _)) => Some(limit),
+            _ => None,
+        };
+
+        let short = Limits {
+            max_pattern_length: 8,
+            ..Limits::default()
+        };
+        assert_eq!(exceeded("abcdefghi", short), Some(Limit::PatternLength));
+        assert_eq!(exceeded("abcdefgh", short), None);
+
+        let shallow = Limits {
+            max_nesting_depth: 2,
+            ..Limits::default()
+        };
+        assert_eq!(exceeded("((a)(b))((c))", shallow), None);
+        assert_eq!(exceeded("(((a)))", shallow), Some(Limit::NestingDepth));
+        assert_eq!(
+            exceeded(&"(".repeat(10_000), Limits::default()),
+            Some(Limit::NestingDepth)
+        );
+
+        let few_repeats = Limits {
+            max_repetition: 100,
+            ..Limits::default()
+        };
+        assert_eq!(exceeded("a{100}b{2,100}", few_repeats), None);
+        assert_eq!(exceeded("a{101}", few_repeats), Some(Limit::Repetition));
+        assert_eq!(exceeded("a{1,101}", few_repeats), Some(Limit::Repetition));
+
+        let small = Limits {
+            max_states: 50,
+            ..Limits::default()
+        };
+        assert_eq!(exceeded("(a|b)*c", small), None);
+        assert_eq!(exceeded(&"(a|b)*c".repeat(10), small), Some(Limit::States));
+        assert_eq!(exceeded("(?=a{30})b", small), Some(Limit::States));
+        assert!(matches!(
+            compile_nfa(&"[a-c]x".repeat(20), &limited(small)),
+            Err(CompilerError::LimitExceeded(Limit::States, _))
+        ));
+    }
+
+    #[test]
+    fn test_dense_table_budget() {
+        let pattern = r"([a-c]x|\d)+y";
+        let options = CompileOptions {
+            limits: Limits {
+                max_dense_table_pairs: 10,
+                ..Limits::default()
+            },
+            ..CompileOptions::default()
+        };
+        let tabulated = compile_nfa(pattern, &CompileOptions::default()).unwrap();
+        let by_rules = compile_nfa(pattern, &options).unwrap();
+
+        assert!(tabulated.dense_table().is_some());
+        assert!(by_rules.dense_table().is_none());
+        for input in ["", "axbx7y", "zz cx3", "9y"] {
+            assert_eq!(by_rules.captures(input), tabulated.captures(input));
+        }
+    }
+
+    #[test]
+    fn test_search_cache_budget() {
+        let text = format!("{}ab", "a".repeat(2_000));
+        let unlimited = compile(r"(a|ab)(b*)").unwrap();
+        // Too small to backtrack over any input, so the automaton does every search
+        let starved = compile_with(
+            r"(a|ab)(b*)",
+            &CompileOptions {
+                limits: Limits {
+                    search_cache_bytes: 0,
+                    ..Limits::default()
+                },
+                ..CompileOptions::default()
+            },
+        )
+        .unwrap();
+
+        for at in [0, 1_999, 2_000] {
+            assert_eq!(starved.find_at(&text, at), unlimited.find_at(&text, at));
+            assert_eq!(
+                starved.captures_at(&text, at),
+                unlimited.captures_at(&text, at)
+            );
+        }
+    }
+
+    #[test]
+    fn test_backtrack_step_budget() {
+        use crate::interrupt::{Interrupt, Outcome};
+
+        let pattern = r"(a|a)*\1\d";
+        let text = "a".repeat(22);
+        let limited = |max_backtrack_steps| {
+            compile_with(
+                pattern,
+                &CompileOptions {
+                    limits: Limits {
+                        max_backtrack_steps,
+                        ..Limits::default()
+                    },
+                    ..CompileOptions::default()
+                },
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            compile(pattern).unwrap().try_find_at(&text, 0),
+            Err(BacktrackError::TooManySteps {
+                limit: DEFAULT_STEP_LIMIT
+            })
+        );
+        assert_eq!(
+            limited(100).try_find_at("aa1", 0),
+            Ok(Some(Match { start: 0, end: 3 }))
+        );
+        // Giving up is reported, never taken for no match
+        let text = format!("{}b aa1", "a".repeat(12));
+        assert_eq!(
+            limited(100).try_find_at(&text, 0),
+            Err(BacktrackError::TooManySteps { limit: 100 })
+        );
+        assert_eq!(limited(100).find(&text), Some(Match { start: 14, end: 17 }));
+        assert_eq!(
+            limited(100)
+                .captures(&text)
+                .map(|captures| captures.whole()),
+            Some(Match { start: 14, end: 17 })
+        );
+        assert_eq!(
+            limited(100).find_all_until(&text, 0, &Interrupt::new()),
+            Outcome::GaveUp(vec![], BacktrackError::TooManySteps { limit: 100 })
+        );
+    }
+
+    #[test]
+    fn test_interrupted_searches() {
+        use crate::interrupt::{CancellationToken, Interrupt, Outcome};
+        use std::time::Instant;
+
+        let input = "ab, ba; ".repeat(40_000);
+        for pattern in ["ab", r"\bab\b", r"(b)\1|ab"] {
+            let gex = compile(pattern).unwrap();
+            let mut all = Vec::new();
+            gex.try_find_iter_at(&input, 0, |found| {
+                all.push(found);
+                Ok::<bool, ()>(true)
+            })
+            .unwrap();
+            assert_eq!(
+                gex.find_all_until(&input, 0, &Interrupt::new()),
+                Outcome::Complete(all.clone()),
+                "{pattern}"
+            );
+            let expired = Interrupt::new().with_deadline(Instant::now());
+            assert_eq!(
+                gex.find_all_until(&input, 0, &expired),
+                Outcome::Cancelled(vec![]),
+                "{pattern}"
+            );
+
+            // Whenever the token is cancelled, the matches found up to then are all correct
+            let token = CancellationToken::new();
+            let interrupt = Interrupt::new().with_token(token.clone());
+            let canceller = std::thread::spawn(move || token.cancel());
+            let outcome = gex.find_all_until(&input, 0, &interrupt);
+            canceller.join().unwrap();
+            let cancelled = outcome.is_cancelled();
+            let found = outcome.into_inner();
+            assert!(all.starts_with(&found), "{pattern}");
+            assert!(cancelled || found == all, "{pattern}");
+        }
+    }
+
+    #[test]
+    fn test_deadlines_stop_runaway_searches() {
+        use crate::interrupt::{Interrupt, Outcome};
+        use std::time::{Duration, Instant};
+
+        // Without a step limit only the deadline stops the backtracker
+        let gex = compile_with(
+            r"(a|a)*\1\d",
+            &CompileOptions {
+                limits: Limits {
+                    max_backtrack_steps: usize::MAX,
+                    ..Limits::default()
+                },
+                ..CompileOptions::default()
+            },
+        )
+        .unwrap();
+        let input = "a".repeat(24);
+        let started = Instant::now();
+        let interrupt = Interrupt::new().with_timeout(Duration::from_millis(200));
+        assert_eq!(
+            gex.find_all_until(&input, 0, &interrupt),
+            Outcome::Cancelled(vec![])
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // Lookarounds keep the machine from searching a slice at a time, so it checks as it goes
+        let gex = compile(r"(?<=b)a").unwrap();
+        let machine = gex.machine().unwrap();
+        let input = "a".repeat(100_000);
+        let expired = Interrupt::new().with_deadline(Instant::now());
+        assert_eq!(
+            machine.find_at_until_cached(&mut machine.create_cache(), &input, 0, Some(&expired)),
+            Outcome::Cancelled(None)
+        );
+        assert_eq!(
+            machine.find_at_until_cached(&mut machine.create_cache(), &input, 0, None),
+            Outcome::Complete(None)
+        );
+    }
+
+    #[test]
+    fn test_shared_across_threads() {
+        use crate::compile::GexSet;
+
+        let patterns = [
+            "needle",
+            "cat|dog|bird|fish",
+            r"(\w+)@(\w+)",
+            r"(a|b)*c",
+            r"(\w)\1",
+            r"\bx\w*",
+        ];
+        let inputs: Vec<String> = (0..40)
+            .map(|idx| format!("x{} me@host cc abac dogs needle {}", idx, "ab".repeat(idx)))
+            .collect();
+        let compiled: Vec<Gex> = patterns
+            .iter()
+            .map(|pattern| compile(pattern).unwrap())
+            .collect();
+        let set = GexSet::new(patterns).unwrap();
+
+        let expected: Vec<_> = inputs
+            .iter()
+            .map(|input| {
+                let found: Vec<_> = compiled
+                    .iter()
+                    .map(|gex| (gex.find(input), gex.captures(input)))
+                    .collect();
+                (found, set.matches(input))
+            })
+            .collect();
+
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let (compiled, set, inputs, expected) = (&compiled, &set, &inputs, &expected);
+                scope.spawn(move || {
+                    for round in 0..5 {
+                        // Threads walk the inputs from different places, so searches overlap
+                        for idx in 0..inputs.len() {
+                            let idx = (idx + thread * 5 + round) % inputs.len();
+                            let (found, matches) = &expected[idx];
+                            for (gex, expected) in compiled.iter().zip(found) {
+                                let input = &inputs[idx];
+                                assert_eq!(&(gex.find(input), gex.captures(input)), expected);
+                            }
+                            assert_eq!(&set.matches(&inputs[idx]), matches);
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_invalid_subroutines() {
+        assert!(matches!(
+            compile(r"(a)(?2)"),
+            Err(CompilerError::InvalidSubroutine(_))
+        ));
+        assert!(matches!(
+            compile(r"a(?=(?R))"),
+            Err(CompilerError::Unsupported(_))
+        ));
+    }
+
+    /// The input split into chunks of the given size, cutting characters where they fall.
+    fn chunked(input: &str, size: usize) -> Vec<String> {
+        input
+            .as_bytes()
+            .chunks(size)
+            .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_haystack_searches() {
+        let input = "say foo, then bar at x1 y2 on the foobar, xy then zz";
+        let patterns = [
+            ("foo|bar", "aho-corasick"),
+            ("[xy][0-9]", "shift-and"),
+            (r"\b(\w)(\w*)\b", "nfa"),
+            (r"(?<=x)(y)", "nfa"),
+            (r"(\w)\1", "backtrack"),
+            ("z*", "nfa"),
+        ];
+        for (pattern, engine) in patterns {
+            let gex = compile(pattern).unwrap();
+            assert_eq!(gex.engine_name(), engine, "{pattern}");
+            let mut expected = Vec::new();
+            gex.try_find_iter_at(input, 0, |found| {
+                expected.push(found);
+                Ok::<bool, ()>(true)
+            })
+            .unwrap();
+
+            for size in [1, 2, 3, 5, 64] {
+                let rope = chunked(input, size);
+                let mut found = Vec::new();
+                gex.try_find_iter_in_at(&rope, 0, |next| {
+                    found.push(next);
+                    Ok::<bool, ()>(true)
+                })
+                .unwrap();
+                assert_eq!(found, expected, "{pattern} in chunks of {size}");
+
+                for at in [0, 6, 20, 35, input.len()] {
+                    assert_eq!(
+                        gex.find_in_at(&rope, at),
+                        gex.find_at(input, at),
+                        "{pattern} from {at} in chunks of {size}"
+                    );
+                    assert_eq!(
+                        gex.captures_in_at(&rope, at),
+                        gex.captures_at(input, at),
+                        "{pattern} from {at} in chunks of {size}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_haystack_multibyte_chunks() {
+        let input = "héllo wörld, ça va";
+        let rope = ["hé", "llo w", "ö", "rld, ç", "a va"];
+        let gex = compile(r"\b\w+\b").unwrap();
+        let mut found = Vec::new();
+        gex.try_find_iter_in_at(&rope[..], 0, |next| {
+            found.push(next.substr(input).to_string());
+            Ok::<bool, ()>(true)
+        })
+        .unwrap();
+        assert_eq!(found, vec!["héllo", "wörld", "ça", "va"]);
+        assert_eq!(
+            gex.captures_in_at(&rope[..], 7)
+                .unwrap()
+                .whole()
+                .substr(input),
+            "wörld"
+        );
+    }
 }
@@ -0,0 +1,339 @@
+use crate::gex::prefilter::Prefilter;
+use crate::railroad::{Ast, AstNode};
+use crate::tokenize::{GroupType, LiteralType, QuantifierType, Token};
+
+/// Upper bound on how many strings a literal set may hold before it is abandoned.
+const MAX_LITERALS: usize = 32;
+
+/// Shape of a sub-pattern while checking for an alternation of plain literals.
+enum LiteralChain {
+    Literal(String),
+    Alternatives(Vec<String>),
+}
+
+impl LiteralChain {
+    fn into_alternatives(self) -> Vec<String> {
+        match self {
+            LiteralChain::Literal(literal) => vec![literal],
+            LiteralChain::Alternatives(alternatives) => alternatives,
+        }
+    }
+}
+
+/// The alternatives of a pattern made only of literal strings joined by `|`, such as
+/// `foo|bar|baz`, in pattern order.
+///
+/// Unlike `Literals::exact` this has no size bound, since such patterns are handed to an engine
+/// built for large literal sets instead of being used as a prefilter.
+pub fn alternation_of_literals(ast: &Ast, input: &str) -> Option<Vec<String>> {
+    let mut stack: Vec<LiteralChain> = Vec::with_capacity(2);
+
+    for ast_node in ast.0.iter() {
+        let chain = match ast_node {
+            AstNode::Literal(LiteralType::Character, token) if token.start() < token.end() => {
+                LiteralChain::Literal(input[token.input_range()].to_string())
+            }
+            AstNode::Literal(LiteralType::EscapedCharacter, token) => {
+                LiteralChain::Literal(input[token.input_range()].chars().skip(1).collect())
+            }
+            AstNode::Cons(_, _) => match (stack.pop()?, stack.pop()?) {
+                (LiteralChain::Literal(right), LiteralChain::Literal(mut left)) => {
+                    left.push_str(&right);
+                    LiteralChain::Literal(left)
+                }
+                _ => return None,
+            },
+            AstNode::Alternation(_, _) => {
+                let right = stack.pop()?;
+                let mut alternatives = stack.pop()?.into_alternatives();
+                alternatives.extend(right.into_alternatives());
+                LiteralChain::Alternatives(alternatives)
+            }
+            _ => return None,
+        };
+        stack.push(chain);
+    }
+
+    match stack.pop()? {
+        LiteralChain::Alternatives(alternatives) if stack.is_empty() => Some(alternatives),
+        _ => None,
+    }
+}
+
+/// Literal facts known about a sub-pattern.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Literals {
+    /// Every string the sub-pattern can match, when that language is small and finite.
+    pub exact: Option<Vec<String>>,
+    /// Literals, one of which starts every match.
+    pub prefixes: Option<Vec<String>>,
+    /// A literal that appears in every match.
+    pub inner: Option<String>,
+    /// A literal that ends every match.
+    pub suffix: Option<String>,
+}
+
+fn cross(left: &[String], right: &[String]) -> Option<Vec<String>> {
+    if left.len() * right.len() > MAX_LITERALS {
+        return None;
+    }
+    Some(
+        left.iter()
+            .flat_map(|prefix| {
+                right
+                    .iter()
+                    .map(move |suffix| format!("{}{}", prefix, suffix))
+            })
+            .collect(),
+    )
+}
+
+fn union(left: &[String], right: &[String]) -> Option<Vec<String>> {
+    if left.len() + right.len() > MAX_LITERALS {
+        return None;
+    }
+    Some(left.iter().chain(right.iter()).cloned().collect())
+}
+
+fn longest(candidates: impl Iterator<Item = Option<String>>) -> Option<String> {
+    candidates
+        .flatten()
+        .filter(|literal| !literal.is_empty())
+        .max_by_key(String::len)
+}
+
+fn non_empty(literals: &[String]) -> Option<Vec<String>> {
+    if literals.is_empty() || literals.iter().any(String::is_empty) {
+        None
+    } else {
+        Some(literals.to_vec())
+    }
+}
+
+impl Literals {
+    fn exactly(literal: String) -> Self {
+        Literals {
+            prefixes: non_empty(std::slice::from_ref(&literal)),
+            inner: Some(literal.clone()).filter(|literal| !literal.is_empty()),
+            suffix: Some(literal.clone()),
+            exact: Some(vec![literal]),
+        }
+    }
+
+    fn unknown() -> Self {
+        Literals::default()
+    }
+
+    fn for_literal(ltype: &LiteralType, token: &Token, input: &str) -> Self {
+        let text = &input[token.input_range()];
+        match ltype {
+            LiteralType::Character => Literals::exactly(text.to_string()),
+            LiteralType::EscapedCharacter => text
+                .chars()
+                .nth(1)
+                .map(|escaped| Literals::exactly(escaped.to_string()))
+                .unwrap_or_else(Literals::unknown),
+            // Assertions match without consuming anything
+            LiteralType::WordBoundary(_) => Literals::exactly(String::new()),
+            _ => Literals::unknown(),
+        }
+    }
+
+    fn cons(left: Literals, right: Literals) -> Self {
+        let exact = match (&left.exact, &right.exact) {
+            (Some(left_exact), Some(right_exact)) => cross(left_exact, right_exact),
+            _ => None,
+        };
+        let prefixes = match (&left.exact, &right.prefixes) {
+            (Some(left_exact), Some(right_prefixes)) => cross(left_exact, right_prefixes),
+            (Some(left_exact), None) => Some(left_exact.clone()),
+            (None, _) => left.prefixes.clone(),
+        }
+        .and_then(|prefixes| non_empty(&prefixes));
+        let suffix = match &right.exact {
+            Some(right_exact) if right_exact.len() == 1 => Some(format!(
+                "{}{}",
+                left.suffix.unwrap_or_default(),
+                right_exact[0]
+            )),
+            _ => right.suffix,
+        };
+
+        Literals {
+            exact,
+            prefixes: prefixes.or(left.prefixes),
+            inner: longest([suffix.clone(), left.inner, right.inner].into_iter()),
+            suffix,
+        }
+    }
+
+    fn alternation(left: Literals, right: Literals) -> Self {
+        let exact = match (&left.exact, &right.exact) {
+            (Some(left_exact), Some(right_exact)) => union(left_exact, right_exact),
+            _ => None,
+        };
+        let prefixes = match (&left.prefixes, &right.prefixes) {
+            (Some(left_prefixes), Some(right_prefixes)) => union(left_prefixes, right_prefixes),
+            _ => None,
+        };
+        let inner = match (left.inner, right.inner) {
+            (Some(left_inner), Some(right_inner)) if left_inner == right_inner => Some(left_inner),
+            _ => None,
+        };
+
+        Literals {
+            exact,
+            prefixes,
+            inner,
+            suffix: None,
+        }
+    }
+
+    fn quantified(operand: Literals, qtype: &QuantifierType) -> Self {
+        match qtype {
+            QuantifierType::OneOrMore => Literals {
+                exact: None,
+                ..operand
+            },
+            QuantifierType::ZeroOrOne => Literals {
+                exact: operand
+                    .exact
+                    .and_then(|exact| union(&exact, &[String::new()])),
+                prefixes: None,
+                inner: None,
+                suffix: None,
+            },
+            QuantifierType::Counted(0, _) | QuantifierType::ZeroOrMore => Literals::unknown(),
+            // At least one copy, so the operand's prefixes and suffixes still hold
+            QuantifierType::Counted(_, _) => Literals {
+                exact: None,
+                ..operand
+            },
+        }
+    }
+
+    /// Analyse the whole AST, evaluating its postfix nodes the same way the compiler does.
+    pub fn from_ast(ast: &Ast, input: &str) -> Self {
+        let mut stack: Vec<Literals> = Vec::with_capacity(2);
+
+        for ast_node in ast.0.iter() {
+            let literals = match ast_node {
+                AstNode::Literal(ltype, token) => Literals::for_literal(ltype, token, input),
+                AstNode::Quantifier(qtype, _) => {
+                    Literals::quantified(stack.pop().unwrap_or_default(), qtype)
+                }
+                AstNode::Cons(_, _) => {
+                    let right = stack.pop().unwrap_or_default();
+                    let left = stack.pop().unwrap_or_default();
+                    Literals::cons(left, right)
+                }
+                AstNode::Alternation(_, _) => {
+                    let right = stack.pop().unwrap_or_default();
+                    let left = stack.pop().unwrap_or_default();
+                    Literals::alternation(left, right)
+                }
+                AstNode::Group(GroupType::Lookahead(_) | GroupType::Lookbehind(_), _, _) => {
+                    // Lookarounds match without consuming anything
+                    stack.pop();
+                    Literals::exactly(String::new())
+                }
+                AstNode::Group(..) => stack.pop().unwrap_or_default(),
+            };
+            stack.push(literals);
+        }
+
+        stack.pop().unwrap_or_default()
+    }
+
+    /// Pick the strongest prefilter the literals allow: required prefixes first, then a
+    /// required inner literal.
+    pub fn prefilter(&self) -> Option<Prefilter> {
+        self.prefixes
+            .clone()
+            .and_then(Prefilter::from_prefixes)
+            .or_else(|| self.inner.clone().map(Prefilter::Inner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenize::tokenize;
+
+    fn literals_for(pattern: &str) -> Literals {
+        let ast = Ast::from_tokens(tokenize(pattern).unwrap()).unwrap();
+        Literals::from_ast(&ast, pattern)
+    }
+
+    fn strings(values: &[&str]) -> Option<Vec<String>> {
+        Some(values.iter().map(|value| value.to_string()).collect())
+    }
+
+    #[test]
+    fn test_literal_prefix() {
+        let literals = literals_for(r"ERROR:\s+\w+");
+
+        assert_eq!(literals.prefixes, strings(&["ERROR:"]));
+        assert_eq!(literals.exact, None);
+        assert_eq!(
+            literals.prefilter(),
+            Some(Prefilter::Prefix("ERROR:".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_escaped_prefix() {
+        let literals = literals_for(r"TODO\(\w*\)");
+
+        assert_eq!(literals.prefixes, strings(&["TODO("]));
+    }
+
+    #[test]
+    fn test_alternation_prefixes() {
+        let literals = literals_for(r"(fn|let) \w+");
+
+        assert_eq!(literals.prefixes, strings(&["fn ", "let "]));
+    }
+
+    #[test]
+    fn test_inner_literal() {
+        let literals = literals_for(r"\d+-needle-\d+");
+
+        assert_eq!(literals.prefixes, None);
+        assert_eq!(literals.inner, Some("-needle-".to_string()));
+        assert_eq!(
+            literals.prefilter(),
+            Some(Prefilter::Inner("-needle-".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_optional_prefix_is_not_required() {
+        assert_eq!(literals_for(r"a?bc").prefixes, strings(&["abc", "bc"]));
+        assert_eq!(literals_for(r"a*bc").prefixes, None);
+        assert_eq!(literals_for(r"a*bc").inner, Some("bc".to_string()));
+        assert_eq!(literals_for(r"x*").prefilter(), None);
+    }
+
+    #[test]
+    fn test_alternation_of_literals() {
+        let alternatives = |pattern: &str| {
+            let ast = Ast::from_tokens(tokenize(pattern).unwrap()).unwrap();
+            alternation_of_literals(&ast, pattern)
+        };
+
+        assert_eq!(
+            alternatives(r"foo|ba\.r|baz"),
+            strings(&["foo", "ba.r", "baz"])
+        );
+        assert_eq!(alternatives(r"foo"), None);
+        assert_eq!(alternatives(r"foo|ba+r"), None);
+        assert_eq!(alternatives(r"(foo)|bar"), None);
+        assert_eq!(alternatives(r"x(foo|bar)"), None);
+    }
+
+    #[test]
+    fn test_exact_alternation() {
+        assert_eq!(literals_for(r"foo|bar").exact, strings(&["foo", "bar"]));
+    }
+}
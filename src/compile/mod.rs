@@ -1,2 +1,10 @@
 mod compiler;
+pub mod literals;
+mod program;
+mod set;
+mod stream;
 pub use compiler::*;
+pub(crate) use program::Engine;
+pub use program::Gex;
+pub use set::GexSet;
+pub use stream::StreamSearcher;
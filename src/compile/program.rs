@@ -0,0 +1,431 @@
+use super::stream::{scans_in_chunks, ChunkScan};
+use crate::engines::aho_corasick::AhoCorasick;
+use crate::engines::backtrack::{
+    BacktrackCache, BacktrackError, Backtracker, DEFAULT_STEP_LIMIT, DEFAULT_VISITED_CAPACITY,
+};
+use crate::engines::shift_and::ShiftAnd;
+use crate::gex::cache::Cache;
+use crate::gex::GexMachine;
+use crate::haystack::{try_windowed_matches, Haystack};
+use crate::interrupt::{find_all_in_slices, find_all_with, Interrupt, Outcome};
+use crate::matcher::{Captures, Match, MatchKind, Matcher};
+use crate::pool::{Pool, PoolGuard};
+
+/// The engine a compiled pattern is searched with.
+#[derive(Debug, Clone)]
+pub(crate) enum Engine {
+    /// The NFA along with its reversal, which finds match starts for searches without captures.
+    ///
+    /// Inputs short enough are searched by backtracking over the NFA instead.
+    Nfa {
+        machine: GexMachine,
+        reverse: Box<GexMachine>,
+    },
+    /// An NFA only a backtracker can search, because of backreferences or atomic groups.
+    Backtrack(GexMachine),
+    AhoCorasick(AhoCorasick),
+    ShiftAnd(ShiftAnd),
+}
+
+// Compiled patterns are meant to be shared across threads
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Gex>();
+    assert_send_sync::<GexMachine>();
+    assert_send_sync::<super::GexSet>();
+};
+
+/// Scratch space for searching the NFA of a pattern, with either engine.
+struct Scratch {
+    nfa: Cache,
+    backtrack: BacktrackCache,
+}
+
+/// A compiled pattern, ready to search.
+///
+/// The compiler picks the engine best suited to the pattern; searching goes through `Matcher`
+/// regardless of which engine was chosen.
+///
+/// A `Gex` is `Send + Sync`, so one compiled pattern can be shared by many threads. Each thread
+/// gets scratch space of its own from a pool, kept for its later searches, so once warmed up
+/// searches don't allocate, and concurrent ones only wait on each other to take scratch space
+/// from the pool or return it. Searches that return captures still allocate the captures they
+/// return, and lookarounds and calls allocate as they are evaluated.
+#[derive(Debug, Clone)]
+pub struct Gex {
+    engine: Engine,
+    /// Size, in bits, of the backtracker's visited set.
+    visited_capacity: usize,
+    /// Most states the backtracker explores in a search it can't memoize.
+    step_limit: usize,
+    scratch: Pool<Scratch>,
+}
+
+impl Gex {
+    fn new(engine: Engine) -> Self {
+        Gex {
+            engine,
+            visited_capacity: DEFAULT_VISITED_CAPACITY,
+            step_limit: DEFAULT_STEP_LIMIT,
+            scratch: Pool::new(),
+        }
+    }
+
+    /// The current thread's scratch space for searching the machine.
+    fn scratch(&self, machine: &GexMachine) -> PoolGuard<'_, Scratch> {
+        self.scratch.get(|| Scratch {
+            nfa: machine.create_cache(),
+            backtrack: BacktrackCache::new(),
+        })
+    }
+
+    fn backtracker<'m>(&self, machine: &'m GexMachine) -> Backtracker<'m> {
+        Backtracker::new(machine)
+            .with_visited_capacity(self.visited_capacity)
+            .with_step_limit(self.step_limit)
+    }
+
+    /// Bound the memory the backtracker may use to remember the states it tried at each
+    /// position; inputs too long for it are searched by the automaton instead.
+    pub fn with_search_cache_bytes(mut self, bytes: usize) -> Self {
+        self.visited_capacity = bytes.saturating_mul(8);
+        self
+    }
+
+    /// Bound the states the backtracker explores from each start position in a search of a
+    /// pattern only it can search. Searches that can report an error, like `try_find_at` and
+    /// `find_all_until`, give up past the bound; `find_at` and `captures_at` search on, since
+    /// giving up would report no match where there may be one.
+    pub fn with_backtrack_step_limit(mut self, step_limit: usize) -> Self {
+        self.step_limit = step_limit;
+        self
+    }
+
+    /// `find_at`, except that a backtracking search gives up with an error past its step limit.
+    pub fn try_find_at(&self, input: &str, at: usize) -> Result<Option<Match>, BacktrackError> {
+        match &self.engine {
+            Engine::Backtrack(machine) => {
+                let Scratch { backtrack, .. } = &mut *self.scratch(machine);
+                self.backtracker(machine)
+                    .try_find_at_cached(backtrack, input, at)
+            }
+            _ => Ok(self.find_at(input, at)),
+        }
+    }
+
+    /// `captures_at`, except that a backtracking search gives up with an error past its step
+    /// limit.
+    pub fn try_captures_at(
+        &self,
+        input: &str,
+        at: usize,
+    ) -> Result<Option<Captures>, BacktrackError> {
+        match &self.engine {
+            Engine::Backtrack(machine) => {
+                let Scratch { backtrack, .. } = &mut *self.scratch(machine);
+                self.backtracker(machine)
+                    .try_captures_at_cached(backtrack, input, at)
+            }
+            _ => Ok(self.captures_at(input, at)),
+        }
+    }
+
+    pub(crate) fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// The NFA for the pattern, when the pattern is searched with one.
+    pub fn machine(&self) -> Option<&GexMachine> {
+        match &self.engine {
+            Engine::Nfa { machine, .. } | Engine::Backtrack(machine) => Some(machine),
+            _ => None,
+        }
+    }
+
+    pub fn match_kind(&self) -> MatchKind {
+        match &self.engine {
+            Engine::Nfa { machine, .. } | Engine::Backtrack(machine) => machine.match_kind(),
+            Engine::AhoCorasick(automaton) => automaton.kind(),
+            Engine::ShiftAnd(automaton) => automaton.kind(),
+        }
+    }
+
+    /// `find_at`, giving up, without a match, once the interrupt fires, or once a backtracking
+    /// search goes past its step limit.
+    ///
+    /// Searches that can't run away, such as those of the automata, finish regardless.
+    pub fn find_at_until(
+        &self,
+        input: &str,
+        at: usize,
+        interrupt: &Interrupt,
+    ) -> Outcome<Option<Match>> {
+        let found = match &self.engine {
+            // Inputs too long to backtrack over are searched forward only, by the machine, as
+            // the search for the start with its reversal doesn't check the interrupt
+            Engine::Nfa { machine, .. } | Engine::Backtrack(machine) => {
+                let Scratch { nfa, backtrack } = &mut *self.scratch(machine);
+                match self
+                    .backtracker(machine)
+                    .with_interrupt(interrupt)
+                    .try_find_at_cached(backtrack, input, at)
+                {
+                    Ok(found) => found,
+                    Err(BacktrackError::Interrupted) => return Outcome::Cancelled(None),
+                    Err(error @ BacktrackError::TooManySteps { .. }) => {
+                        return Outcome::GaveUp(None, error)
+                    }
+                    Err(_) => return machine.find_at_until_cached(nfa, input, at, Some(interrupt)),
+                }
+            }
+            _ => self.find_at(input, at),
+        };
+        Outcome::Complete(found)
+    }
+
+    /// Whether the engine can search text a chunk at a time rather than all at once.
+    fn scans_in_chunks(&self) -> bool {
+        match &self.engine {
+            Engine::Nfa { machine, .. } => scans_in_chunks(machine),
+            Engine::Backtrack(_) => false,
+            Engine::AhoCorasick(_) | Engine::ShiftAnd(_) => true,
+        }
+    }
+
+    /// Short name of the engine chosen for the pattern.
+    pub fn engine_name(&self) -> &'static str {
+        match &self.engine {
+            Engine::Nfa { .. } => "nfa",
+            Engine::Backtrack(_) => "backtrack",
+            Engine::AhoCorasick(_) => "aho-corasick",
+            Engine::ShiftAnd(_) => "shift-and",
+        }
+    }
+}
+
+/// Bytes of text on either side of a match copied along with it to find its captures, enough
+/// for the characters word boundaries look at.
+const CAPTURE_CONTEXT: usize = 4;
+
+/// Call `matched` with the matches of the machine in the haystack from `at`, scanning it a
+/// chunk at a time.
+fn try_scan_chunks<H, F, E>(
+    machine: &GexMachine,
+    haystack: &H,
+    at: usize,
+    mut matched: F,
+) -> Result<(), E>
+where
+    H: Haystack + ?Sized,
+    F: FnMut(Match) -> Result<bool, E>,
+{
+    let mut scan = ChunkScan::new(machine, at);
+    let mut found = Vec::new();
+    let mut pushed = false;
+    for chunk in haystack.chunks() {
+        if !pushed && scan.offset() + chunk.len() <= at {
+            scan.skip(chunk);
+            continue;
+        }
+        pushed = true;
+        scan.push(machine, chunk.as_bytes(), |next| found.push(next));
+        for next in found.drain(..) {
+            if !matched(next)? {
+                return Ok(());
+            }
+        }
+    }
+    scan.finish(machine, |next| found.push(next));
+    for next in found {
+        if !matched(next)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl From<GexMachine> for Gex {
+    fn from(machine: GexMachine) -> Self {
+        let engine = if machine.needs_backtracking() {
+            Engine::Backtrack(machine)
+        } else {
+            Engine::Nfa {
+                reverse: Box::new(machine.reverse()),
+                machine,
+            }
+        };
+        Gex::new(engine)
+    }
+}
+
+impl From<AhoCorasick> for Gex {
+    fn from(automaton: AhoCorasick) -> Self {
+        Gex::new(Engine::AhoCorasick(automaton))
+    }
+}
+
+impl From<ShiftAnd> for Gex {
+    fn from(automaton: ShiftAnd) -> Self {
+        Gex::new(Engine::ShiftAnd(automaton))
+    }
+}
+
+impl Matcher for Gex {
+    fn find_at(&self, input: &str, at: usize) -> Option<Match> {
+        match &self.engine {
+            Engine::Nfa { machine, reverse } => {
+                let Scratch { nfa, backtrack } = &mut *self.scratch(machine);
+                self.backtracker(machine)
+                    .try_find_at_cached(backtrack, input, at)
+                    .unwrap_or_else(|_| machine.find_with_reverse_cached(reverse, nfa, input, at))
+            }
+            Engine::Backtrack(machine) => {
+                let Scratch { backtrack, .. } = &mut *self.scratch(machine);
+                self.backtracker(machine)
+                    .with_step_limit(usize::MAX)
+                    .try_find_at_cached(backtrack, input, at)
+                    .unwrap_or(None)
+            }
+            Engine::AhoCorasick(automaton) => automaton.find_at(input, at),
+            Engine::ShiftAnd(automaton) => automaton.find_at(input, at),
+        }
+    }
+
+    fn captures_at(&self, input: &str, at: usize) -> Option<Captures> {
+        match &self.engine {
+            Engine::Nfa { machine, .. } => {
+                let Scratch { nfa, backtrack } = &mut *self.scratch(machine);
+                self.backtracker(machine)
+                    .try_captures_at_cached(backtrack, input, at)
+                    .unwrap_or_else(|_| machine.captures_at_cached(nfa, input, at))
+            }
+            Engine::Backtrack(machine) => {
+                let Scratch { backtrack, .. } = &mut *self.scratch(machine);
+                self.backtracker(machine)
+                    .with_step_limit(usize::MAX)
+                    .try_captures_at_cached(backtrack, input, at)
+                    .unwrap_or(None)
+            }
+            Engine::AhoCorasick(automaton) => automaton.captures_at(input, at),
+            Engine::ShiftAnd(automaton) => automaton.captures_at(input, at),
+        }
+    }
+
+    fn try_find_iter_in_at<H, F, E>(&self, haystack: &H, at: usize, matched: F) -> Result<(), E>
+    where
+        H: Haystack + ?Sized,
+        F: FnMut(Match) -> Result<bool, E>,
+    {
+        match &self.engine {
+            Engine::Nfa { machine, .. } if scans_in_chunks(machine) => {
+                try_scan_chunks(machine, haystack, at, matched)
+            }
+            Engine::AhoCorasick(automaton) => try_windowed_matches(
+                haystack,
+                at,
+                automaton.max_match_bytes(),
+                |input, at| automaton.find_at(input, at),
+                matched,
+            ),
+            Engine::ShiftAnd(automaton) => try_windowed_matches(
+                haystack,
+                at,
+                automaton.max_match_bytes(),
+                |input, at| automaton.find_at(input, at),
+                matched,
+            ),
+            _ => self.try_find_iter_at(&haystack.to_text(), at, matched),
+        }
+    }
+
+    /// Searches a slice of the input at a time when the engine can, checking the interrupt
+    /// between slices; otherwise the interrupt is checked during each search too.
+    fn find_all_until(&self, input: &str, at: usize, interrupt: &Interrupt) -> Outcome<Vec<Match>> {
+        match self.scans_in_chunks() {
+            true => find_all_in_slices(self, input, at, interrupt),
+            false => find_all_with(input, at, interrupt, |at| {
+                self.find_at_until(input, at, interrupt)
+            }),
+        }
+    }
+
+    /// Finds the match a chunk at a time when the engine can, then its captures in a copy of
+    /// the match and the text just around it.
+    fn captures_in_at<H: Haystack + ?Sized>(&self, haystack: &H, at: usize) -> Option<Captures> {
+        if !self.scans_in_chunks() {
+            return self.captures_at(&haystack.to_text(), at);
+        }
+
+        let found = self.find_in_at(haystack, at)?;
+        let (context_start, context) = haystack
+            .slice(found.start.saturating_sub(CAPTURE_CONTEXT)..found.end + CAPTURE_CONTEXT);
+        let captures = self
+            .captures_at(&context, found.start - context_start)
+            .map(|captures| captures.shift(context_start));
+        match captures {
+            Some(captures) if captures.get(0) == Some(found) => Some(captures),
+            // Text past the context changed the match after all
+            _ => self.captures_at(&haystack.to_text(), at),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::compile;
+    use crate::matcher::Matcher;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// The system allocator, counting the allocations each thread makes.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCATIONS.with(|count| count.set(count.get() + 1));
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            ALLOCATIONS.with(|count| count.set(count.get() + 1));
+            unsafe { System.realloc(ptr, layout, new_size) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    #[test]
+    fn test_warm_searches_dont_allocate() {
+        let long = format!("{}me@example", "x ".repeat(100_000));
+        let cases = [
+            (r"\w+@\w+", "nfa", "mail me@example now"),
+            (r"\w+@\w+", "nfa", long.as_str()),
+            (r"(a|ab)(c|bcd)(d*)", "nfa", "xabcd"),
+            (r"(ab)\1", "backtrack", "xxababx"),
+            (r"(?>a+)b", "backtrack", "aaaab"),
+            (r"cat|dog|bird", "aho-corasick", "a bird"),
+            (r"\d\d-[a-z]?x", "shift-and", "on 12-x"),
+        ];
+
+        for (pattern, engine, input) in cases {
+            let gex = compile(pattern).unwrap();
+            assert_eq!(gex.engine_name(), engine);
+            let expected = gex.find(input);
+            assert!(expected.is_some(), "{}", pattern);
+
+            let before = ALLOCATIONS.with(Cell::get);
+            let found = gex.find(input);
+            assert_eq!(ALLOCATIONS.with(Cell::get), before, "{}", pattern);
+            assert_eq!(found, expected);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use crate::compile::{compile_with_nfa, CompileOptions, CompilerError, Gex};
+use crate::gex::cache::Cache;
+use crate::gex::GexMachine;
+use crate::matcher::{Match, Matcher};
+use crate::pool::Pool;
+use std::collections::{BTreeMap, BTreeSet};
+
+type Result<T> = std::result::Result<T, CompilerError>;
+
+/// Many patterns compiled together, to find out which of them match an input.
+///
+/// The patterns share one automaton, each with its own final state, so a single scan over the
+/// input tells every pattern that matches from every one that doesn't. Patterns only a
+/// backtracker can search, because of backreferences, atomic groups or calls, are left out of
+/// the automaton and searched one at a time instead.
+///
+/// Like `Gex`, a set can be shared by many threads, each searching with scratch space of its own.
+#[derive(Debug, Clone)]
+pub struct GexSet {
+    /// Each pattern compiled on its own, for finding where it matches.
+    patterns: Vec<Gex>,
+    union: GexMachine,
+    /// Final state in the union of each pattern in it, in state order.
+    finals: Vec<usize>,
+    /// Index of each pattern in the union, in the same order as `finals`.
+    in_union: Vec<usize>,
+    /// Patterns searched one at a time.
+    backtracked: Vec<usize>,
+    union_caches: Pool<Cache>,
+}
+
+impl GexSet {
+    pub fn new<I, S>(patterns: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        GexSet::new_with(patterns, &CompileOptions::default())
+    }
+
+    pub fn new_with<I, S>(patterns: I, options: &CompileOptions) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut compiled = Vec::new();
+        let mut machines = Vec::new();
+        let mut in_union = Vec::new();
+        let mut backtracked = Vec::new();
+
+        for (idx, pattern) in patterns.into_iter().enumerate() {
+            let pattern = pattern.as_ref();
+            let in_pattern = |error| CompilerError::InPattern(idx, Box::new(error));
+            let (gex, machine) = compile_with_nfa(pattern, options).map_err(in_pattern)?;
+            compiled.push(gex);
+            if machine.needs_backtracking() {
+                backtracked.push(idx);
+            } else {
+                machines.push(machine);
+                in_union.push(idx);
+            }
+        }
+
+        let (union, finals) = GexMachine::union(machines);
+        Ok(GexSet {
+            patterns: compiled,
+            union: union.with_dense_table(),
+            finals,
+            in_union,
+            backtracked,
+            union_caches: Pool::new(),
+        })
+    }
+
+    /// Number of patterns in the set.
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// The pattern at the index, compiled on its own.
+    pub fn pattern(&self, idx: usize) -> Option<&Gex> {
+        self.patterns.get(idx)
+    }
+
+    /// Indices of the patterns that match somewhere in the input.
+    pub fn matches(&self, input: &str) -> BTreeSet<usize> {
+        self.matches_at(input, 0)
+    }
+
+    /// Indices of the patterns with a match starting at or after `at`.
+    pub fn matches_at(&self, input: &str, at: usize) -> BTreeSet<usize> {
+        let mut matched = vec![false; self.finals.len()];
+        if !self.finals.is_empty() {
+            let mut cache = self.union_caches.get(|| self.union.create_cache());
+            self.union
+                .accepting_finals(&mut cache, input, at, &self.finals, &mut matched);
+        }
+
+        let in_union = self
+            .in_union
+            .iter()
+            .zip(matched)
+            .filter(|(_, matched)| *matched)
+            .map(|(&idx, _)| idx);
+        let backtracked = self
+            .backtracked
+            .iter()
+            .copied()
+            .filter(|&idx| self.patterns[idx].find_at(input, at).is_some());
+        in_union.chain(backtracked).collect()
+    }
+
+    /// The leftmost match of every pattern that matches somewhere in the input, by index.
+    ///
+    /// Only the patterns the scan finds to match are searched for their matches.
+    pub fn leftmost_matches(&self, input: &str) -> BTreeMap<usize, Match> {
+        self.leftmost_matches_at(input, 0)
+    }
+
+    pub fn leftmost_matches_at(&self, input: &str, at: usize) -> BTreeMap<usize, Match> {
+        self.matches_at(input, at)
+            .into_iter()
+            .filter_map(|idx| {
+                self.patterns[idx]
+                    .find_at(input, at)
+                    .map(|found| (idx, found))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::compile_with;
+    use crate::matcher::MatchKind;
+    use crate::tokenize::TokenizeError;
+
+    #[test]
+    fn test_matching_patterns() {
+        let set = GexSet::new([
+            r"\d+",
+            r"foo|bar",
+            r"^never$x",
+            r"[a-z]+@[a-z]+",
+            r"\bbar\b",
+        ])
+        .unwrap();
+        assert_eq!(set.len(), 5);
+
+        assert_eq!(set.matches("mail foo@bar 42"), BTreeSet::from([0, 1, 3, 4]));
+        assert_eq!(set.matches("foobar"), BTreeSet::from([1]));
+        assert_eq!(set.matches("---"), BTreeSet::new());
+        assert_eq!(set.matches(""), BTreeSet::new());
+        assert_eq!(set.matches_at("42 a@b", 2), BTreeSet::from([3]));
+    }
+
+    #[test]
+    fn test_agrees_with_patterns() {
+        let patterns = [
+            r"a(b|c)*d",
+            r"(?<=x)y+",
+            r"\w{3}\b",
+            r"(a)\1",
+            r"(?>a+)b",
+            r"x?",
+            r"\s+",
+        ];
+        let set = GexSet::new(patterns).unwrap();
+        let inputs = ["abcbd xyy", "aab aa", "ab", "", "a é ", "zzz"];
+
+        for input in inputs {
+            let expected: BTreeMap<usize, Match> = patterns
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, pattern)| {
+                    compile_with(pattern, &CompileOptions::default())
+                        .unwrap()
+                        .find(input)
+                        .map(|found| (idx, found))
+                })
+                .collect();
+            assert_eq!(set.leftmost_matches(input), expected, "{:?}", input);
+            assert_eq!(
+                set.matches(input),
+                expected.keys().copied().collect(),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_leftmost_match_kinds() {
+        let options = CompileOptions {
+            match_kind: MatchKind::LeftmostFirst,
+            ..CompileOptions::default()
+        };
+        let set = GexSet::new_with([r"a|ab", r"b+"], &options).unwrap();
+        assert_eq!(
+            set.leftmost_matches("xabb"),
+            BTreeMap::from([
+                (0, Match { start: 1, end: 2 }),
+                (1, Match { start: 2, end: 4 })
+            ])
+        );
+        let set = GexSet::new([r"a|ab"]).unwrap();
+        assert_eq!(
+            set.leftmost_matches("xabb"),
+            BTreeMap::from([(0, Match { start: 1, end: 3 })])
+        );
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(GexSet::new(Vec::<&str>::new()).unwrap().is_empty());
+        assert!(matches!(
+            GexSet::new([r"a", r"b", r"[c"]),
+            Err(CompilerError::InPattern(
+                2,
+                error
+            )) if matches!(*error, CompilerError::LexicalError(TokenizeError::UnterminatedCharacterSet(0)))
+        ));
+    }
+}
@@ -0,0 +1,548 @@
+use crate::compile::{compile_nfa, CompileOptions, CompilerError};
+use crate::gex::cache::SparseSet;
+use crate::gex::{GexMachine, Next};
+use crate::matcher::{Match, MatchKind};
+use std::collections::VecDeque;
+use std::mem::take;
+use std::str::from_utf8;
+
+type Result<T> = std::result::Result<T, CompilerError>;
+
+/// A character decoded from the stream, and where its bytes sit in the stream.
+#[derive(Debug, Clone, Copy)]
+struct Decoded {
+    offset: usize,
+    width: usize,
+    character: char,
+}
+
+/// Threads of the scan between positions, and the match they are working towards.
+#[derive(Debug, Clone)]
+struct Scan {
+    /// Where each thread goes next, with where its match started, in priority order.
+    pending: Vec<(Next, usize)>,
+    /// States of the threads at the current position, with where their matches started.
+    threads: Vec<(usize, usize)>,
+    seen: SparseSet,
+    stack: Vec<Next>,
+    /// Best match found so far; it may still change while the threads that could beat it run.
+    candidate: Option<Match>,
+    /// Earliest position a new match may start at.
+    search_from: usize,
+}
+
+impl Scan {
+    fn new(size: usize) -> Self {
+        Scan {
+            pending: Vec::new(),
+            threads: Vec::new(),
+            seen: SparseSet::new(size),
+            stack: Vec::new(),
+            candidate: None,
+            search_from: 0,
+        }
+    }
+
+    /// Record a match by a thread, returning whether lower priority threads are to be dropped.
+    fn accept(&mut self, match_kind: MatchKind, start: usize, end: usize) -> bool {
+        let found = Match { start, end };
+        match match_kind {
+            // Only threads of higher priority are left to accept once a thread has accepted
+            MatchKind::LeftmostFirst => {
+                self.candidate = Some(found);
+                true
+            }
+            MatchKind::LeftmostLongest => {
+                if self.candidate.is_none_or(|candidate| {
+                    start < candidate.start || (start == candidate.start && end > candidate.end)
+                }) {
+                    self.candidate = Some(found);
+                }
+                false
+            }
+        }
+    }
+
+    /// Follow the zero-width transitions that hold at `position` from a thread, adding every
+    /// state reached. Returns whether lower priority threads are to be dropped.
+    fn follow(
+        &mut self,
+        machine: &GexMachine,
+        (next, start): (Next, usize),
+        position: usize,
+        window: &str,
+        window_position: usize,
+    ) -> bool {
+        self.stack.push(next);
+        while let Some(next) = self.stack.pop() {
+            let state_label = match next {
+                Next::Accept => {
+                    if self.accept(machine.match_kind(), start, position) {
+                        self.stack.clear();
+                        return true;
+                    }
+                    continue;
+                }
+                Next::Target(state_label) => state_label,
+            };
+            if !self.seen.insert(state_label) {
+                continue;
+            }
+            self.threads.push((state_label, start));
+            // Reversed so the first transition is explored first
+            for (rule, next) in machine.states[state_label].transitions.iter().rev() {
+                if rule.is_zero_width() && machine.evaluate_assertion(rule, window, window_position)
+                {
+                    self.stack.push(*next);
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Scan of text that arrives a chunk at a time, carrying its threads from one chunk to the
+/// next so matches may straddle chunks.
+///
+/// Each match is reported by its offsets in the whole text once no later input can change it,
+/// which takes at least the character after the match, or the end of the text. Only the text
+/// since the earliest start a match could still have is kept, which the scan resumes from once
+/// a match is reported. Matches don't overlap, as with `try_find_iter_at`.
+#[derive(Debug, Clone)]
+pub(crate) struct ChunkScan {
+    scan: Scan,
+    /// Bytes of a character split across chunks.
+    partial: Vec<u8>,
+    /// Offset in the text where `partial` starts: the end of the decoded text.
+    decoded_end: usize,
+    /// Characters kept for the scan, up to the end of the decoded text.
+    text: VecDeque<Decoded>,
+    /// The character before the first one kept, for word boundaries.
+    before: Option<char>,
+    /// Index in `text` of the next character to scan.
+    cursor: usize,
+}
+
+/// Whether the machine can be scanned a chunk at a time: lookarounds need text the scan may
+/// not have kept or received yet, and the scan can't backtrack.
+pub(crate) fn scans_in_chunks(machine: &GexMachine) -> bool {
+    !machine.has_lookarounds() && !machine.needs_backtracking()
+}
+
+impl ChunkScan {
+    /// A scan for matches of the machine starting at or after `at`.
+    pub(crate) fn new(machine: &GexMachine, at: usize) -> Self {
+        let mut scan = Scan::new(machine.size());
+        scan.search_from = at;
+        ChunkScan {
+            scan,
+            partial: Vec::new(),
+            decoded_end: 0,
+            text: VecDeque::new(),
+            before: None,
+            cursor: 0,
+        }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        self.decoded_end + self.partial.len()
+    }
+
+    /// Pass over a chunk that ends before any match can start, keeping only its last character
+    /// for word boundaries. Only valid before anything has been pushed.
+    pub(crate) fn skip(&mut self, chunk: &str) {
+        if let Some(last) = chunk.chars().next_back() {
+            self.before = Some(last);
+        }
+        self.decoded_end += chunk.len();
+    }
+
+    /// Scan the next chunk, calling `matched` with every match that is now final.
+    pub(crate) fn push<F: FnMut(Match)>(
+        &mut self,
+        machine: &GexMachine,
+        chunk: &[u8],
+        mut matched: F,
+    ) {
+        let mut bytes = take(&mut self.partial);
+        bytes.extend_from_slice(chunk);
+        let mut decoded = 0;
+        while decoded < bytes.len() {
+            match from_utf8(&bytes[decoded..]) {
+                Ok(valid) => {
+                    self.extend(valid);
+                    decoded = bytes.len();
+                }
+                Err(error) => {
+                    let valid_up_to = decoded + error.valid_up_to();
+                    self.extend(from_utf8(&bytes[decoded..valid_up_to]).unwrap());
+                    match error.error_len() {
+                        Some(invalid) => {
+                            self.extend_invalid(invalid);
+                            decoded = valid_up_to + invalid;
+                        }
+                        // The rest may be completed by the next chunk
+                        None => {
+                            self.partial = bytes[valid_up_to..].to_vec();
+                            decoded = bytes.len();
+                        }
+                    }
+                }
+            }
+        }
+
+        self.run(machine, false, &mut matched);
+        self.trim();
+    }
+
+    /// End the text, calling `matched` with the matches still pending.
+    pub(crate) fn finish<F: FnMut(Match)>(&mut self, machine: &GexMachine, mut matched: F) {
+        if !self.partial.is_empty() {
+            let invalid = self.partial.len();
+            self.partial.clear();
+            self.extend_invalid(invalid);
+        }
+        self.run(machine, true, &mut matched);
+    }
+
+    fn extend(&mut self, valid: &str) {
+        for (idx, character) in valid.char_indices() {
+            self.text.push_back(Decoded {
+                offset: self.decoded_end + idx,
+                width: character.len_utf8(),
+                character,
+            });
+        }
+        self.decoded_end += valid.len();
+    }
+
+    fn extend_invalid(&mut self, width: usize) {
+        self.text.push_back(Decoded {
+            offset: self.decoded_end,
+            width,
+            character: char::REPLACEMENT_CHARACTER,
+        });
+        self.decoded_end += width;
+    }
+
+    /// Scan the kept text from the cursor, through the end of the stream when `at_end`.
+    fn run<F: FnMut(Match)>(&mut self, machine: &GexMachine, at_end: bool, matched: &mut F) {
+        loop {
+            let next = self.text.get(self.cursor).copied();
+            if next.is_none() && !at_end {
+                return;
+            }
+            self.scan_position(machine, next);
+            if self.scan.pending.is_empty() {
+                if let Some(found) = self.scan.candidate.take() {
+                    matched(found);
+                    self.resume_after(found);
+                    continue;
+                }
+            }
+            match next {
+                Some(_) => self.cursor += 1,
+                None => return,
+            }
+        }
+    }
+
+    /// Follow the zero-width transitions of the pending threads at the cursor, then consume the
+    /// character there, if the stream has one.
+    fn scan_position(&mut self, machine: &GexMachine, next: Option<Decoded>) {
+        let position = next.map_or(self.decoded_end, |next| next.offset);
+        let prev = match self.cursor {
+            0 => self.before,
+            cursor => Some(self.text[cursor - 1].character),
+        };
+        // Word boundaries only need the characters on either side of the position
+        let mut buffer = [0u8; 8];
+        let prev_width = prev.map_or(0, |prev| prev.encode_utf8(&mut buffer).len());
+        let next_width = next.map_or(0, |next| {
+            next.character.encode_utf8(&mut buffer[prev_width..]).len()
+        });
+        let window = from_utf8(&buffer[..prev_width + next_width]).unwrap();
+
+        let scan = &mut self.scan;
+        scan.seen.clear();
+        scan.threads.clear();
+        // A new, lowest priority, thread starts here while no match has been found
+        let start = (scan.candidate.is_none() && position >= scan.search_from)
+            .then_some((Next::Target(0), position));
+        for thread in take(&mut scan.pending).into_iter().chain(start) {
+            if scan.follow(machine, thread, position, window, prev_width) {
+                break;
+            }
+        }
+        // Threads that started after the candidate can no longer win
+        if let Some(candidate) = scan.candidate {
+            scan.threads.retain(|&(_, start)| start <= candidate.start);
+        }
+
+        if let Some(next) = next {
+            let class = machine.class_of(next.character);
+            for &(state_label, start) in scan.threads.iter() {
+                for target in machine.consuming_targets(state_label, next.character, class) {
+                    scan.pending.push((target, start));
+                }
+            }
+        }
+    }
+
+    /// Move the cursor back to the end of a reported match, to search again from there.
+    fn resume_after(&mut self, found: Match) {
+        self.cursor = self
+            .text
+            .partition_point(|decoded| decoded.offset < found.end);
+        // An empty match is followed by a search from the next character
+        self.scan.search_from = match (found.start == found.end, self.text.get(self.cursor)) {
+            (true, Some(next)) => found.end + next.width,
+            (true, None) => found.end + 1,
+            (false, _) => found.end,
+        };
+    }
+
+    /// Drop the text before the earliest start a match could still have.
+    fn trim(&mut self) {
+        let position = self
+            .text
+            .get(self.cursor)
+            .map_or(self.decoded_end, |next| next.offset);
+        let keep_from = self
+            .scan
+            .pending
+            .iter()
+            .map(|&(_, start)| start)
+            .chain(self.scan.candidate.map(|candidate| candidate.start))
+            .fold(position, usize::min);
+        while self
+            .text
+            .front()
+            .is_some_and(|decoded| decoded.offset < keep_from)
+        {
+            self.before = self.text.pop_front().map(|decoded| decoded.character);
+            self.cursor -= 1;
+        }
+    }
+}
+
+/// Search over a stream of bytes pushed a chunk at a time, such as a large file or a socket.
+///
+/// Matches may straddle chunks, and are reported by their offsets in the whole stream once no
+/// later input can change them, which takes at least the character after the match, or the end
+/// of the stream. Only the text since the earliest start a match could still have is kept.
+/// Matches don't overlap, as with `try_find_iter_at`.
+///
+/// Bytes that aren't valid UTF-8 are searched as `U+FFFD`. Lookarounds need text the stream
+/// may not have kept or received yet, so they aren't supported, nor are patterns only the
+/// backtracker can search.
+#[derive(Debug, Clone)]
+pub struct StreamSearcher {
+    machine: GexMachine,
+    chunks: ChunkScan,
+}
+
+impl StreamSearcher {
+    pub fn new(pattern: &str) -> Result<Self> {
+        StreamSearcher::new_with(pattern, &CompileOptions::default())
+    }
+
+    pub fn new_with(pattern: &str, options: &CompileOptions) -> Result<Self> {
+        let machine = compile_nfa(pattern, options)?;
+        if !scans_in_chunks(&machine) {
+            return Err(CompilerError::Unsupported(
+                "lookarounds, backreferences, atomic groups and calls can't be searched in a stream"
+                    .to_string(),
+            ));
+        }
+
+        Ok(StreamSearcher {
+            chunks: ChunkScan::new(&machine, 0),
+            machine,
+        })
+    }
+
+    /// Number of bytes pushed since the stream began.
+    pub fn offset(&self) -> usize {
+        self.chunks.offset()
+    }
+
+    /// Search the next chunk of the stream, calling `matched` with every match that is now
+    /// final.
+    pub fn push<F: FnMut(Match)>(&mut self, chunk: &[u8], matched: F) {
+        self.chunks.push(&self.machine, chunk, matched);
+    }
+
+    /// End the stream, calling `matched` with the matches still pending, and get ready for a new
+    /// stream.
+    pub fn finish<F: FnMut(Match)>(&mut self, matched: F) {
+        self.chunks.finish(&self.machine, matched);
+        self.chunks = ChunkScan::new(&self.machine, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::compile_with;
+    use crate::matcher::Matcher;
+
+    /// Push the input in chunks of the given size, collecting every match.
+    fn stream_matches(
+        searcher: &mut StreamSearcher,
+        input: &[u8],
+        chunk_size: usize,
+    ) -> Vec<Match> {
+        let mut found = Vec::new();
+        for chunk in input.chunks(chunk_size) {
+            searcher.push(chunk, |matched| found.push(matched));
+        }
+        searcher.finish(|matched| found.push(matched));
+        found
+    }
+
+    fn all_matches(pattern: &str, input: &str, options: &CompileOptions) -> Vec<Match> {
+        let mut found = Vec::new();
+        compile_with(pattern, options)
+            .unwrap()
+            .try_find_iter_at(input, 0, |matched| {
+                found.push(matched);
+                Ok::<bool, ()>(true)
+            })
+            .unwrap();
+        found
+    }
+
+    #[test]
+    fn test_agrees_with_whole_input() {
+        let patterns = [
+            r"\w+",
+            r"a(b|c)*d",
+            r"\bis\b",
+            r"[0-9]{2,3}",
+            r"x|xy|xyz",
+            r"(a|ab)(c|bcd)",
+            r"\s*;",
+        ];
+        let inputs = [
+            "this is it; abcbd xyz 12345 ; x",
+            "abcd xy x isis is",
+            "",
+            "héllo wörld 42 ;",
+        ];
+
+        for match_kind in [MatchKind::LeftmostLongest, MatchKind::LeftmostFirst] {
+            let options = CompileOptions {
+                match_kind,
+                ..CompileOptions::default()
+            };
+            for pattern in patterns {
+                let mut searcher = StreamSearcher::new_with(pattern, &options).unwrap();
+                for input in inputs {
+                    let expected = all_matches(pattern, input, &options);
+                    for chunk_size in [1, 2, 3, 7, input.len().max(1)] {
+                        assert_eq!(
+                            stream_matches(&mut searcher, input.as_bytes(), chunk_size),
+                            expected,
+                            "{} on {:?} in chunks of {} ({:?})",
+                            pattern,
+                            input,
+                            chunk_size,
+                            match_kind
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_matches_straddle_chunks() {
+        let mut searcher = StreamSearcher::new(r"needle\d+").unwrap();
+        let mut found = Vec::new();
+        for chunk in ["hay nee", "dle", "12", "3 hay needle", "4"] {
+            searcher.push(chunk.as_bytes(), |matched| found.push(matched));
+        }
+        // The second match may still grow until the stream ends
+        assert_eq!(found, vec![Match { start: 4, end: 13 }]);
+        assert_eq!(searcher.offset(), 25);
+        searcher.finish(|matched| found.push(matched));
+        assert_eq!(found[1], Match { start: 18, end: 25 });
+    }
+
+    #[test]
+    fn test_empty_matches() {
+        let mut searcher = StreamSearcher::new(r"a*").unwrap();
+        assert_eq!(
+            stream_matches(&mut searcher, b"baab", 1),
+            vec![
+                Match { start: 0, end: 0 },
+                Match { start: 1, end: 3 },
+                Match { start: 3, end: 3 },
+                Match { start: 4, end: 4 }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_and_invalid_characters() {
+        let mut searcher = StreamSearcher::new(r"\w+").unwrap();
+        let input = "añb ü".as_bytes();
+        // Every split of a character across chunks decodes the same
+        for chunk_size in 1..input.len() {
+            assert_eq!(
+                stream_matches(&mut searcher, input, chunk_size),
+                vec![Match { start: 0, end: 4 }, Match { start: 5, end: 7 }]
+            );
+        }
+
+        // Offsets count the bytes of the stream, invalid ones included
+        let mut found = Vec::new();
+        searcher.push(b"ab\xff\xfecd\xc3", |matched| found.push(matched));
+        searcher.finish(|matched| found.push(matched));
+        assert_eq!(
+            found,
+            vec![Match { start: 0, end: 2 }, Match { start: 4, end: 6 }]
+        );
+    }
+
+    #[test]
+    fn test_keeps_only_pending_text() {
+        let mut searcher = StreamSearcher::new(r"ab+c").unwrap();
+        let mut found = Vec::new();
+        for _ in 0..1_000 {
+            searcher.push(b"xxxxxxxxxxxxxxxxxxxxxxxxxxxxxx", |matched| {
+                found.push(matched)
+            });
+        }
+        assert!(searcher.chunks.text.len() <= 1);
+        searcher.push(b"abbb", |matched| found.push(matched));
+        assert_eq!(searcher.chunks.text.len(), 4);
+        searcher.push(b"bc", |matched| found.push(matched));
+        // The match is final once the character after it shows it can't grow
+        assert!(found.is_empty());
+        searcher.push(b"x", |matched| found.push(matched));
+        assert_eq!(
+            found,
+            vec![Match {
+                start: 30_000,
+                end: 30_006
+            }]
+        );
+        assert!(searcher.chunks.text.len() <= 1);
+    }
+
+    #[test]
+    fn test_unsupported_patterns() {
+        for pattern in [r"a(?=b)", r"(a)\1", r"(?>a+)b", r"(a(?1)?b)"] {
+            assert!(
+                matches!(
+                    StreamSearcher::new(pattern),
+                    Err(CompilerError::Unsupported(_))
+                ),
+                "{}",
+                pattern
+            );
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use crate::matcher::{Captures, Match, MatchKind, Matcher};
+use std::collections::VecDeque;
+
+const ROOT: usize = 0;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Node {
+    /// Trie edges, kept sorted by byte so they can be binary searched.
+    transitions: Vec<(u8, usize)>,
+    fail: usize,
+    /// Lowest index of the patterns spelled out by this node, if any.
+    pattern: Option<usize>,
+    /// Length of the longest pattern ending at this node, including those found by following
+    /// the failure links.
+    longest_output: Option<usize>,
+    depth: usize,
+}
+
+impl Node {
+    fn new(depth: usize) -> Self {
+        Node {
+            transitions: Vec::new(),
+            fail: ROOT,
+            pattern: None,
+            longest_output: None,
+            depth,
+        }
+    }
+
+    fn child(&self, byte: u8) -> Option<usize> {
+        self.transitions
+            .binary_search_by_key(&byte, |&(edge, _)| edge)
+            .ok()
+            .map(|idx| self.transitions[idx].1)
+    }
+}
+
+/// Aho-Corasick automaton over the bytes of a set of literal patterns.
+///
+/// Searching runs the automaton to find the leftmost position where any pattern starts, then
+/// walks the trie from that position to choose between the patterns starting there according to
+/// the `MatchKind`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AhoCorasick {
+    nodes: Vec<Node>,
+    /// Dense copy of the root's transitions, since the root is revisited constantly.
+    root_transitions: Vec<usize>,
+    max_pattern_length: usize,
+    pattern_count: usize,
+    kind: MatchKind,
+}
+
+impl AhoCorasick {
+    pub fn new(patterns: &[String], kind: MatchKind) -> Self {
+        let mut nodes = vec![Node::new(0)];
+
+        for (pattern_idx, pattern) in patterns.iter().enumerate() {
+            let mut current = ROOT;
+            for &byte in pattern.as_bytes() {
+                current = match nodes[current].child(byte) {
+                    Some(next) => next,
+                    None => {
+                        let next = nodes.len();
+                        nodes.push(Node::new(nodes[current].depth + 1));
+                        let transitions = &mut nodes[current].transitions;
+                        let insert_at = transitions
+                            .binary_search_by_key(&byte, |&(edge, _)| edge)
+                            .unwrap_err();
+                        transitions.insert(insert_at, (byte, next));
+                        next
+                    }
+                };
+            }
+            let node = &mut nodes[current];
+            node.pattern.get_or_insert(pattern_idx);
+            node.longest_output = Some(node.depth);
+        }
+
+        let mut automaton = AhoCorasick {
+            nodes,
+            root_transitions: vec![ROOT; 256],
+            max_pattern_length: patterns.iter().map(String::len).max().unwrap_or(0),
+            pattern_count: patterns.len(),
+            kind,
+        };
+        automaton.build_failure_links();
+        automaton
+    }
+
+    /// Breadth-first pass filling in failure links and propagating outputs along them.
+    fn build_failure_links(&mut self) {
+        for &(byte, child) in self.nodes[ROOT].transitions.iter() {
+            self.root_transitions[byte as usize] = child;
+        }
+
+        let mut queue: VecDeque<usize> = self.nodes[ROOT]
+            .transitions
+            .iter()
+            .map(|&(_, child)| child)
+            .collect();
+
+        while let Some(current) = queue.pop_front() {
+            let transitions = self.nodes[current].transitions.clone();
+            for (byte, child) in transitions {
+                let mut fallback = self.nodes[current].fail;
+                let fail = loop {
+                    match self.nodes[fallback].child(byte) {
+                        Some(next) if next != child => break next,
+                        _ if fallback == ROOT => break ROOT,
+                        _ => fallback = self.nodes[fallback].fail,
+                    }
+                };
+                let inherited = self.nodes[fail].longest_output;
+                let node = &mut self.nodes[child];
+                node.fail = fail;
+                node.longest_output = node.longest_output.max(inherited);
+                queue.push_back(child);
+            }
+        }
+    }
+
+    pub fn kind(&self) -> MatchKind {
+        self.kind
+    }
+
+    pub fn pattern_count(&self) -> usize {
+        self.pattern_count
+    }
+
+    /// Patterns the automaton searches for, in order, read back out of the trie. A pattern that
+    /// repeats an earlier one can't be told apart from it, so the first pattern stands in for
+    /// it, which matches the same.
+    pub(crate) fn patterns(&self) -> Vec<String> {
+        let mut patterns = vec![None; self.pattern_count];
+        let mut pending = vec![(ROOT, Vec::new())];
+        while let Some((node, spelled)) = pending.pop() {
+            if let Some(pattern) = self.nodes[node].pattern {
+                patterns[pattern] = Some(String::from_utf8_lossy(&spelled).into_owned());
+            }
+            for &(byte, child) in self.nodes[node].transitions.iter() {
+                let mut spelled = spelled.clone();
+                spelled.push(byte);
+                pending.push((child, spelled));
+            }
+        }
+        let stand_in = patterns.first().cloned().flatten().unwrap_or_default();
+        patterns
+            .into_iter()
+            .map(|pattern| pattern.unwrap_or_else(|| stand_in.clone()))
+            .collect()
+    }
+
+    /// Length in bytes of the longest match the automaton can report.
+    pub fn max_match_bytes(&self) -> usize {
+        self.max_pattern_length
+    }
+
+    fn next_state(&self, mut state: usize, byte: u8) -> usize {
+        loop {
+            if state == ROOT {
+                return self.root_transitions[byte as usize];
+            }
+            if let Some(next) = self.nodes[state].child(byte) {
+                return next;
+            }
+            state = self.nodes[state].fail;
+        }
+    }
+
+    /// Finds the leftmost position at or after `at` where any pattern starts.
+    fn leftmost_start(&self, bytes: &[u8], at: usize) -> Option<usize> {
+        let mut best_start = self.nodes[ROOT].longest_output.map(|_| at);
+        let mut state = ROOT;
+
+        for (idx, &byte) in bytes.iter().enumerate().skip(at) {
+            if let Some(start) = best_start {
+                // Nothing that ends from here on can start at or before the best start
+                if idx - start >= self.max_pattern_length {
+                    break;
+                }
+            }
+            state = self.next_state(state, byte);
+            if let Some(length) = self.nodes[state].longest_output {
+                let start = idx + 1 - length;
+                if best_start.is_none_or(|best| start < best) {
+                    best_start = Some(start);
+                }
+            }
+        }
+
+        best_start
+    }
+
+    /// Chooses between the patterns that start exactly at `start`.
+    fn resolve(&self, bytes: &[u8], start: usize) -> Option<Match> {
+        let mut state = ROOT;
+        let mut chosen = self.nodes[ROOT].pattern.map(|pattern| (pattern, 0));
+
+        for &byte in bytes[start..].iter() {
+            state = match self.nodes[state].child(byte) {
+                Some(next) => next,
+                None => break,
+            };
+            let node = &self.nodes[state];
+            if let Some(pattern) = node.pattern {
+                chosen = match (self.kind, chosen) {
+                    (MatchKind::LeftmostFirst, Some((best, _))) if best < pattern => chosen,
+                    _ => Some((pattern, node.depth)),
+                };
+            }
+        }
+
+        chosen.map(|(_, length)| Match {
+            start,
+            end: start + length,
+        })
+    }
+}
+
+impl Matcher for AhoCorasick {
+    fn find_at(&self, input: &str, at: usize) -> Option<Match> {
+        let bytes = input.as_bytes();
+        self.leftmost_start(bytes, at)
+            .and_then(|start| self.resolve(bytes, start))
+    }
+
+    fn captures_at(&self, input: &str, at: usize) -> Option<Captures> {
+        self.find_at(input, at).map(Captures::from_match)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn automaton(patterns: &[&str], kind: MatchKind) -> AhoCorasick {
+        let patterns: Vec<String> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+        AhoCorasick::new(&patterns, kind)
+    }
+
+    fn matched<'a>(ac: &AhoCorasick, input: &'a str) -> Option<&'a str> {
+        ac.find(input).map(|found| found.substr(input))
+    }
+
+    #[test]
+    fn test_leftmost_start_wins() {
+        let ac = automaton(&["cd", "abcde", "bc"], MatchKind::LeftmostLongest);
+
+        assert_eq!(matched(&ac, "xabcdez"), Some("abcde"));
+        assert_eq!(matched(&ac, "xabcdz"), Some("bc"));
+        assert_eq!(matched(&ac, "xyz"), None);
+    }
+
+    #[test]
+    fn test_leftmost_first() {
+        let ac = automaton(&["a", "ab"], MatchKind::LeftmostFirst);
+        assert_eq!(matched(&ac, "xab"), Some("a"));
+
+        let ac = automaton(&["ab", "a"], MatchKind::LeftmostFirst);
+        assert_eq!(matched(&ac, "xab"), Some("ab"));
+    }
+
+    #[test]
+    fn test_leftmost_longest() {
+        let ac = automaton(&["a", "ab", "abc"], MatchKind::LeftmostLongest);
+
+        assert_eq!(matched(&ac, "xabd"), Some("ab"));
+        assert_eq!(matched(&ac, "xabcd"), Some("abc"));
+    }
+
+    #[test]
+    fn test_failure_links() {
+        let ac = automaton(&["he", "she", "his", "hers"], MatchKind::LeftmostLongest);
+
+        assert_eq!(ac.find("ushers"), Some(Match { start: 1, end: 4 }));
+        assert_eq!(ac.find_at("ushers", 2), Some(Match { start: 2, end: 6 }));
+        assert_eq!(matched(&ac, "ahishe"), Some("his"));
+    }
+
+    #[test]
+    fn test_iterates_all_matches() {
+        let keywords: Vec<String> = (0..2000).map(|idx| format!("api_{}_old", idx)).collect();
+        let ac = AhoCorasick::new(&keywords, MatchKind::LeftmostLongest);
+        let input = "call api_7_old then api_1999_old, not api_2000_old";
+
+        let mut found = Vec::new();
+        ac.try_find_iter_at(input, 0, |found_match| {
+            found.push(found_match.substr(input));
+            Ok::<bool, ()>(true)
+        })
+        .unwrap();
+
+        assert_eq!(found, vec!["api_7_old", "api_1999_old"]);
+    }
+
+    #[test]
+    fn test_unicode_literals() {
+        let ac = automaton(&["naïve", "café"], MatchKind::LeftmostFirst);
+
+        assert_eq!(matched(&ac, "un café naïve"), Some("café"));
+        assert_eq!(
+            ac.find_at("un café naïve", 4),
+            Some(Match { start: 9, end: 15 })
+        );
+    }
+}
@@ -0,0 +1,939 @@
+use crate::gex::gmatcher::{posix_prefers, Slots};
+use crate::gex::{GexMachine, Next, Rule};
+use crate::interrupt::Interrupt;
+use crate::matcher::{Captures, Match, MatchKind, Matcher};
+use std::error;
+use std::fmt;
+
+/// Default size, in bits, of the visited set; the longest input searched is this divided by the
+/// number of states in the machine.
+pub const DEFAULT_VISITED_CAPACITY: usize = 256 * 1024;
+
+/// Default number of calls to groups, or to the whole pattern, that may be nested at once.
+pub const DEFAULT_RECURSION_LIMIT: usize = 256;
+
+/// Default number of states a search that can't memoize may explore from any one start
+/// position before giving up.
+pub const DEFAULT_STEP_LIMIT: usize = 1 << 20;
+
+/// States explored without memoizing between checks of the interrupt.
+const INTERRUPT_CHECK_STEPS: usize = 4096;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BacktrackError {
+    /// The input is too long for every (state, position) pair to fit in the visited set.
+    InputTooLong { length: usize, max_length: usize },
+    /// POSIX submatch rules need every path compared, which memoizing the visited pairs rules
+    /// out.
+    UnsupportedCaptures,
+    /// The search explored more states from one start position than the step limit allows,
+    /// without settling whether a match starts there.
+    TooManySteps { limit: usize },
+    /// The search's interrupt fired before it was done.
+    Interrupted,
+}
+
+impl error::Error for BacktrackError {}
+
+impl fmt::Display for BacktrackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BacktrackError::InputTooLong { length, max_length } => write!(
+                f,
+                "Input of {} bytes exceeds the backtracking limit of {}",
+                length, max_length
+            ),
+            BacktrackError::UnsupportedCaptures => {
+                write!(
+                    f,
+                    "Leftmost-longest captures are not supported by backtracking"
+                )
+            }
+            BacktrackError::TooManySteps { limit } => write!(
+                f,
+                "Backtracking gave up after exploring {} states from one start",
+                limit
+            ),
+            BacktrackError::Interrupted => write!(f, "Backtracking was interrupted"),
+        }
+    }
+}
+
+enum Job {
+    /// Follow a transition to `Next` at the given position.
+    Explore(Next, usize),
+    /// Record the position in a capture slot, then follow the transition.
+    Save(usize, Next, usize),
+    /// Undo a capture made by a path that failed.
+    Restore(usize, Option<usize>),
+    /// Enter an atomic group, then follow the transition.
+    Enter(Next, usize),
+    /// Bottom of the jobs exploring the body of an atomic group.
+    Barrier,
+    /// Leave an atomic group, dropping the body's other paths, then follow the transition.
+    Commit(Next, usize),
+    /// Call the numbered group, returning to the transition's target once it matches.
+    Call(u16, Next, usize),
+    /// Undo a call made by a path that failed.
+    Leave,
+    /// Undo a return from a call, for a path that failed after it.
+    Resume(Frame),
+    /// Take the last entry off the trail, once every path through it has been explored.
+    Untrail,
+}
+
+/// Trail entry marking a call or a return, past which states are in a different call.
+const CALL_BOUNDARY: (usize, usize) = (usize::MAX, usize::MAX);
+
+/// Which (state, position) pairs a search marks as explored, skipping them when reached again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Memo {
+    Every,
+    /// Every pair, but those reached inside an atomic group only until the group is left. Which
+    /// paths a group drops depends on where it was entered, so a pair's outcome carries over
+    /// within one entry into the group but not to the next.
+    WithinAtomicGroups,
+    Nothing,
+}
+
+/// A call in progress: where to continue once it returns, and the captures to put back then.
+struct Frame {
+    next: Next,
+    slots: Slots,
+}
+
+/// Bit per (state, position) pair, marking the pairs a search has already explored.
+#[derive(Default)]
+struct Visited {
+    bits: Vec<u64>,
+    positions: usize,
+    at: usize,
+}
+
+impl Visited {
+    /// Unmark every pair, making room for `positions` positions from `at`.
+    fn reset(&mut self, states: usize, positions: usize, at: usize) {
+        self.bits.clear();
+        self.bits.resize((states * positions).div_ceil(64), 0);
+        self.positions = positions;
+        self.at = at;
+    }
+
+    /// Mark the pair, returning whether it was already marked.
+    fn insert(&mut self, state_label: usize, position: usize) -> bool {
+        let idx = state_label * self.positions + position - self.at;
+        let (word, bit) = (idx / 64, 1 << (idx % 64));
+        let seen = self.bits[word] & bit != 0;
+        self.bits[word] |= bit;
+        seen
+    }
+
+    fn remove(&mut self, state_label: usize, position: usize) {
+        let idx = state_label * self.positions + position - self.at;
+        self.bits[idx / 64] &= !(1 << (idx % 64));
+    }
+
+    /// Unmark the pairs marked since `start` in `scoped`, forgetting them there too.
+    fn unmark(&mut self, scoped: &mut Vec<(usize, usize)>, start: usize) {
+        for (state_label, position) in scoped.drain(start..) {
+            self.remove(state_label, position);
+        }
+    }
+}
+
+/// Scratch space for backtracking, allocated once and reused across searches of any machine.
+#[derive(Default)]
+pub struct BacktrackCache {
+    visited: Visited,
+    stack: Vec<Job>,
+    /// (state, position) pairs on the path being explored that aren't memoized.
+    trail: Vec<(usize, usize)>,
+    /// Pairs marked inside the open atomic groups, unmarked as each group is left.
+    scoped: Vec<(usize, usize)>,
+    /// Where the pairs of each open atomic group start in `scoped`, innermost last.
+    scopes: Vec<usize>,
+    /// Slots of the path being explored, or of the match once one is found.
+    slots: Slots,
+    /// Slots of the best match found so far under leftmost-longest.
+    best: Slots,
+}
+
+impl BacktrackCache {
+    pub fn new() -> Self {
+        BacktrackCache::default()
+    }
+}
+
+/// Depth-first search over a `GexMachine`, trying transitions in priority order.
+///
+/// Each (state, position) pair is explored at most once, since a pair that failed to reach the
+/// Accept will fail again however it is reached. That bounds the work by the size of the visited
+/// set, and in turn the input lengths the backtracker agrees to search. Longer inputs are
+/// declined; the `Matcher` impl falls back to the machine's own search for those.
+///
+/// Backreferences make whether a pair succeeds depend on what was captured on the way there,
+/// atomic groups on which paths were dropped before reaching it, and calls on which calls are in
+/// progress, so machines with any of them are searched without memoizing. Atomic groups only
+/// matter to pairs inside them, so machines whose atomic groups are all that needs backtracking
+/// still memoize, when the input fits the visited set: pairs outside the groups for the whole
+/// search, and pairs inside a group for as long as it stays open. The rest of the
+/// work is bounded by the step limit instead: a search that explores more states from one start
+/// position than it allows gives up with an error, which the `Matcher` impl doesn't, since no
+/// match would be a wrong answer. A path that comes back to a state at the same position, having consumed nothing
+/// since, is dropped, so loops whose body can match empty end. Calls nested deeper than the
+/// recursion limit fail instead of matching. An interrupt, when given, is checked every so
+/// often as states are explored without memoizing.
+#[derive(Debug, Clone, Copy)]
+pub struct Backtracker<'m> {
+    machine: &'m GexMachine,
+    visited_capacity: usize,
+    recursion_limit: usize,
+    step_limit: usize,
+    interrupt: Option<&'m Interrupt>,
+    memoize: bool,
+    /// Whether atomic groups are the only reason the machine needs backtracking.
+    only_atomic: bool,
+}
+
+impl<'m> Backtracker<'m> {
+    pub fn new(machine: &'m GexMachine) -> Self {
+        Backtracker {
+            machine,
+            visited_capacity: DEFAULT_VISITED_CAPACITY,
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+            step_limit: DEFAULT_STEP_LIMIT,
+            interrupt: None,
+            memoize: !machine.needs_backtracking(),
+            only_atomic: !machine.has_backreferences() && !machine.has_calls(),
+        }
+    }
+
+    pub fn with_visited_capacity(mut self, visited_capacity: usize) -> Self {
+        self.visited_capacity = visited_capacity;
+        self
+    }
+
+    pub fn with_recursion_limit(mut self, recursion_limit: usize) -> Self {
+        self.recursion_limit = recursion_limit;
+        self
+    }
+
+    /// Bound the states a search without memoizing explores from each start position.
+    pub fn with_step_limit(mut self, step_limit: usize) -> Self {
+        self.step_limit = step_limit;
+        self
+    }
+
+    /// Give up with `BacktrackError::Interrupted` once the interrupt fires.
+    pub fn with_interrupt(mut self, interrupt: &'m Interrupt) -> Self {
+        self.interrupt = Some(interrupt);
+        self
+    }
+
+    /// Longest input, in bytes past the search start, the backtracker will search.
+    pub fn max_input_length(&self) -> usize {
+        if !self.memoize {
+            return usize::MAX;
+        }
+        (self.visited_capacity / self.machine.size()).saturating_sub(1)
+    }
+
+    fn capture_slot_count(&self) -> usize {
+        2 * (self.machine.group_count() + 1)
+    }
+
+    pub fn try_find_at(&self, input: &str, at: usize) -> Result<Option<Match>, BacktrackError> {
+        self.try_find_at_cached(&mut BacktrackCache::new(), input, at)
+    }
+
+    /// `try_find_at`, reusing the scratch space in `cache` instead of allocating.
+    pub fn try_find_at_cached(
+        &self,
+        cache: &mut BacktrackCache,
+        input: &str,
+        at: usize,
+    ) -> Result<Option<Match>, BacktrackError> {
+        if !self.search(cache, input, at, false)? {
+            return Ok(None);
+        }
+        Ok(Some(Match {
+            start: cache.slots[0].expect("A match has a start"),
+            end: cache.slots[1].expect("A match has an end"),
+        }))
+    }
+
+    pub fn try_captures_at(
+        &self,
+        input: &str,
+        at: usize,
+    ) -> Result<Option<Captures>, BacktrackError> {
+        self.try_captures_at_cached(&mut BacktrackCache::new(), input, at)
+    }
+
+    /// `try_captures_at`, reusing the scratch space in `cache`; only the returned captures are
+    /// allocated.
+    pub fn try_captures_at_cached(
+        &self,
+        cache: &mut BacktrackCache,
+        input: &str,
+        at: usize,
+    ) -> Result<Option<Captures>, BacktrackError> {
+        if self.memoize && self.machine.match_kind() == MatchKind::LeftmostLongest {
+            return Err(BacktrackError::UnsupportedCaptures);
+        }
+        Ok(self
+            .search(cache, input, at, true)?
+            .then(|| self.machine.captures_from_slots(&cache.slots)))
+    }
+
+    /// Try each start position from `at` in turn, leaving the slots of the first match in
+    /// `cache.slots`.
+    fn search(
+        &self,
+        cache: &mut BacktrackCache,
+        input: &str,
+        at: usize,
+        captures: bool,
+    ) -> Result<bool, BacktrackError> {
+        let length = input.len() - at;
+        let fits = length <= (self.visited_capacity / self.machine.size()).saturating_sub(1);
+        let longest = self.machine.match_kind() == MatchKind::LeftmostLongest;
+        let memo = if self.memoize {
+            if !fits {
+                let max_length = self.max_input_length();
+                return Err(BacktrackError::InputTooLong { length, max_length });
+            }
+            Memo::Every
+        } else if self.only_atomic && fits && !(captures && longest) {
+            Memo::WithinAtomicGroups
+        } else {
+            Memo::Nothing
+        };
+        // Backreferences need the groups they refer to captured, even when only finding
+        let slot_count = match captures || self.machine.has_backreferences() {
+            true => self.capture_slot_count(),
+            false => 2,
+        };
+
+        let prefilter = self.machine.prefilter();
+        if prefilter.is_some_and(|prefilter| !prefilter.could_match(input, at)) {
+            return Ok(false);
+        }
+
+        // Pairs explored from an earlier start found no match, so they are shared by every start
+        if memo != Memo::Nothing {
+            cache.visited.reset(self.machine.size(), length + 1, at);
+        }
+        cache.stack.clear();
+        // Counted across every start, for the interrupt checks
+        let mut explored = 0;
+        let mut start = at;
+
+        loop {
+            if let Some(prefilter) = prefilter.filter(|prefilter| prefilter.is_prefix()) {
+                start = match prefilter.find_candidate(input, start) {
+                    Some(candidate) => candidate,
+                    None => return Ok(false),
+                };
+            }
+
+            cache.slots.clear();
+            cache.slots.resize(slot_count, None);
+            cache.slots[0] = Some(start);
+            if self.backtrack(input, start, memo, cache, &mut explored)? {
+                return Ok(true);
+            }
+
+            match input[start..].chars().next() {
+                Some(input_char) => start += input_char.len_utf8(),
+                None => return Ok(false),
+            }
+        }
+    }
+
+    /// Search for a match starting at `start`, leaving its slots in `slots`.
+    ///
+    /// Under leftmost-first the first path to reach the Accept wins. Under leftmost-longest every
+    /// path is tried and the one POSIX rules prefer is kept.
+    fn backtrack(
+        &self,
+        input: &str,
+        start: usize,
+        memo: Memo,
+        cache: &mut BacktrackCache,
+        explored: &mut usize,
+    ) -> Result<bool, BacktrackError> {
+        let BacktrackCache {
+            visited,
+            stack,
+            trail,
+            scoped,
+            scopes,
+            slots,
+            best,
+        } = cache;
+        trail.clear();
+        // Left marked by a search that gave up inside a group
+        visited.unmark(scoped, 0);
+        scopes.clear();
+        // Barriers on the stack, one for each atomic group the next job is inside
+        let mut open_groups = 0;
+        let longest = self.machine.match_kind() == MatchKind::LeftmostLongest;
+        let mut found = false;
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut steps = 0;
+        stack.push(Job::Explore(Next::Target(0), start));
+
+        while let Some(job) = stack.pop() {
+            let (state_label, position) = match job {
+                Job::Restore(slot, value) => {
+                    slots[slot] = value;
+                    continue;
+                }
+                Job::Save(slot, next, position) => {
+                    if let Some(entry) = slots.get_mut(slot) {
+                        stack.push(Job::Restore(slot, *entry));
+                        *entry = Some(position);
+                    }
+                    stack.push(Job::Explore(next, position));
+                    continue;
+                }
+                Job::Barrier => {
+                    open_groups -= 1;
+                    let start = scopes.pop().expect("A group is open");
+                    visited.unmark(scoped, start);
+                    continue;
+                }
+                Job::Enter(next, position) => {
+                    open_groups += 1;
+                    scopes.push(scoped.len());
+                    stack.push(Job::Barrier);
+                    stack.push(Job::Explore(next, position));
+                    continue;
+                }
+                Job::Commit(next, position) => {
+                    commit(stack);
+                    open_groups -= 1;
+                    let start = scopes.pop().expect("A group is open");
+                    visited.unmark(scoped, start);
+                    stack.push(Job::Explore(next, position));
+                    continue;
+                }
+                Job::Call(group_number, next, position) => {
+                    let entry = self.machine.subroutine_entry(group_number);
+                    if let Some(entry) = entry.filter(|_| frames.len() < self.recursion_limit) {
+                        frames.push(Frame {
+                            next,
+                            slots: slots.clone(),
+                        });
+                        stack.push(Job::Leave);
+                        trail.push(CALL_BOUNDARY);
+                        stack.push(Job::Untrail);
+                        stack.push(Job::Explore(Next::Target(entry), position));
+                    }
+                    continue;
+                }
+                Job::Leave => {
+                    frames.pop();
+                    continue;
+                }
+                Job::Untrail => {
+                    trail.pop();
+                    continue;
+                }
+                Job::Resume(frame) => {
+                    frames.push(frame);
+                    continue;
+                }
+                // Reaching the Accept inside a call returns from it
+                Job::Explore(Next::Accept, position) if !frames.is_empty() => {
+                    let frame = frames.pop().expect("A call is in progress");
+                    let next = frame.next;
+                    for (slot, saved) in frame.slots.iter().enumerate() {
+                        if slots[slot] != *saved {
+                            stack.push(Job::Restore(slot, slots[slot]));
+                            slots[slot] = *saved;
+                        }
+                    }
+                    stack.push(Job::Resume(frame));
+                    trail.push(CALL_BOUNDARY);
+                    stack.push(Job::Untrail);
+                    stack.push(Job::Explore(next, position));
+                    continue;
+                }
+                Job::Explore(Next::Accept, position) => {
+                    if !longest {
+                        stack.clear();
+                        slots[1] = Some(position);
+                        return Ok(true);
+                    }
+                    // The path's slots stand in for the candidate, ending here for the comparison
+                    let end = slots[1].replace(position);
+                    if !found || posix_prefers(slots, best) {
+                        best.clone_from(slots);
+                        found = true;
+                    }
+                    slots[1] = end;
+                    continue;
+                }
+                Job::Explore(Next::Target(state_label), position) => (state_label, position),
+            };
+            if memo != Memo::Nothing {
+                if visited.insert(state_label, position) {
+                    continue;
+                }
+                if open_groups > 0 {
+                    scoped.push((state_label, position));
+                    *explored += 1;
+                    if explored.is_multiple_of(INTERRUPT_CHECK_STEPS)
+                        && self.interrupt.is_some_and(Interrupt::is_interrupted)
+                    {
+                        return Err(BacktrackError::Interrupted);
+                    }
+                }
+            } else {
+                // Back where this path already was, with nothing consumed in between
+                let looped = trail
+                    .iter()
+                    .rev()
+                    .take_while(|(_, at)| *at == position)
+                    .any(|(label, _)| *label == state_label);
+                if looped {
+                    continue;
+                }
+                steps += 1;
+                if steps > self.step_limit {
+                    return Err(BacktrackError::TooManySteps {
+                        limit: self.step_limit,
+                    });
+                }
+                *explored += 1;
+                if explored.is_multiple_of(INTERRUPT_CHECK_STEPS)
+                    && self.interrupt.is_some_and(Interrupt::is_interrupted)
+                {
+                    return Err(BacktrackError::Interrupted);
+                }
+                trail.push((state_label, position));
+                stack.push(Job::Untrail);
+            }
+
+            let state = &self.machine.states[state_label];
+            let input_char = input[position..].chars().next();
+            // The dense table already accounts for short-circuit states
+            let table = input_char
+                .zip(self.machine.dense_table())
+                .map(|(input_char, dense)| {
+                    dense.transitions(state_label, dense.class_of(input_char))
+                });
+            let consumes = |idx: usize, rule: &Rule| match table {
+                Some(table) => table.contains(&(idx as u16)),
+                None => input_char
+                    .is_some_and(|input_char| GexMachine::evaluate_rule(rule, &input_char)),
+            };
+            // A single falsy rule rejects the whole short-circuit state
+            let rejected = table.is_none()
+                && state.short_circuit()
+                && !state
+                    .transitions
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (rule, _))| !rule.is_zero_width())
+                    .all(|(idx, (rule, _))| consumes(idx, rule));
+
+            // Reversed so the first transition is explored first
+            for (idx, (rule, next)) in state.transitions.iter().enumerate().rev() {
+                if let Rule::Backreference(group_number) = rule {
+                    if let Some(width) = backreference_width(slots, *group_number, input, position)
+                    {
+                        stack.push(Job::Explore(*next, position + width));
+                    }
+                } else if let Rule::Save(slot) = rule {
+                    stack.push(Job::Save(*slot, *next, position));
+                } else if let Rule::Call(group_number) = rule {
+                    stack.push(Job::Call(*group_number, *next, position));
+                } else if *rule == Rule::AtomicOpen {
+                    stack.push(Job::Enter(*next, position));
+                } else if *rule == Rule::AtomicClose {
+                    stack.push(Job::Commit(*next, position));
+                } else if rule.is_zero_width() {
+                    if self.machine.evaluate_assertion(rule, input, position) {
+                        stack.push(Job::Explore(*next, position));
+                    }
+                } else if !rejected && consumes(idx, rule) {
+                    let width = input_char.map_or(0, char::len_utf8);
+                    stack.push(Job::Explore(*next, position + width));
+                }
+            }
+        }
+
+        if found {
+            slots.clone_from(best);
+        }
+        Ok(found)
+    }
+}
+
+/// Drop the untried paths through the innermost open atomic group, down to and including its
+/// barrier. Captures, calls and trail entries made inside the group are still undone if the path
+/// later fails.
+fn commit(stack: &mut Vec<Job>) {
+    let mut restores = Vec::new();
+    while let Some(job) = stack.pop() {
+        match job {
+            Job::Barrier => break,
+            Job::Restore(..) | Job::Leave | Job::Resume(_) | Job::Untrail => restores.push(job),
+            Job::Explore(..) | Job::Save(..) | Job::Enter(..) | Job::Commit(..) | Job::Call(..) => {
+            }
+        }
+    }
+    stack.extend(restores.into_iter().rev());
+}
+
+/// Length of the text captured by the group when it repeats at `position`, or None when the group
+/// has captured nothing or the text doesn't repeat.
+fn backreference_width(
+    slots: &Slots,
+    group_number: u16,
+    input: &str,
+    position: usize,
+) -> Option<usize> {
+    let slot = 2 * group_number as usize;
+    let captured = match (slots.get(slot)?, slots.get(slot + 1)?) {
+        (Some(start), Some(end)) if start <= end => &input[*start..*end],
+        _ => return None,
+    };
+    input[position..]
+        .starts_with(captured)
+        .then_some(captured.len())
+}
+
+impl Matcher for Backtracker<'_> {
+    /// Searches past the step limit, since giving up would report no match where there may be
+    /// one; the machine's own search can't stand in, as it can't follow what made backtracking
+    /// necessary.
+    fn find_at(&self, input: &str, at: usize) -> Option<Match> {
+        match self.with_step_limit(usize::MAX).try_find_at(input, at) {
+            Ok(found) => found,
+            Err(_) => self.machine.find_at(input, at),
+        }
+    }
+
+    fn captures_at(&self, input: &str, at: usize) -> Option<Captures> {
+        match self.with_step_limit(usize::MAX).try_captures_at(input, at) {
+            Ok(captures) => captures,
+            Err(_) => self.machine.captures_at(input, at),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::compile_machine;
+    use crate::railroad::Ast;
+    use crate::tokenize::tokenize;
+
+    fn machine_for(pattern: &str, kind: MatchKind) -> GexMachine {
+        compile_machine(
+            &Ast::from_tokens(tokenize(pattern).unwrap()).unwrap(),
+            pattern,
+        )
+        .unwrap()
+        .with_match_kind(kind)
+    }
+
+    #[test]
+    fn test_backtracking_matches() {
+        let machine = machine_for(r"a|ab", MatchKind::LeftmostFirst);
+        let backtracker = Backtracker::new(&machine);
+        assert_eq!(
+            backtracker.try_find_at("xab", 0),
+            Ok(Some(Match { start: 1, end: 2 }))
+        );
+
+        let machine = machine_for(r"a|ab", MatchKind::LeftmostLongest);
+        let backtracker = Backtracker::new(&machine);
+        assert_eq!(
+            backtracker.try_find_at("xab", 0),
+            Ok(Some(Match { start: 1, end: 3 }))
+        );
+        assert_eq!(backtracker.try_find_at("xab", 2), Ok(None));
+
+        let machine = machine_for(r"\w+@\w+", MatchKind::LeftmostLongest);
+        assert_eq!(
+            Backtracker::new(&machine).find("mail: me@héllo!"),
+            Some(Match { start: 6, end: 15 })
+        );
+    }
+
+    #[test]
+    fn test_backtracking_captures() {
+        let machine = machine_for(r"(a|ab)(c|bcd)(d*)", MatchKind::LeftmostFirst);
+        let captures = Backtracker::new(&machine)
+            .try_captures_at("abcd", 0)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(captures.get(0), Some(Match { start: 0, end: 4 }));
+        assert_eq!(captures.get(1), Some(Match { start: 0, end: 1 }));
+        assert_eq!(captures.get(2), Some(Match { start: 1, end: 4 }));
+        assert_eq!(captures.get(3), Some(Match { start: 4, end: 4 }));
+
+        let machine = machine_for(r"(a|ab)(c|bcd)(d*)", MatchKind::LeftmostLongest);
+        assert_eq!(
+            Backtracker::new(&machine).try_captures_at("abcd", 0),
+            Err(BacktrackError::UnsupportedCaptures)
+        );
+    }
+
+    #[test]
+    fn test_declines_long_inputs() {
+        let machine = machine_for(r"b+c", MatchKind::LeftmostFirst);
+        let backtracker = Backtracker::new(&machine).with_visited_capacity(10 * machine.size());
+        let input = "aaaaaaaaaaaabbc";
+
+        assert_eq!(backtracker.max_input_length(), 9);
+        assert_eq!(
+            backtracker.try_find_at(input, 0),
+            Err(BacktrackError::InputTooLong {
+                length: 15,
+                max_length: 9
+            })
+        );
+        assert_eq!(
+            backtracker.try_find_at(input, 6),
+            Ok(Some(Match { start: 12, end: 15 }))
+        );
+        // The Matcher impl still finds matches in long inputs
+        assert_eq!(backtracker.find(input), Some(Match { start: 12, end: 15 }));
+    }
+
+    #[test]
+    fn test_nested_repetition_stays_bounded() {
+        for kind in [MatchKind::LeftmostFirst, MatchKind::LeftmostLongest] {
+            let machine = machine_for(r"(a*)*(a|b)*c", kind);
+            let input = "a".repeat(2000);
+            assert_eq!(Backtracker::new(&machine).try_find_at(&input, 0), Ok(None));
+        }
+    }
+
+    #[test]
+    fn test_agrees_with_pike_vm() {
+        let patterns = [r"a*b|ab*", r"(x|xy)(z|yz)?", r"\d+(\.\d+)?", r"[^ ]+ ?"];
+        let inputs = ["", "aab abb", "xyz xz", "v1.25 or 3.", "one two"];
+
+        for kind in [MatchKind::LeftmostFirst, MatchKind::LeftmostLongest] {
+            for pattern in patterns {
+                let machine = machine_for(pattern, kind);
+                let backtracker = Backtracker::new(&machine);
+                for input in inputs {
+                    assert_eq!(
+                        backtracker.try_find_at(input, 0),
+                        Ok(machine.find(input)),
+                        "{} on {:?}",
+                        pattern,
+                        input
+                    );
+                    if kind == MatchKind::LeftmostFirst {
+                        assert_eq!(
+                            backtracker.try_captures_at(input, 0),
+                            Ok(machine.captures(input))
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_repeated_lookarounds_end() {
+        let machine = machine_for(r"(?:(?=a))*(a)\1", MatchKind::LeftmostFirst);
+        let backtracker = Backtracker::new(&machine);
+
+        assert_eq!(backtracker.try_find_at("a", 0), Ok(None));
+        assert_eq!(
+            backtracker
+                .try_captures_at("aa", 0)
+                .unwrap()
+                .unwrap()
+                .get(1),
+            Some(Match { start: 0, end: 1 })
+        );
+    }
+
+    #[test]
+    fn test_memoizes_outside_atomic_groups() {
+        let machine = machine_for(r"(?>x?)(a|a)*\d", MatchKind::LeftmostFirst);
+        let backtracker = Backtracker::new(&machine);
+        let input = "a".repeat(20);
+
+        assert_eq!(backtracker.try_find_at(&input, 0), Ok(None));
+        assert_eq!(
+            backtracker.try_find_at(&format!("{}1", input), 0),
+            Ok(Some(Match { start: 0, end: 21 }))
+        );
+
+        let machine = machine_for(r"(?:(?=a))*(?>a)", MatchKind::LeftmostFirst);
+        assert_eq!(
+            Backtracker::new(&machine).try_find_at("a", 0),
+            Ok(Some(Match { start: 0, end: 1 }))
+        );
+        // Inputs too long to memoize are searched within the step limit instead, alike
+        let patterns = [
+            r"(?>a+)b|a",
+            r"(?>a|ab)c|(a*)(?>b*)b",
+            r"x(?>(a|ab)*)c",
+            r"a?(?>a+)ab",
+            r"(?>(?>a|ab)+b)c",
+        ];
+        let inputs = [
+            "aab",
+            "aa",
+            "abc ac",
+            "abbb",
+            "xababc xaac",
+            "aaab",
+            "ababbc",
+        ];
+        for kind in [MatchKind::LeftmostFirst, MatchKind::LeftmostLongest] {
+            for pattern in patterns {
+                let machine = machine_for(pattern, kind);
+                let memoized = Backtracker::new(&machine);
+                let unmemoized = memoized.with_visited_capacity(0);
+                for input in inputs {
+                    assert_eq!(
+                        memoized.try_find_at(input, 0),
+                        unmemoized.try_find_at(input, 0),
+                        "{} on {:?}",
+                        pattern,
+                        input
+                    );
+                    assert_eq!(
+                        memoized.try_captures_at(input, 0),
+                        unmemoized.try_captures_at(input, 0)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_memoizes_inside_atomic_groups() {
+        // Each entry into the group takes the rest of the input, and is memoized while it lasts
+        let machine = machine_for(r"(?>a+)b", MatchKind::LeftmostFirst);
+        let backtracker = Backtracker::new(&machine).with_step_limit(100);
+        let input = "a".repeat(1_000);
+        assert_eq!(backtracker.try_find_at(&input, 0), Ok(None));
+        assert_eq!(
+            backtracker.try_find_at(&format!("{}b", input), 0),
+            Ok(Some(Match {
+                start: 0,
+                end: 1_001
+            }))
+        );
+
+        // Exponentially many ways through the group, but each pair is explored once an entry
+        for kind in [MatchKind::LeftmostFirst, MatchKind::LeftmostLongest] {
+            let machine = machine_for(r"(?>(a|a)*)\d", kind);
+            let backtracker = Backtracker::new(&machine).with_step_limit(100);
+            let input = "a".repeat(300);
+            assert_eq!(backtracker.try_find_at(&input, 0), Ok(None));
+            assert_eq!(
+                backtracker.try_find_at(&format!("{}7", input), 0),
+                Ok(Some(Match { start: 0, end: 301 }))
+            );
+        }
+
+        // What an earlier entry into the group explored isn't skipped by a later one: the group
+        // entered at 0 takes "aa", which the entry at 1 saw as the end of its own "a"
+        let machine = machine_for(r"a?(?>a+)ab", MatchKind::LeftmostFirst);
+        assert_eq!(Backtracker::new(&machine).try_find_at("aab", 0), Ok(None));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let machine = machine_for(r"(a|a)*\1\d", MatchKind::LeftmostFirst);
+        let backtracker = Backtracker::new(&machine);
+        let input = "a".repeat(22);
+
+        assert_eq!(
+            backtracker.try_find_at(&input, 0),
+            Err(BacktrackError::TooManySteps {
+                limit: DEFAULT_STEP_LIMIT
+            })
+        );
+        assert_eq!(
+            backtracker.with_step_limit(1000).try_find_at("aaa1", 0),
+            Ok(Some(Match { start: 0, end: 4 }))
+        );
+        // The limit bounds each start on its own, and `find` searches on past it
+        let input = format!("{}b aa1", "a".repeat(12));
+        let limited = backtracker.with_step_limit(1000);
+        assert_eq!(
+            limited.try_find_at(&input, 0),
+            Err(BacktrackError::TooManySteps { limit: 1000 })
+        );
+        assert_eq!(
+            limited.try_find_at(&input, 12),
+            Ok(Some(Match { start: 14, end: 17 }))
+        );
+        assert_eq!(limited.find(&input), Some(Match { start: 14, end: 17 }));
+
+        let machine = machine_for(r"\b(\w+)\s+\1\b", MatchKind::LeftmostFirst);
+        let words: String = (0..20_000).map(|word| format!("w{} ", word)).collect();
+        let line = format!("{}the the", words);
+        assert_eq!(
+            Backtracker::new(&machine).try_find_at(&line, 0),
+            Ok(Some(Match {
+                start: line.len() - 7,
+                end: line.len()
+            }))
+        );
+    }
+
+    #[test]
+    fn test_empty_iterations_end_loops() {
+        let machine = machine_for(r"(?:\b)*(a)\1", MatchKind::LeftmostFirst);
+        let backtracker = Backtracker::new(&machine);
+
+        assert_eq!(backtracker.try_find_at("a", 0), Ok(None));
+        assert_eq!(
+            backtracker.try_find_at("baa", 0),
+            Ok(Some(Match { start: 1, end: 3 }))
+        );
+    }
+
+    #[test]
+    fn test_recursion_limit() {
+        let machine = machine_for(r"\((?R)?\)", MatchKind::LeftmostFirst);
+        let find = |recursion_limit| {
+            Backtracker::new(&machine)
+                .with_recursion_limit(recursion_limit)
+                .try_find_at("((()))", 0)
+        };
+
+        assert_eq!(find(2), Ok(Some(Match { start: 0, end: 6 })));
+        // Too shallow to match from the outermost parenthesis, so the match starts further in
+        assert_eq!(find(1), Ok(Some(Match { start: 1, end: 5 })));
+        assert_eq!(find(0), Ok(Some(Match { start: 2, end: 4 })));
+    }
+
+    #[test]
+    fn test_calls_within_step_limit() {
+        let machine = machine_for(r"\((?:[^()]|(?R))*\)", MatchKind::LeftmostFirst);
+        let backtracker = Backtracker::new(&machine);
+
+        assert_eq!(
+            backtracker.try_find_at("f((a)(b(c)))", 0),
+            Ok(Some(Match { start: 1, end: 12 }))
+        );
+        // Unbalanced, so every way of nesting the calls is tried, until the limit stops it
+        assert_eq!(
+            backtracker.try_find_at(&"(a".repeat(200), 0),
+            Err(BacktrackError::TooManySteps {
+                limit: DEFAULT_STEP_LIMIT
+            })
+        );
+        assert_eq!(backtracker.try_find_at(&"(a".repeat(5), 0), Ok(None));
+    }
+}
@@ -0,0 +1,5 @@
+//! Search engines specialised for particular shapes of pattern, chosen by the compiler when a
+//! pattern allows it.
+pub mod aho_corasick;
+pub mod backtrack;
+pub mod shift_and;
@@ -0,0 +1,425 @@
+use crate::gex::{GexMachine, Rule, State};
+use crate::matcher::{Captures, Match, MatchKind, Matcher};
+use std::iter::once;
+
+/// Most positions a pattern may have, one per bit of the state word; only patterns with fewer
+/// than 64 qualify, so the word always has a bit to spare.
+pub const MAX_POSITIONS: usize = 63;
+
+/// Bits of the state word looked up at once when following transitions.
+const CHUNK_BITS: usize = 8;
+const CHUNK_VALUES: usize = 1 << CHUNK_BITS;
+
+/// A sub-pattern under construction, described by the positions it can begin and end on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fragment {
+    first: u64,
+    last: u64,
+    nullable: bool,
+    /// Shortest and longest match, in characters.
+    min_length: usize,
+    max_length: usize,
+}
+
+/// Builds the Glushkov automaton of a pattern: one position for each character class in the
+/// pattern, and for each position the set of positions that may follow it.
+///
+/// Only patterns with a bounded match length can be built, since the search relies on it to find
+/// where matches start.
+#[derive(Debug, Default)]
+pub struct ShiftAndBuilder {
+    classes: Vec<State>,
+    follow: Vec<u64>,
+}
+
+impl ShiftAndBuilder {
+    pub fn new() -> Self {
+        ShiftAndBuilder::default()
+    }
+
+    /// A fragment matching one character of `class`, or None once every position is taken.
+    pub fn position(&mut self, class: State) -> Option<Fragment> {
+        if self.classes.len() >= MAX_POSITIONS {
+            return None;
+        }
+        let bit = 1 << self.classes.len();
+        self.classes.push(class);
+        self.follow.push(0);
+        Some(Fragment {
+            first: bit,
+            last: bit,
+            nullable: false,
+            min_length: 1,
+            max_length: 1,
+        })
+    }
+
+    /// A fragment matching only the empty string.
+    pub fn empty(&self) -> Fragment {
+        Fragment {
+            first: 0,
+            last: 0,
+            nullable: true,
+            min_length: 0,
+            max_length: 0,
+        }
+    }
+
+    pub fn cons(&mut self, left: Fragment, right: Fragment) -> Fragment {
+        for (position, follow) in self.follow.iter_mut().enumerate() {
+            if left.last & (1 << position) != 0 {
+                *follow |= right.first;
+            }
+        }
+        Fragment {
+            first: left.first | if left.nullable { right.first } else { 0 },
+            last: right.last | if right.nullable { left.last } else { 0 },
+            nullable: left.nullable && right.nullable,
+            min_length: left.min_length + right.min_length,
+            max_length: left.max_length + right.max_length,
+        }
+    }
+
+    pub fn or(&self, left: Fragment, right: Fragment) -> Fragment {
+        Fragment {
+            first: left.first | right.first,
+            last: left.last | right.last,
+            nullable: left.nullable || right.nullable,
+            min_length: left.min_length.min(right.min_length),
+            max_length: left.max_length.max(right.max_length),
+        }
+    }
+
+    pub fn zero_or_one(&self, operand: Fragment) -> Fragment {
+        Fragment {
+            nullable: true,
+            min_length: 0,
+            ..operand
+        }
+    }
+
+    /// Finish the automaton for `pattern`.
+    ///
+    /// The search cannot rank matches by priority, so leftmost-first is only offered when every
+    /// match has the same length and the two kinds agree.
+    pub fn build(self, pattern: Fragment, kind: MatchKind) -> Option<ShiftAnd> {
+        if kind == MatchKind::LeftmostFirst && pattern.min_length != pattern.max_length {
+            return None;
+        }
+
+        let chunks = self.classes.len().div_ceil(CHUNK_BITS);
+        let mut follow_table = vec![0; chunks * CHUNK_VALUES];
+        for chunk in 0..chunks {
+            for value in 0..CHUNK_VALUES {
+                follow_table[chunk * CHUNK_VALUES + value] = (0..CHUNK_BITS)
+                    .filter(|bit| value & (1 << bit) != 0)
+                    .filter_map(|bit| self.follow.get(chunk * CHUNK_BITS + bit))
+                    .fold(0, |acc, follow| acc | follow);
+            }
+        }
+
+        let mut automaton = ShiftAnd {
+            classes: self.classes,
+            follow_table,
+            ascii_masks: Vec::new(),
+            first: pattern.first,
+            last: pattern.last,
+            nullable: pattern.nullable,
+            max_length: pattern.max_length,
+            kind,
+        };
+        automaton.ascii_masks = (0..128u8)
+            .map(|byte| automaton.compute_mask(byte as char))
+            .collect();
+        Some(automaton)
+    }
+}
+
+/// Bit-parallel simulation of a Glushkov automaton, for short patterns of bounded length.
+///
+/// Bit `i` of the state word is set while position `i` is active, and a character moves every
+/// active position to its followers in a handful of table lookups. Searching finds the earliest
+/// end of any match, then runs anchored from each start that could reach it, which is only a
+/// short window because match lengths are bounded.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ShiftAnd {
+    classes: Vec<State>,
+    /// Union of the followers of every position set in each byte of the state word.
+    follow_table: Vec<u64>,
+    /// Positions accepting each ASCII character.
+    ascii_masks: Vec<u64>,
+    first: u64,
+    last: u64,
+    nullable: bool,
+    max_length: usize,
+    kind: MatchKind,
+}
+
+/// What a `ShiftAnd` is made of, apart from the tables derived from it, for serializing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ShiftAndParts {
+    pub classes: Vec<State>,
+    /// Positions that may follow each position.
+    pub follow: Vec<u64>,
+    pub first: u64,
+    pub last: u64,
+    pub nullable: bool,
+    pub max_length: usize,
+    pub kind: MatchKind,
+}
+
+impl ShiftAnd {
+    pub(crate) fn to_parts(&self) -> ShiftAndParts {
+        let follow = (0..self.classes.len())
+            .map(|position| {
+                let (chunk, bit) = (position / CHUNK_BITS, position % CHUNK_BITS);
+                self.follow_table[chunk * CHUNK_VALUES + (1 << bit)]
+            })
+            .collect();
+        ShiftAndParts {
+            classes: self.classes.clone(),
+            follow,
+            first: self.first,
+            last: self.last,
+            nullable: self.nullable,
+            max_length: self.max_length,
+            kind: self.kind,
+        }
+    }
+
+    /// The automaton made of the parts, or None when they refer to positions it doesn't have.
+    pub(crate) fn from_parts(parts: ShiftAndParts) -> Option<ShiftAnd> {
+        let positions = parts.classes.len();
+        if positions > MAX_POSITIONS || parts.follow.len() != positions {
+            return None;
+        }
+        let unused = u64::MAX << positions;
+        let sets = [parts.first, parts.last]
+            .into_iter()
+            .chain(parts.follow.iter().copied());
+        if sets.into_iter().any(|set| set & unused != 0) {
+            return None;
+        }
+        let builder = ShiftAndBuilder {
+            classes: parts.classes,
+            follow: parts.follow,
+        };
+        let pattern = Fragment {
+            first: parts.first,
+            last: parts.last,
+            nullable: parts.nullable,
+            min_length: parts.max_length,
+            max_length: parts.max_length,
+        };
+        builder.build(pattern, parts.kind)
+    }
+
+    pub fn kind(&self) -> MatchKind {
+        self.kind
+    }
+
+    pub fn position_count(&self) -> usize {
+        self.classes.len()
+    }
+
+    /// Length in bytes of the longest match the automaton can report.
+    pub fn max_match_bytes(&self) -> usize {
+        self.max_length * char::MAX.len_utf8()
+    }
+
+    fn compute_mask(&self, input_char: char) -> u64 {
+        self.classes
+            .iter()
+            .enumerate()
+            .filter(|(_, class)| class_matches(class, input_char))
+            .fold(0, |acc, (position, _)| acc | 1 << position)
+    }
+
+    fn mask(&self, input_char: char) -> u64 {
+        match self.ascii_masks.get(input_char as usize) {
+            Some(&mask) => mask,
+            None => self.compute_mask(input_char),
+        }
+    }
+
+    fn follow(&self, state: u64) -> u64 {
+        self.follow_table
+            .chunks(CHUNK_VALUES)
+            .enumerate()
+            .fold(0, |acc, (chunk, table)| {
+                acc | table[((state >> (chunk * CHUNK_BITS)) as usize) & (CHUNK_VALUES - 1)]
+            })
+    }
+
+    /// Where the earliest ending match that starts at or after `at` ends.
+    fn earliest_end(&self, input: &str, at: usize) -> Option<usize> {
+        if self.nullable {
+            return Some(at);
+        }
+        let mut state = 0;
+        for (idx, input_char) in input[at..].char_indices() {
+            state = (self.follow(state) | self.first) & self.mask(input_char);
+            if state & self.last != 0 {
+                return Some(at + idx + input_char.len_utf8());
+            }
+        }
+        None
+    }
+
+    /// End of the longest match starting exactly at `start`.
+    fn longest_at(&self, input: &str, start: usize) -> Option<usize> {
+        let mut end = self.nullable.then_some(start);
+        let mut state = self.first;
+        for (idx, input_char) in input[start..].char_indices() {
+            state &= self.mask(input_char);
+            if state == 0 {
+                break;
+            }
+            if state & self.last != 0 {
+                end = Some(start + idx + input_char.len_utf8());
+            }
+            state = self.follow(state);
+        }
+        end
+    }
+}
+
+fn class_matches(class: &State, input_char: char) -> bool {
+    let mut rules = class
+        .transitions
+        .iter()
+        .map(|(rule, _)| rule)
+        .filter(|rule| **rule != Rule::Null);
+    if class.short_circuit() {
+        rules.all(|rule| GexMachine::evaluate_rule(rule, &input_char))
+    } else {
+        rules.any(|rule| GexMachine::evaluate_rule(rule, &input_char))
+    }
+}
+
+impl Matcher for ShiftAnd {
+    fn find_at(&self, input: &str, at: usize) -> Option<Match> {
+        let end = self.earliest_end(input, at)?;
+        // The leftmost match starts no later than the earliest end, and no match is longer than
+        // `max_length` characters
+        let window_start = input[at..end]
+            .char_indices()
+            .rev()
+            .nth(self.max_length.saturating_sub(1))
+            .map_or(at, |(idx, _)| at + idx);
+
+        input[window_start..end]
+            .char_indices()
+            .map(|(idx, _)| window_start + idx)
+            .chain(once(end))
+            .find_map(|start| {
+                self.longest_at(input, start).map(|match_end| Match {
+                    start,
+                    end: match_end,
+                })
+            })
+    }
+
+    fn captures_at(&self, input: &str, at: usize) -> Option<Captures> {
+        self.find_at(input, at).map(Captures::from_match)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::{compile_machine, compile_shift_and};
+    use crate::railroad::Ast;
+    use crate::tokenize::tokenize;
+
+    fn ast_for(pattern: &str) -> Ast {
+        Ast::from_tokens(tokenize(pattern).unwrap()).unwrap()
+    }
+
+    fn automaton(pattern: &str, kind: MatchKind) -> Option<ShiftAnd> {
+        compile_shift_and(&ast_for(pattern), pattern, kind)
+    }
+
+    fn matched<'a>(pattern: &str, input: &'a str) -> Option<&'a str> {
+        automaton(pattern, MatchKind::LeftmostLongest)
+            .unwrap()
+            .find(input)
+            .map(|found| found.substr(input))
+    }
+
+    #[test]
+    fn test_qualifying_patterns() {
+        assert!(automaton(r"a\d[x-z]?b", MatchKind::LeftmostLongest).is_some());
+        assert!(automaton(r"ab|cd", MatchKind::LeftmostFirst).is_some());
+        assert!(automaton(r"a|ab", MatchKind::LeftmostFirst).is_none());
+        assert!(automaton(r"ab*", MatchKind::LeftmostLongest).is_none());
+        assert!(automaton(r"(ab)", MatchKind::LeftmostLongest).is_none());
+
+        let long = "x".repeat(MAX_POSITIONS);
+        assert_eq!(
+            automaton(&long, MatchKind::LeftmostLongest)
+                .unwrap()
+                .position_count(),
+            MAX_POSITIONS
+        );
+        assert!(automaton(&format!("{}y", long), MatchKind::LeftmostLongest).is_none());
+        assert!(automaton(&"x".repeat(64), MatchKind::LeftmostLongest).is_none());
+        assert!(automaton(&"[a-c]".repeat(64), MatchKind::LeftmostFirst).is_none());
+    }
+
+    #[test]
+    fn test_shift_and_matches() {
+        assert_eq!(matched(r"b\dc", "ab1cd"), Some("b1c"));
+        assert_eq!(matched(r"a|ab", "xab"), Some("ab"));
+        assert_eq!(matched(r"colou?r", "my colour"), Some("colour"));
+        assert_eq!(matched(r"[^a]b", "abbb"), Some("bb"));
+        assert_eq!(matched(r"\w\s\w", "héllo wörld"), Some("o w"));
+        assert_eq!(matched(r"ab?c?", "xacab"), Some("ac"));
+        assert_eq!(matched(r"x?", "abc"), Some(""));
+        assert_eq!(matched(r"abc", "ababd"), None);
+    }
+
+    #[test]
+    fn test_leftmost_start_before_earliest_end() {
+        // "bc" ends first, but the longer alternative started earlier
+        assert_eq!(matched(r"abcd|bc", "xabcd"), Some("abcd"));
+        assert_eq!(matched(r"a..d|c", "abcd"), Some("abcd"));
+        assert_eq!(
+            automaton(r"a..d|c", MatchKind::LeftmostLongest)
+                .unwrap()
+                .find_at("abcdabc", 1),
+            Some(Match { start: 2, end: 3 })
+        );
+    }
+
+    #[test]
+    fn test_wide_patterns() {
+        let pattern = format!("{}[0-9]", "ab".repeat(31));
+        let input = format!("zz{}7", "ab".repeat(31));
+        assert_eq!(matched(&pattern, &input), Some(&input[2..]));
+        assert_eq!(matched(&pattern, &input[..input.len() - 1]), None);
+    }
+
+    #[test]
+    fn test_agrees_with_nfa() {
+        let patterns = [r"ab|a.c", r"a?b?c", r"[a-c]\d|\w\w\w", r"x|xy?z?", r".\s."];
+        let inputs = ["", "abc", "a1c b2", "xyz xz x", "a bc", "ćc1 ab"];
+
+        for pattern in patterns {
+            let shift_and = automaton(pattern, MatchKind::LeftmostLongest).unwrap();
+            let machine = compile_machine(&ast_for(pattern), pattern).unwrap();
+            for input in inputs {
+                for at in input.char_indices().map(|(idx, _)| idx) {
+                    assert_eq!(
+                        shift_and.find_at(input, at),
+                        machine.find_at(input, at),
+                        "{} on {:?} at {}",
+                        pattern,
+                        input,
+                        at
+                    );
+                }
+            }
+        }
+    }
+}
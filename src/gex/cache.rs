@@ -0,0 +1,239 @@
+use crate::gex::gmatcher::Slots;
+use crate::gex::machine::{GexMachine, Next};
+use crate::gex::reverse::StateList;
+
+/// Set of state ids below a fixed capacity, with constant time insertion, lookup and clearing.
+///
+/// `dense` holds the members in insertion order, and `sparse` maps a member back to its place in
+/// `dense`. Stale entries in `sparse` are harmless, since a value is only a member if the two
+/// agree, so clearing never has to touch `sparse`.
+#[derive(Debug, Clone)]
+pub(crate) struct SparseSet {
+    dense: Vec<usize>,
+    sparse: Vec<usize>,
+}
+
+impl SparseSet {
+    pub(crate) fn new(capacity: usize) -> Self {
+        SparseSet {
+            dense: Vec::with_capacity(capacity),
+            sparse: vec![0; capacity],
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.sparse.len()
+    }
+
+    /// Grow the set to hold values below `capacity`, emptying it.
+    pub(crate) fn resize(&mut self, capacity: usize) {
+        self.clear();
+        if capacity > self.capacity() {
+            self.dense.reserve(capacity - self.dense.len());
+            self.sparse.resize(capacity, 0);
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.dense.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.dense.is_empty()
+    }
+
+    /// Where the value was inserted, in insertion order, if it is a member.
+    pub(crate) fn index_of(&self, value: usize) -> Option<usize> {
+        let idx = self.sparse[value];
+        (idx < self.dense.len() && self.dense[idx] == value).then_some(idx)
+    }
+
+    pub(crate) fn contains(&self, value: usize) -> bool {
+        self.index_of(value).is_some()
+    }
+
+    /// Add the value, returning whether it was not already a member.
+    pub(crate) fn insert(&mut self, value: usize) -> bool {
+        if self.contains(value) {
+            return false;
+        }
+        self.sparse[value] = self.dense.len();
+        self.dense.push(value);
+        true
+    }
+
+    pub(crate) fn get(&self, idx: usize) -> usize {
+        self.dense[idx]
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.dense.clear();
+    }
+}
+
+/// Threads alive at one position, in priority order: the states they sit on, and a row of
+/// capture slots for each.
+#[derive(Debug, Clone)]
+pub(crate) struct ThreadList {
+    pub(crate) set: SparseSet,
+    slots: Vec<Option<usize>>,
+    slot_count: usize,
+}
+
+impl ThreadList {
+    fn new(size: usize, slot_count: usize) -> Self {
+        ThreadList {
+            set: SparseSet::new(size),
+            slots: vec![None; size * slot_count],
+            slot_count,
+        }
+    }
+
+    /// A list with no room, left in a cache while its list is in use.
+    pub(crate) fn empty() -> Self {
+        ThreadList::new(0, 0)
+    }
+
+    /// Empty the list and lay its slot table out for `slot_count` slots a thread, growing it
+    /// only when a larger machine or more slots are needed.
+    fn reset(&mut self, size: usize, slot_count: usize) {
+        self.set.resize(size);
+        self.slot_count = slot_count;
+        let table_size = self.set.capacity() * slot_count;
+        if table_size > self.slots.len() {
+            self.slots.resize(table_size, None);
+        }
+    }
+
+    pub(crate) fn slots(&self, idx: usize) -> &[Option<usize>] {
+        &self.slots[idx * self.slot_count..(idx + 1) * self.slot_count]
+    }
+
+    pub(crate) fn slots_mut(&mut self, idx: usize) -> &mut [Option<usize>] {
+        &mut self.slots[idx * self.slot_count..(idx + 1) * self.slot_count]
+    }
+}
+
+/// Work left while following zero-width transitions from a new thread.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Frame {
+    Explore(Next),
+    /// Record the position in a capture slot, then follow the transition.
+    Save(usize, Next),
+    /// Undo a capture once every path through it has been followed.
+    Restore(usize, Option<usize>),
+}
+
+/// Scratch space for searching a `GexMachine`, allocated once and reused across searches.
+///
+/// A search with a warm cache allocates nothing, except to evaluate lookarounds. A cache is
+/// only valid for the machine that created it, and for that machine's reversal.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    pub(crate) curr: ThreadList,
+    pub(crate) next: ThreadList,
+    pub(crate) stack: Vec<Frame>,
+    /// Slots of the thread being followed.
+    pub(crate) scratch: Slots,
+    /// Slots of the best match found so far, meaningful once `has_match` is set.
+    pub(crate) matched: Slots,
+    pub(crate) has_match: bool,
+    /// State lists for the scans that don't track captures.
+    pub(crate) scan_curr: StateList,
+    pub(crate) scan_next: StateList,
+}
+
+impl Cache {
+    pub fn new(machine: &GexMachine) -> Self {
+        let size = machine.size();
+        let slot_count = machine.slot_count();
+        Cache {
+            curr: ThreadList::new(size, slot_count),
+            next: ThreadList::new(size, slot_count),
+            stack: Vec::new(),
+            scratch: vec![None; slot_count],
+            matched: vec![None; slot_count],
+            has_match: false,
+            scan_curr: StateList::new(size),
+            scan_next: StateList::new(size),
+        }
+    }
+
+    /// Prepare for a search of a machine with `size` states, tracking `slot_count` slots.
+    pub(crate) fn reset(&mut self, size: usize, slot_count: usize) {
+        self.curr.reset(size, slot_count);
+        self.next.reset(size, slot_count);
+        self.stack.clear();
+        self.scratch.resize(slot_count, None);
+        self.matched.resize(slot_count, None);
+        self.has_match = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::compile_machine;
+    use crate::matcher::{Match, Matcher};
+    use crate::railroad::Ast;
+    use crate::tokenize::tokenize;
+
+    fn machine_for(pattern: &str) -> GexMachine {
+        compile_machine(
+            &Ast::from_tokens(tokenize(pattern).unwrap()).unwrap(),
+            pattern,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sparse_set() {
+        let mut set = SparseSet::new(8);
+        assert!(set.insert(5));
+        assert!(set.insert(2));
+        assert!(!set.insert(5));
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.index_of(2), Some(1));
+        assert_eq!((set.get(0), set.get(1)), (5, 2));
+        assert!(!set.contains(0));
+
+        set.clear();
+        assert!(set.is_empty());
+        assert!(!set.contains(5));
+        assert!(set.insert(2));
+        assert_eq!(set.index_of(2), Some(0));
+
+        set.resize(16);
+        assert!(set.is_empty());
+        assert!(set.insert(15));
+    }
+
+    #[test]
+    fn test_cache_reused_across_searches() {
+        let machine = machine_for(r"(\w+)@(\w+)");
+        let reverse = machine.reverse();
+        let mut cache = machine.create_cache();
+        let inputs = ["mail me@host now", "", "no address", "a@b c@d", "x@"];
+
+        for _ in 0..2 {
+            for input in inputs {
+                assert_eq!(
+                    machine.captures_at_cached(&mut cache, input, 0),
+                    machine.captures(input)
+                );
+                assert_eq!(
+                    machine.find_at_cached(&mut cache, input, 0),
+                    machine.find(input)
+                );
+                assert_eq!(
+                    machine.find_with_reverse_cached(&reverse, &mut cache, input, 0),
+                    machine.find(input)
+                );
+            }
+        }
+        assert_eq!(
+            machine.find_at_cached(&mut cache, "a@b c@d", 4),
+            Some(Match { start: 4, end: 7 })
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use crate::gex::machine::{Rule, State};
+use std::collections::{BTreeSet, HashMap};
+
+/// Unicode properties a rule may test, beyond code point ranges, as bits of a class key.
+const WORD: u8 = 0b001;
+const DIGIT: u8 = 0b010;
+const WHITESPACE: u8 = 0b100;
+const PROPERTY_COMBINATIONS: usize = 8;
+
+/// Most (state, class) pairs a dense table is built for by default, which keeps its offsets
+/// within 16 MiB.
+pub const DEFAULT_MAX_DENSE_PAIRS: usize = 1 << 22;
+
+/// Whether a rule tests the character it consumes, and so belongs in the class table.
+fn is_class_rule(rule: &Rule) -> bool {
+    matches!(
+        rule,
+        Rule::Range(..) | Rule::Not(_) | Rule::IsWord(_) | Rule::IsDigit(_) | Rule::IsWhitespace(_)
+    )
+}
+
+/// Partition of the alphabet into classes of characters that every rule of a machine treats
+/// alike.
+///
+/// Code points are first cut into segments at every boundary of a `Range` or `Not` rule, so
+/// those rules are constant over a segment. A segment together with the Unicode properties the
+/// machine tests forms a raw class, and raw classes on which every rule agrees are merged.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CharClasses {
+    /// First code point of each segment, sorted.
+    segment_starts: Vec<u32>,
+    /// Properties the machine tests, so the others need not be computed.
+    properties: u8,
+    /// Class of each raw class, indexed by segment and then property bits.
+    raw_classes: Vec<u16>,
+    /// Class of each ASCII character, skipping the segment search.
+    ascii: Vec<u16>,
+    /// A raw class, as a segment start and property bits, standing for each class.
+    representatives: Vec<(u32, u8)>,
+}
+
+impl CharClasses {
+    pub fn new<'a>(rules: impl Iterator<Item = &'a Rule>) -> Self {
+        let mut boundaries = BTreeSet::from([0]);
+        let mut properties = 0;
+        let mut distinct: Vec<Rule> = Vec::new();
+
+        for rule in rules.filter(|rule| is_class_rule(rule)) {
+            match rule {
+                Rule::Range(start, end, _) => boundaries.extend([*start, end + 1]),
+                Rule::Not(value) => boundaries.extend([*value, value + 1]),
+                Rule::IsWord(_) => properties |= WORD,
+                Rule::IsDigit(_) => properties |= DIGIT,
+                Rule::IsWhitespace(_) => properties |= WHITESPACE,
+                _ => {}
+            }
+            if !distinct.contains(rule) {
+                distinct.push(*rule);
+            }
+        }
+        let segment_starts: Vec<u32> = boundaries
+            .into_iter()
+            .filter(|&start| start <= char::MAX as u32)
+            .collect();
+
+        // Raw classes with the same verdict from every rule share a class
+        let mut signatures: HashMap<Vec<bool>, u16> = HashMap::new();
+        let mut representatives = Vec::new();
+        let mut raw_classes = Vec::with_capacity(segment_starts.len() * PROPERTY_COMBINATIONS);
+        for &segment_start in segment_starts.iter() {
+            for bits in 0..PROPERTY_COMBINATIONS as u8 {
+                let bits = bits & properties;
+                let signature: Vec<bool> = distinct
+                    .iter()
+                    .map(|rule| rule_holds(rule, segment_start, bits))
+                    .collect();
+                let next_class = signatures.len() as u16;
+                let class = *signatures.entry(signature).or_insert_with(|| {
+                    representatives.push((segment_start, bits));
+                    next_class
+                });
+                raw_classes.push(class);
+            }
+        }
+
+        let mut classes = CharClasses {
+            segment_starts,
+            properties,
+            raw_classes,
+            ascii: Vec::new(),
+            representatives,
+        };
+        classes.ascii = (0..128u8)
+            .map(|byte| classes.search_class(byte as char))
+            .collect();
+        classes
+    }
+
+    pub fn class_count(&self) -> usize {
+        self.representatives.len()
+    }
+
+    /// The class the character belongs to.
+    pub fn class_of(&self, input_char: char) -> usize {
+        match self.ascii.get(input_char as usize) {
+            Some(&class) => class as usize,
+            None => self.search_class(input_char) as usize,
+        }
+    }
+
+    fn search_class(&self, input_char: char) -> u16 {
+        let code_point = input_char as u32;
+        let segment = self
+            .segment_starts
+            .partition_point(|&start| start <= code_point)
+            - 1;
+        let mut bits = 0;
+        if self.properties & WORD != 0 && input_char.is_alphanumeric() {
+            bits |= WORD;
+        }
+        if self.properties & DIGIT != 0 && input_char.is_numeric() {
+            bits |= DIGIT;
+        }
+        if self.properties & WHITESPACE != 0 && input_char.is_whitespace() {
+            bits |= WHITESPACE;
+        }
+        self.raw_classes[segment * PROPERTY_COMBINATIONS + bits as usize]
+    }
+
+    /// Whether the rule accepts the characters of the class.
+    pub fn accepts(&self, rule: &Rule, class: usize) -> bool {
+        let (segment_start, bits) = self.representatives[class];
+        rule_holds(rule, segment_start, bits)
+    }
+}
+
+/// Verdict of a class rule on the characters of a segment that have the given properties.
+fn rule_holds(rule: &Rule, segment_start: u32, bits: u8) -> bool {
+    match rule {
+        Rule::Range(start, end, positive) => {
+            (*start <= segment_start && segment_start <= *end) ^ !positive
+        }
+        Rule::Not(value) => segment_start != *value,
+        Rule::IsWord(positive) => (bits & WORD != 0) ^ !positive,
+        Rule::IsDigit(positive) => (bits & DIGIT != 0) ^ !positive,
+        Rule::IsWhitespace(positive) => (bits & WHITESPACE != 0) ^ !positive,
+        _ => false,
+    }
+}
+
+/// Transitions of every state, tabulated by character class.
+///
+/// Each (state, class) pair lists the indices of the state's transitions that consume a
+/// character of the class, in priority order, so a step is a class lookup and a slice.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DenseTable {
+    classes: CharClasses,
+    /// Where each (state, class) pair's transitions start in `transitions`, with the end of the
+    /// last pair at the end.
+    offsets: Vec<u32>,
+    transitions: Vec<u16>,
+}
+
+impl DenseTable {
+    /// The table for the states, or None when it would hold more than `max_pairs` (state,
+    /// class) pairs, in which case searches test each character against the rules instead.
+    pub fn new(states: &[State], max_pairs: usize) -> Option<Self> {
+        let classes = CharClasses::new(
+            states
+                .iter()
+                .flat_map(|state| state.transitions.iter().map(|(rule, _)| rule)),
+        );
+        let class_count = classes.class_count();
+        let pairs = states
+            .len()
+            .checked_mul(class_count)
+            .filter(|&pairs| pairs <= max_pairs)?;
+        let mut offsets = Vec::with_capacity(pairs + 1);
+        let mut transitions = Vec::new();
+
+        for state in states.iter() {
+            for class in 0..class_count {
+                offsets.push(transitions.len() as u32);
+                let accepting = state
+                    .transitions
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (rule, _))| is_class_rule(rule));
+                // A single falsy rule rejects the whole short-circuit state
+                if state.short_circuit()
+                    && !accepting
+                        .clone()
+                        .all(|(_, (rule, _))| classes.accepts(rule, class))
+                {
+                    continue;
+                }
+                transitions.extend(
+                    accepting
+                        .filter(|(_, (rule, _))| classes.accepts(rule, class))
+                        .map(|(idx, _)| idx as u16),
+                );
+            }
+        }
+        offsets.push(transitions.len() as u32);
+
+        Some(DenseTable {
+            classes,
+            offsets,
+            transitions,
+        })
+    }
+
+    pub fn class_of(&self, input_char: char) -> usize {
+        self.classes.class_of(input_char)
+    }
+
+    pub fn class_count(&self) -> usize {
+        self.classes.class_count()
+    }
+
+    /// Indices of the state's transitions that consume a character of the class.
+    pub fn transitions(&self, state_label: usize, class: usize) -> &[u16] {
+        let pair = state_label * self.class_count() + class;
+        &self.transitions[self.offsets[pair] as usize..self.offsets[pair + 1] as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::compile_machine;
+    use crate::engines::backtrack::Backtracker;
+    use crate::gex::GexMachine;
+    use crate::matcher::Matcher;
+    use crate::railroad::Ast;
+    use crate::tokenize::tokenize;
+
+    #[test]
+    fn test_classes_merge_alike_characters() {
+        let rules = [
+            Rule::Range('a' as u32, 'z' as u32, true),
+            Rule::Range('0' as u32, '9' as u32, true),
+            Rule::Not('q' as u32),
+        ];
+        let classes = CharClasses::new(rules.iter());
+
+        assert_eq!(classes.class_of('b'), classes.class_of('y'));
+        assert_ne!(classes.class_of('b'), classes.class_of('q'));
+        assert_ne!(classes.class_of('b'), classes.class_of('5'));
+        // Everything outside the ranges, other than 'q', is alike
+        assert_eq!(classes.class_of('-'), classes.class_of('é'));
+        assert_eq!(classes.class_of('A'), classes.class_of('\u{10ffff}'));
+        assert_eq!(classes.class_count(), 4);
+    }
+
+    #[test]
+    fn test_classes_agree_with_rules() {
+        let rules = [
+            Rule::IsWord(true),
+            Rule::IsDigit(false),
+            Rule::IsWhitespace(true),
+            Rule::Range('a' as u32, 'f' as u32, false),
+            Rule::Range(0x400, 0x4ff, true),
+            Rule::Not('_' as u32),
+        ];
+        let classes = CharClasses::new(rules.iter());
+        let samples = "azAF09_ \t\n-éЖ٣\u{3000}\u{10ffff}".chars();
+
+        for input_char in samples {
+            for rule in rules.iter() {
+                assert_eq!(
+                    classes.accepts(rule, classes.class_of(input_char)),
+                    GexMachine::evaluate_rule(rule, &input_char),
+                    "{:?} on {:?}",
+                    rule,
+                    input_char
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_dense_table_agrees_with_rules() {
+        let patterns = [
+            r"[^a-c]\w+",
+            r"(\d+|[x-z])\s*[^\d\s]",
+            r"\W\D\S.",
+            r"[a-fA-F0-9]+h?",
+            r"(?<=[^a])b+",
+            r"[a-f]{2,3}\d{0,}z{1}",
+        ];
+        let inputs = [
+            "",
+            "abc dd",
+            "12 x  y3",
+            "-+é Ж٣\u{3000}ff",
+            "a0fh zz9",
+            "abbcbb",
+        ];
+
+        for pattern in patterns {
+            let machine = compile_machine(
+                &Ast::from_tokens(tokenize(pattern).unwrap()).unwrap(),
+                pattern,
+            )
+            .unwrap();
+            assert!(machine.dense_table().is_some());
+            let mut by_rules = machine.clone();
+            by_rules.dense = None;
+
+            for input in inputs {
+                assert_eq!(machine.captures(input), by_rules.captures(input));
+                assert_eq!(
+                    machine.find_with_reverse(&machine.reverse(), input, 0),
+                    by_rules.find_with_reverse(&by_rules.reverse(), input, 0)
+                );
+                assert_eq!(
+                    Backtracker::new(&machine).find(input),
+                    Backtracker::new(&by_rules).find(input),
+                    "{} on {:?}",
+                    pattern,
+                    input
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use crate::engines::backtrack::Backtracker;
+use crate::gex::export::rule_label;
+use crate::gex::machine::{GexMachine, Rule};
+use crate::gex::Next;
+use crate::matcher::Captures;
+use std::fmt;
+
+/// Receives the steps of the machine's own simulation as it searches, for explaining or
+/// debugging a match.
+///
+/// Every method does nothing by default, so an observer only implements the events it cares
+/// about. Positions are byte offsets into the input. States are entered, and their zero-width
+/// rules tested, as threads reach them; consuming rules are tested against the character at
+/// the position of the threads sitting on their states.
+///
+/// Backreferences, atomic groups and calls need the backtracker, so the simulation never
+/// follows them; their rules are reported as failing. `GexMachine::explain` doesn't trace
+/// machines with any of them for that reason.
+pub trait Observer {
+    /// Whether the simulation reports to this observer at all; when `false`, the work of
+    /// reporting is compiled out.
+    const ACTIVE: bool = true;
+
+    /// A new, lowest priority, thread starts at the position.
+    fn thread_started(&mut self, _position: usize) {}
+
+    fn state_entered(&mut self, _state_label: usize, _position: usize) {}
+
+    /// A rule of the state was tested at the position, against the character there when it
+    /// consumes one.
+    fn rule_tested(&mut self, _state_label: usize, _rule: &Rule, _position: usize, _held: bool) {}
+
+    /// A thread on the state consumed the character at the position, moving to `next`.
+    fn character_consumed(
+        &mut self,
+        _state_label: usize,
+        _character: char,
+        _position: usize,
+        _next: Next,
+    ) {
+    }
+
+    fn capture_opened(&mut self, _group_number: usize, _position: usize) {}
+
+    fn capture_closed(&mut self, _group_number: usize, _position: usize) {}
+
+    /// A thread reached the Accept, matching up to the position.
+    fn accept_reached(&mut self, _position: usize) {}
+}
+
+/// The observer of searches no one is watching.
+impl Observer for () {
+    const ACTIVE: bool = false;
+}
+
+/// Observer writing each step of a search as a line of plain English.
+///
+/// Lines are grouped under the position where threads start and consume a character, along
+/// with the states those threads go on to enter.
+#[derive(Debug, Clone)]
+pub struct Tracer<'a> {
+    machine: &'a GexMachine,
+    input: &'a str,
+    lines: Vec<String>,
+    /// Position of the threads being followed, so steps are grouped under it.
+    position: Option<usize>,
+}
+
+impl<'a> Tracer<'a> {
+    pub fn new(machine: &'a GexMachine, input: &'a str) -> Self {
+        Tracer {
+            machine,
+            input,
+            lines: Vec::new(),
+            position: None,
+        }
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Record a step of the threads at the position.
+    fn step(&mut self, position: usize, step: String) {
+        if self.position != Some(position) {
+            self.position = Some(position);
+            let heading = match self.input[position..].chars().next() {
+                Some(character) => format!("at {}, before {:?}:", position, character),
+                None => format!("at {}, end of input:", position),
+            };
+            self.lines.push(heading);
+        }
+        self.line(step);
+    }
+
+    /// Record a step following on from the last one, such as entering the states after a
+    /// character is consumed.
+    fn line(&mut self, step: String) {
+        self.lines.push(format!("  {}", step));
+    }
+}
+
+impl Observer for Tracer<'_> {
+    fn thread_started(&mut self, position: usize) {
+        self.step(position, "start a new thread in state 0".to_string());
+    }
+
+    fn state_entered(&mut self, state_label: usize, position: usize) {
+        match self.position {
+            None => self.step(position, format!("enter state {}", state_label)),
+            Some(_) => self.line(format!("enter state {}", state_label)),
+        }
+    }
+
+    fn rule_tested(&mut self, state_label: usize, rule: &Rule, position: usize, held: bool) {
+        let label = rule_label(self.machine, rule, "");
+        let verdict = if held { "holds" } else { "fails" };
+        let step = match (rule.is_zero_width(), self.input[position..].chars().next()) {
+            (false, Some(character)) => format!(
+                "state {}: {} {} for {:?}",
+                state_label, label, verdict, character
+            ),
+            _ => format!("state {}: {} {}", state_label, label, verdict),
+        };
+        self.step(position, step);
+    }
+
+    fn character_consumed(
+        &mut self,
+        state_label: usize,
+        character: char,
+        position: usize,
+        next: Next,
+    ) {
+        let to = match next {
+            Next::Target(target) => format!("state {}", target),
+            Next::Accept => "the accept".to_string(),
+        };
+        self.step(
+            position,
+            format!(
+                "state {}: consume {:?}, on to {}",
+                state_label, character, to
+            ),
+        );
+    }
+
+    fn capture_opened(&mut self, group_number: usize, position: usize) {
+        self.line(format!("open group {} at {}", group_number, position));
+    }
+
+    fn capture_closed(&mut self, group_number: usize, position: usize) {
+        self.line(format!("close group {} at {}", group_number, position));
+    }
+
+    fn accept_reached(&mut self, position: usize) {
+        self.line(format!("reach the accept at {}", position));
+    }
+}
+
+impl fmt::Display for Tracer<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.lines.iter() {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl GexMachine {
+    /// Step-by-step account of the machine searching the input, ending with the match it found
+    /// and its groups, or why there was none.
+    ///
+    /// Machines that need backtracking can't be stepped through this way, so for those the
+    /// account says so, and gives only the backtracker's verdict.
+    pub fn explain(&self, input: &str) -> String {
+        if self.needs_backtracking() {
+            let mut explanation = String::from(
+                "cannot explain step by step: backreferences, atomic groups and calls are \
+                 searched by backtracking\n",
+            );
+            match Backtracker::new(self).try_captures_at(input, 0) {
+                Ok(Some(captures)) => describe(&captures, input, &mut explanation),
+                Ok(None) => explanation.push_str("no match: no path reached the accept\n"),
+                Err(err) => explanation.push_str(&format!("no answer: {}\n", err)),
+            }
+            return explanation;
+        }
+
+        let mut tracer = Tracer::new(self, input);
+        let captures = self.captures_at_observed(input, 0, &mut tracer);
+        let mut explanation = tracer.to_string();
+
+        match captures {
+            Some(captures) => describe(&captures, input, &mut explanation),
+            None if tracer.lines().is_empty() => {
+                explanation.push_str("no match: the literal every match needs is missing\n")
+            }
+            None => explanation.push_str("no match: no thread reached the accept\n"),
+        }
+        explanation
+    }
+}
+
+/// Append a line for the match, and one for each of its groups.
+fn describe(captures: &Captures, input: &str, explanation: &mut String) {
+    for (group_number, span) in captures.iter().enumerate() {
+        let name = match group_number {
+            0 => "match".to_string(),
+            _ => format!("group {}", group_number),
+        };
+        let line = match span {
+            Some(span) => format!(
+                "{} {}..{}: {:?}\n",
+                name,
+                span.start,
+                span.end,
+                span.substr(input)
+            ),
+            None => format!("{} took no part\n", name),
+        };
+        explanation.push_str(&line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::{compile_nfa, CompileOptions};
+    use crate::matcher::Matcher;
+
+    fn machine(pattern: &str) -> GexMachine {
+        compile_nfa(pattern, &CompileOptions::default()).unwrap()
+    }
+
+    /// Observer counting the events it gets.
+    #[derive(Default)]
+    struct Counts {
+        started: usize,
+        consumed: Vec<char>,
+        opened: Vec<(usize, usize)>,
+        closed: Vec<(usize, usize)>,
+        accepted: Vec<usize>,
+    }
+
+    impl Observer for Counts {
+        fn thread_started(&mut self, _position: usize) {
+            self.started += 1;
+        }
+
+        fn character_consumed(
+            &mut self,
+            _state_label: usize,
+            character: char,
+            _position: usize,
+            _next: Next,
+        ) {
+            self.consumed.push(character);
+        }
+
+        fn capture_opened(&mut self, group_number: usize, position: usize) {
+            self.opened.push((group_number, position));
+        }
+
+        fn capture_closed(&mut self, group_number: usize, position: usize) {
+            self.closed.push((group_number, position));
+        }
+
+        fn accept_reached(&mut self, position: usize) {
+            self.accepted.push(position);
+        }
+    }
+
+    #[test]
+    fn test_observed_events() {
+        let machine = machine(r"x(a+)");
+        let mut counts = Counts::default();
+
+        let captures = machine.captures_at_observed("zxaa", 0, &mut counts);
+        assert_eq!(captures, machine.captures("zxaa"));
+        assert_eq!(counts.consumed, vec!['x', 'a', 'a']);
+        assert_eq!(counts.opened, vec![(1, 2)]);
+        assert_eq!(counts.closed, vec![(1, 3), (1, 4)]);
+        assert_eq!(counts.accepted, vec![3, 4]);
+        assert!(counts.started >= 2);
+    }
+
+    #[test]
+    fn test_explain_match() {
+        let explanation = machine(r"(b+)c").explain("abbc");
+        let lines: Vec<&str> = explanation.lines().collect();
+
+        // The prefilter skips straight to the first 'b'
+        assert_eq!(
+            lines[..4],
+            [
+                "at 1, before 'b':",
+                "  start a new thread in state 0",
+                "  enter state 0",
+                "  open group 1 at 1",
+            ]
+        );
+        let at = |heading: &str| lines.iter().position(|line| *line == heading).unwrap();
+        let (at_2, at_3) = (at("at 2, before 'b':"), at("at 3, before 'c':"));
+        assert!(at_2 < at_3);
+        assert!(lines[at_2..at_3].contains(&"  close group 1 at 3"));
+        assert!(lines[at_3..].contains(&"  state 3: b fails for 'c'"));
+        assert!(lines[at_3..].contains(&"  state 7: c holds for 'c'"));
+        assert!(lines[at_3..].contains(&"  state 7: consume 'c', on to state 8"));
+        assert!(lines[at_3..].contains(&"  reach the accept at 4"));
+        assert!(explanation.ends_with("match 1..4: \"bbc\"\ngroup 1 1..3: \"bb\"\n"));
+    }
+
+    #[test]
+    fn test_explain_no_match() {
+        let explanation = machine(r"a\bc").explain("ac");
+
+        assert!(explanation.contains(r"\b fails"));
+        assert!(explanation.ends_with("no match: no thread reached the accept\n"));
+        assert_eq!(
+            machine("needle").explain("haystack"),
+            "no match: the literal every match needs is missing\n"
+        );
+    }
+
+    #[test]
+    fn test_explain_backtracking() {
+        let cannot = "cannot explain step by step: backreferences, atomic groups and calls are \
+                      searched by backtracking\n";
+
+        // The simulation would let `a+` give back the last 'a'
+        assert_eq!(
+            machine(r"(?>a+)a").explain("aa"),
+            format!("{}no match: no path reached the accept\n", cannot)
+        );
+        // and would never follow the backreference
+        assert_eq!(
+            machine(r"(a)\1").explain("aa"),
+            format!("{}match 0..2: \"aa\"\ngroup 1 0..1: \"a\"\n", cannot)
+        );
+    }
+}
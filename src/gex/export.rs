@@ -0,0 +1,283 @@
+use crate::gex::machine::{GexMachine, LookKind, Next, Rule};
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Text formats a machine can be drawn in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz DOT, for `dot -Tsvg`.
+    Dot,
+    /// Mermaid flowchart, for Markdown that renders it.
+    Mermaid,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "dot" => Ok(GraphFormat::Dot),
+            "mermaid" => Ok(GraphFormat::Mermaid),
+            _ => Err(format!(
+                "Unknown graph format '{}' (expected dot or mermaid)",
+                name
+            )),
+        }
+    }
+}
+
+impl GexMachine {
+    /// Draw the machine as a graph, for debugging patterns.
+    ///
+    /// Each state is a node labelled with its index, and each transition an edge labelled with
+    /// its rule: `ε` for a null transition, classes like `[a-z]` for ranges, and `(n` and `n)`
+    /// where group `n` opens and closes, drawn apart from the others. Transitions to the Accept
+    /// lead to an accept node, and short-circuit states, where one failing rule fails the
+    /// whole state, get a shape of their own. Each lookaround's machine is drawn as a subgraph
+    /// named after the rules that test it.
+    pub fn to_graph(&self, format: GraphFormat) -> String {
+        let mut graph = Graph {
+            format,
+            out: String::new(),
+        };
+        match format {
+            GraphFormat::Dot => {
+                graph.line(0, "digraph gex {");
+                graph.line(1, "rankdir=LR;");
+                graph.line(1, "node [shape=circle];");
+            }
+            GraphFormat::Mermaid => graph.line(0, "flowchart LR"),
+        }
+        graph.machine(self, "", 1);
+        if format == GraphFormat::Dot {
+            graph.line(0, "}");
+        }
+        graph.out
+    }
+}
+
+struct Graph {
+    format: GraphFormat,
+    out: String,
+}
+
+impl Graph {
+    fn line(&mut self, depth: usize, line: &str) {
+        let _ = writeln!(self.out, "{:indent$}{}", "", line, indent = depth * 4);
+    }
+
+    /// Draw the machine's states and transitions, with node names starting with `prefix`, then
+    /// its lookarounds.
+    fn machine(&mut self, machine: &GexMachine, prefix: &str, depth: usize) {
+        let (start, accept) = (format!("{}start", prefix), format!("{}accept", prefix));
+        let first = format!("{}s0", prefix);
+        match self.format {
+            GraphFormat::Dot => {
+                self.line(depth, &format!("{} [shape=point];", start));
+                self.line(depth, &format!("{} -> {};", start, first));
+                self.line(
+                    depth,
+                    &format!("{} [label=\"accept\", shape=doublecircle];", accept),
+                );
+            }
+            GraphFormat::Mermaid => {
+                self.line(depth, &format!("{}([start]) --> {}", start, first));
+                self.line(depth, &format!("{}(((accept)))", accept));
+            }
+        }
+
+        for (state_label, state) in machine.states.iter().enumerate() {
+            let node = format!("{}s{}", prefix, state_label);
+            let line = match (self.format, state.short_circuit()) {
+                (GraphFormat::Dot, false) => format!("{} [label=\"{}\"];", node, state_label),
+                (GraphFormat::Dot, true) => {
+                    format!("{} [label=\"{}\", shape=octagon];", node, state_label)
+                }
+                (GraphFormat::Mermaid, false) => format!("{}((\"{}\"))", node, state_label),
+                (GraphFormat::Mermaid, true) => format!("{}{{{{\"{}\"}}}}", node, state_label),
+            };
+            self.line(depth, &line);
+        }
+
+        for (state_label, state) in machine.states.iter().enumerate() {
+            let from = format!("{}s{}", prefix, state_label);
+            for (rule, next) in state.transitions.iter() {
+                let to = match next {
+                    Next::Target(target) => format!("{}s{}", prefix, target),
+                    Next::Accept => accept.clone(),
+                };
+                let label = rule_label(machine, rule, prefix);
+                let capture = matches!(rule, Rule::Save(_));
+                let line = match (self.format, capture) {
+                    (GraphFormat::Dot, false) => {
+                        format!("{} -> {} [label=\"{}\"];", from, to, dot_escape(&label))
+                    }
+                    (GraphFormat::Dot, true) => format!(
+                        "{} -> {} [label=\"{}\", color=blue, fontcolor=blue];",
+                        from,
+                        to,
+                        dot_escape(&label)
+                    ),
+                    (GraphFormat::Mermaid, false) => {
+                        format!("{} -->|\"{}\"| {}", from, mermaid_escape(&label), to)
+                    }
+                    (GraphFormat::Mermaid, true) => {
+                        format!("{} -.->|\"{}\"| {}", from, mermaid_escape(&label), to)
+                    }
+                };
+                self.line(depth, &line);
+            }
+        }
+
+        for (look_idx, look) in machine.looks.iter().enumerate() {
+            let name = format!("{}l{}", prefix, look_idx);
+            let title = match look.kind {
+                LookKind::Ahead(_) => format!("lookahead {}", name),
+                LookKind::Behind(_) => format!("lookbehind {} (reversed)", name),
+            };
+            match self.format {
+                GraphFormat::Dot => {
+                    self.line(depth, &format!("subgraph cluster_{} {{", name));
+                    self.line(depth + 1, &format!("label=\"{}\";", title));
+                }
+                GraphFormat::Mermaid => {
+                    self.line(depth, &format!("subgraph {} [\"{}\"]", name, title))
+                }
+            }
+            self.machine(&look.machine, &format!("{}_", name), depth + 1);
+            match self.format {
+                GraphFormat::Dot => self.line(depth, "}"),
+                GraphFormat::Mermaid => self.line(depth, "end"),
+            }
+        }
+    }
+}
+
+/// The rule in pattern syntax, or as near as it gets.
+pub(super) fn rule_label(machine: &GexMachine, rule: &Rule, prefix: &str) -> String {
+    match *rule {
+        Rule::Range(start, end, true) if start == end => class_char(start),
+        Rule::Range(start, end, false) if start == end => format!("[^{}]", class_char(start)),
+        Rule::Range(start, end, positive) => format!(
+            "[{}{}-{}]",
+            if positive { "" } else { "^" },
+            class_char(start),
+            class_char(end)
+        ),
+        Rule::Not(value) => format!("[^{}]", class_char(value)),
+        Rule::IsWord(positive) => if positive { r"\w" } else { r"\W" }.to_string(),
+        Rule::IsDigit(positive) => if positive { r"\d" } else { r"\D" }.to_string(),
+        Rule::IsWhitespace(positive) => if positive { r"\s" } else { r"\S" }.to_string(),
+        Rule::WordBoundary(positive) => if positive { r"\b" } else { r"\B" }.to_string(),
+        Rule::Backreference(group_number) => format!(r"\{}", group_number),
+        Rule::Look(look_idx) => {
+            let opener = match machine.looks.get(look_idx).map(|look| look.kind) {
+                Some(LookKind::Ahead(true)) => "(?=",
+                Some(LookKind::Ahead(false)) => "(?!",
+                Some(LookKind::Behind(true)) => "(?<=",
+                Some(LookKind::Behind(false)) => "(?<!",
+                None => "(?",
+            };
+            format!("{}{}l{})", opener, prefix, look_idx)
+        }
+        Rule::AtomicOpen => "(?>".to_string(),
+        Rule::AtomicClose => "(?>)".to_string(),
+        Rule::Call(0) => "(?R)".to_string(),
+        Rule::Call(group_number) => format!("(?{})", group_number),
+        Rule::Save(slot) if slot % 2 == 0 => format!("({}", slot / 2),
+        Rule::Save(slot) => format!("{})", slot / 2),
+        Rule::Null => "ε".to_string(),
+    }
+}
+
+/// A code point as written in a character class.
+fn class_char(code_point: u32) -> String {
+    match char::from_u32(code_point) {
+        Some(character @ ('\\' | ']' | '[' | '-' | '^')) => format!("\\{}", character),
+        Some(' ') => r"\x20".to_string(),
+        Some(character) if character.is_control() || character.is_whitespace() => {
+            character.escape_default().to_string()
+        }
+        Some(character) => character.to_string(),
+        None => format!("\\u{{{:x}}}", code_point),
+    }
+}
+
+fn dot_escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn mermaid_escape(label: &str) -> String {
+    label.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::compile;
+
+    fn graph(pattern: &str, format: GraphFormat) -> String {
+        compile(pattern)
+            .unwrap()
+            .machine()
+            .unwrap()
+            .to_graph(format)
+    }
+
+    #[test]
+    fn test_rule_labels() {
+        let machine = GexMachine::default();
+        let labels: Vec<String> = [
+            Rule::Range('a' as u32, 'a' as u32, true),
+            Rule::Range('a' as u32, 'z' as u32, false),
+            Rule::Range('-' as u32, ']' as u32, true),
+            Rule::Not('\n' as u32),
+            Rule::IsDigit(false),
+            Rule::Save(2),
+            Rule::Save(3),
+            Rule::Call(0),
+            Rule::Null,
+        ]
+        .iter()
+        .map(|rule| rule_label(&machine, rule, ""))
+        .collect();
+
+        assert_eq!(
+            labels,
+            vec!["a", "[^a-z]", r"[\--\]]", r"[^\n]", r"\D", "(1", "1)", "(?R)", "ε"]
+        );
+    }
+
+    #[test]
+    fn test_dot() {
+        let dot = graph(r#"(a|[^0-9"])b"#, GraphFormat::Dot);
+
+        assert!(dot.starts_with("digraph gex {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("start -> s0;"));
+        assert!(dot.contains("accept [label=\"accept\", shape=doublecircle];"));
+        assert!(dot.contains("[label=\"(1\", color=blue, fontcolor=blue];"));
+        assert!(dot.contains("[label=\"1)\", color=blue, fontcolor=blue];"));
+        assert!(dot.contains("[label=\"[^0-9]\"];"));
+        assert!(dot.contains("[label=\"[^\\\"]\"];"));
+        assert!(dot.contains("[label=\"ε\"];"));
+        assert!(dot.contains("-> accept [label=\"ε\"];"));
+        assert!(dot.contains("shape=octagon"));
+    }
+
+    #[test]
+    fn test_mermaid() {
+        let mermaid = graph(r#"x(?<!"y)\b"#, GraphFormat::Mermaid);
+
+        assert!(mermaid.starts_with("flowchart LR\n"));
+        assert!(mermaid.contains("    start([start]) --> s0\n"));
+        assert!(mermaid.contains("    accept(((accept)))\n"));
+        assert!(mermaid.contains("    s0((\"0\"))\n"));
+        assert!(mermaid.contains("-->|\"(?<!l0)\"|"));
+        assert!(mermaid.contains("-->|\"\\b\"|"));
+        assert!(mermaid.contains("    subgraph l0 [\"lookbehind l0 (reversed)\"]\n"));
+        assert!(mermaid.contains("        l0_start([start]) --> l0_s0\n"));
+        assert!(mermaid.contains("-->|\"#quot;\"|"));
+        assert!(mermaid.contains("    end\n"));
+    }
+}
@@ -1,49 +1,37 @@
-use crate::gex::machine::{GexMachine, Next, Rule};
-use crate::matcher::{Match, Matcher};
-use std::collections::{HashMap, HashSet};
-use std::iter::once;
-
-#[derive(Debug, PartialEq, Eq, Clone)]
-struct MatchCandidate {
-    start: usize,
-    end: Option<usize>,
+use crate::gex::cache::{Cache, Frame, ThreadList};
+use crate::gex::explain::Observer;
+use crate::gex::machine::{GexMachine, LookKind, Next, Rule};
+use crate::interrupt::{Interrupt, Outcome};
+use crate::matcher::{Captures, Match, MatchKind, Matcher};
+use std::cmp::Ordering;
+use std::mem::swap;
+
+/// Positions stepped over between checks of the interrupt.
+const INTERRUPT_CHECK_POSITIONS: usize = 4096;
+
+/// Capture positions carried by a thread: start and end of every group in turn, with the whole
+/// match as group 0.
+pub(crate) type Slots = Vec<Option<usize>>;
+
+fn compare_starts(candidate: Option<usize>, incumbent: Option<usize>) -> Ordering {
+    // Groups that never started lose to any that did
+    incumbent
+        .unwrap_or(usize::MAX)
+        .cmp(&candidate.unwrap_or(usize::MAX))
 }
 
-impl MatchCandidate {
-    fn new() -> Self {
-        MatchCandidate::with_start(0)
-    }
-
-    fn with_start(start: usize) -> Self {
-        MatchCandidate { start, end: None }
-    }
-}
-
-impl Match {
-    fn from_candidate(candidate: &MatchCandidate) -> Self {
-        Match {
-            start: candidate.start,
-            end: candidate
-                .end
-                .expect("End required for conversion to a match"),
-        }
-    }
-}
-
-struct GexMatcher {
-    captures: Option<HashMap<u16, MatchCandidate>>,
-}
-
-impl GexMatcher {
-    fn unwrap_captures(&self) -> HashMap<u16, Match> {
-        let mut actual_captures: HashMap<u16, Match> = HashMap::new();
-        for (&idx, candidate) in self.captures.as_ref().unwrap().iter() {
-            if let Some(_) = candidate.end {
-                actual_captures.insert(idx, Match::from_candidate(&candidate));
-            }
+/// Whether `candidate` beats `incumbent` under POSIX rules: the earliest start, then the longest
+/// match, then for every group in order of its opening parenthesis, the earliest start and then
+/// the longest span.
+pub(crate) fn posix_prefers(candidate: &[Option<usize>], incumbent: &[Option<usize>]) -> bool {
+    for (candidate_pair, incumbent_pair) in candidate.chunks(2).zip(incumbent.chunks(2)) {
+        let order = compare_starts(candidate_pair[0], incumbent_pair[0])
+            .then_with(|| candidate_pair[1].cmp(&incumbent_pair[1]));
+        if order != Ordering::Equal {
+            return order == Ordering::Greater;
         }
-        actual_captures
     }
+    false
 }
 
 /// Matcher-trait-specific impl for GexMachine
@@ -52,7 +40,7 @@ impl GexMachine {
     ///
     /// Null transition rules will always evaluate as falsy since they need to be collapsed to next
     /// states without consuming a character, and this is handled separately.
-    fn evaluate_rule(rule: &Rule, given: &char) -> bool {
+    pub(crate) fn evaluate_rule(rule: &Rule, given: &char) -> bool {
         match rule {
             Rule::Range(start, end, positive) => {
                 (*start <= *given as u32 && *given as u32 <= *end) ^ !positive
@@ -61,232 +49,439 @@ impl GexMachine {
             Rule::IsWord(positive) => given.is_alphanumeric() ^ !positive,
             Rule::IsDigit(positive) => given.is_numeric() ^ !positive,
             Rule::IsWhitespace(positive) => given.is_whitespace() ^ !positive,
+            // Zero-width rules are followed by `evaluate_assertion` instead
+            Rule::WordBoundary(_) | Rule::Look(_) => false,
+            Rule::AtomicOpen | Rule::AtomicClose => false,
+            Rule::Call(_) => false,
+            Rule::Backreference(_) => false,
+            Rule::Save(_) => false,
             Rule::Null => false, // skip Null bc it will collapse from the previous state
         }
     }
 
-    /// Follows Null (Epsilon) transitions until the current states are all non-Null transitions.
+    /// Class of the character in the machine's dense table, when it has one.
+    pub(crate) fn class_of(&self, input_char: char) -> Option<usize> {
+        self.dense.as_ref().map(|dense| dense.class_of(input_char))
+    }
+
+    /// Targets of the state's transitions that consume `input_char`, in priority order.
     ///
-    /// Prevents consumption of input on Null transitions.
-    fn collapse_null_transitions(
+    /// With the character's `class`, the targets come straight from the dense table; otherwise
+    /// every rule of the state is tested against the character.
+    pub(crate) fn consuming_targets(
         &self,
-        curr_states: HashSet<usize>,
-        position: usize,
-        matcher: &mut GexMatcher,
-    ) -> (HashSet<usize>, bool) {
-        // Keep track of visited states to prevent uncontrolled recursive collapse.
-        let mut visited = HashSet::<usize>::new();
-        let mut collapsed_states = HashSet::<usize>::new();
-        let mut states = curr_states.into_iter().collect::<Vec<usize>>();
-
-        let mut accept = false;
-
-        while let Some(last_state_label) = states.pop() {
-            collapsed_states.insert(last_state_label);
-            if visited.contains(&last_state_label) {
-                continue;
-            }
-            visited.insert(last_state_label);
-
-            if let Some(state) = self.states.get(last_state_label) {
-                self.evaluate_state_flags(matcher, last_state_label, position);
-                for (rule, transition) in state.transitions.iter() {
-                    if let Rule::Null = rule {
-                        match transition {
-                            Next::Target(next) => {
-                                collapsed_states.insert(*next);
-                                // This state might collapse further
-                                states.push(*next);
-                            }
-                            Next::Accept => accept = true,
-                        }
-                        collapsed_states.remove(&last_state_label);
-                    }
-                }
-            }
-        }
-        (collapsed_states, accept)
+        state_label: usize,
+        input_char: char,
+        class: Option<usize>,
+    ) -> impl Iterator<Item = Next> + '_ {
+        let state = &self.states[state_label];
+        let table = self
+            .dense
+            .as_ref()
+            .zip(class)
+            .map(|(dense, class)| dense.transitions(state_label, class));
+
+        let by_rules = table.is_none().then(|| {
+            let consuming = state
+                .transitions
+                .iter()
+                .filter(|(rule, _)| !rule.is_zero_width());
+            // A single falsy rule rejects the whole short-circuit state
+            let rejected = state.short_circuit()
+                && !consuming
+                    .clone()
+                    .all(|(rule, _)| GexMachine::evaluate_rule(rule, &input_char));
+            consuming
+                .filter(move |(rule, _)| !rejected && GexMachine::evaluate_rule(rule, &input_char))
+        });
+
+        table
+            .into_iter()
+            .flatten()
+            .map(|&idx| state.transitions[idx as usize].1)
+            .chain(by_rules.into_iter().flatten().map(|(_, next)| *next))
     }
 
-    fn capture_group(&self, matcher: &mut GexMatcher, state_label: usize, position: usize) {
-        if let Some(group_numbers) = self.features.group_numbers(state_label) {
-            for (group_number, close_group_flag) in group_numbers {
-                let captures = matcher.captures.as_mut().unwrap();
-                match close_group_flag {
-                    0 => {
-                        captures.insert(group_number, MatchCandidate::with_start(position));
+    /// Evaluate whether a zero-width rule holds at the given position of the input.
+    pub(crate) fn evaluate_assertion(&self, rule: &Rule, input: &str, position: usize) -> bool {
+        let is_word = |character: char| character.is_alphanumeric() || character == '_';
+        match rule {
+            // Automata can't commit to one path, so atomic groups match like any other group
+            Rule::Null | Rule::AtomicOpen | Rule::AtomicClose => true,
+            // Scans that don't track captures pass straight through saves
+            Rule::Save(_) => true,
+            Rule::WordBoundary(positive) => {
+                let before = input[..position].chars().next_back().is_some_and(is_word);
+                let after = input[position..].chars().next().is_some_and(is_word);
+                (before != after) == *positive
+            }
+            Rule::Look(look_idx) => {
+                let look = &self.looks[*look_idx];
+                match look.kind {
+                    LookKind::Ahead(positive) => {
+                        look.machine.matches_anchored(input, position, false) == positive
                     }
-                    1 => {
-                        println!("close {} at {}", group_number, position);
-                        if group_number == 3 {
-                            // panic!("debug");
-                        }
-                        captures.get_mut(&group_number).as_mut().unwrap().end = Some(position);
+                    LookKind::Behind(positive) => {
+                        look.machine.matches_anchored(input, position, true) == positive
                     }
-                    _ => panic!("unrecognized group flag"),
                 }
             }
+            _ => false,
         }
     }
 
-    fn evaluate_state_flags(&self, matcher: &mut GexMatcher, state_label: usize, position: usize) {
-        if matcher.captures.is_some() {
-            self.capture_group(matcher, state_label, position);
+    /// Number of capture slots for the whole match and every group.
+    pub(crate) fn slot_count(&self) -> usize {
+        2 * (self.group_count() + 1)
+    }
+
+    /// The captures a search that finished with these slots found.
+    pub(crate) fn captures_from_slots(&self, slots: &[Option<usize>]) -> Captures {
+        Captures::new(slots.to_vec(), self.group_names.clone())
+    }
+
+    /// Scratch space for searching this machine without allocating.
+    pub fn create_cache(&self) -> Cache {
+        Cache::new(self)
+    }
+
+    /// Record a thread reaching the Accept at `position`.
+    ///
+    /// Returns whether lower priority threads should be dropped.
+    fn accept(&self, cache: &mut Cache, position: usize) -> bool {
+        cache.scratch[1] = Some(position);
+        let take = match self.match_kind {
+            // Threads are visited in priority order, so any later accept comes from a thread that
+            // outranks the current match
+            MatchKind::LeftmostFirst => true,
+            MatchKind::LeftmostLongest => {
+                !cache.has_match || posix_prefers(&cache.scratch, &cache.matched)
+            }
+        };
+        if take {
+            cache.matched.copy_from_slice(&cache.scratch);
+            cache.has_match = true;
         }
+        self.match_kind == MatchKind::LeftmostFirst
     }
 
-    /// Attempts to consume an input and determines the set of states after the transition.
-    fn do_transition(
+    /// Adds a thread for `target`, with the slots in `cache.scratch`, following Null transitions
+    /// (Epsilon) until every thread sits on a state that consumes input.
+    ///
+    /// States are entered in priority order. Under leftmost-first the first thread to reach a
+    /// state keeps it; under leftmost-longest a later thread takes the state over when POSIX
+    /// rules prefer it. Captures are undone as the paths through them are exhausted, so the
+    /// scratch slots are as they started once this returns, unless lower priority threads are to
+    /// be dropped, which it returns.
+    fn add_thread<O: Observer>(
         &self,
-        curr_states: &HashSet<usize>,
-        input_char: &char,
-        matcher: &mut GexMatcher,
+        list: &mut ThreadList,
+        cache: &mut Cache,
+        observer: &mut O,
+        target: Next,
+        input: &str,
         position: usize,
-        mut accepted: bool,
-    ) -> (HashSet<usize>, bool, usize) {
-        let mut new_states: HashSet<usize> = HashSet::new();
-
-        let mut consumed_a_character = false;
-        let mut new_position = position;
-
-        for state_label in curr_states.iter() {
-            if let Some(state) = self.states.get(*state_label) {
-                let mut short_circuit = false;
-                let mut states_to_add: HashSet<usize> = HashSet::new();
-
-                self.evaluate_state_flags(matcher, *state_label, position);
-                for (rule, transition) in state.transitions.iter() {
-                    if GexMachine::evaluate_rule(rule, input_char) {
-                        consumed_a_character = true;
-                        match transition {
-                            Next::Target(next) => {
-                                states_to_add.insert(*next);
-                            }
-                            Next::Accept => {
-                                accepted = true;
-                            }
+    ) -> bool {
+        cache.stack.push(Frame::Explore(target));
+
+        while let Some(frame) = cache.stack.pop() {
+            let state_label = match frame {
+                Frame::Restore(slot, value) => {
+                    cache.scratch[slot] = value;
+                    continue;
+                }
+                Frame::Save(slot, next) => {
+                    if O::ACTIVE {
+                        match slot % 2 {
+                            0 => observer.capture_opened(slot / 2, position),
+                            _ => observer.capture_closed(slot / 2, position),
                         }
-                    } else if state.short_circuit() {
-                        consumed_a_character = false;
-                        short_circuit = true;
-                        break;
                     }
+                    if let Some(entry) = cache.scratch.get_mut(slot) {
+                        cache.stack.push(Frame::Restore(slot, *entry));
+                        *entry = Some(position);
+                    }
+                    cache.stack.push(Frame::Explore(next));
+                    continue;
                 }
-
-                if short_circuit {
-                    break;
+                Frame::Explore(Next::Accept) => {
+                    if O::ACTIVE {
+                        observer.accept_reached(position);
+                    }
+                    if self.accept(cache, position) {
+                        cache.stack.clear();
+                        return true;
+                    }
+                    continue;
+                }
+                Frame::Explore(Next::Target(state_label)) => state_label,
+            };
+
+            match list.set.index_of(state_label) {
+                Some(existing)
+                    if self.match_kind == MatchKind::LeftmostLongest
+                        && posix_prefers(&cache.scratch, list.slots(existing)) =>
+                {
+                    list.slots_mut(existing).copy_from_slice(&cache.scratch);
+                }
+                Some(_) => continue,
+                None => {
+                    list.set.insert(state_label);
+                    list.slots_mut(list.set.len() - 1)
+                        .copy_from_slice(&cache.scratch);
                 }
-                new_states.extend(states_to_add);
             }
-        }
+            if O::ACTIVE {
+                observer.state_entered(state_label, position);
+            }
 
-        if consumed_a_character {
-            new_position += input_char.len_utf8();
+            // Reversed so the first transition is explored first
+            for (rule, next) in self.states[state_label].transitions.iter().rev() {
+                if let Rule::Save(slot) = rule {
+                    cache.stack.push(Frame::Save(*slot, *next));
+                } else if rule.is_zero_width() {
+                    let held = self.evaluate_assertion(rule, input, position);
+                    if O::ACTIVE && *rule != Rule::Null {
+                        observer.rule_tested(state_label, rule, position, held);
+                    }
+                    if held {
+                        cache.stack.push(Frame::Explore(*next));
+                    }
+                }
+            }
         }
-
-        // handle Null states, as they should not consume a character
-        let (new_states, accepted_via_null) =
-            self.collapse_null_transitions(new_states, new_position, matcher);
-
-        (new_states, accepted || accepted_via_null, new_position)
+        false
     }
 
-    fn run_machine(&self, input: &str, matcher: &mut GexMatcher) -> Option<Match> {
-        // start state is always the zeroth state
-        let mut curr_states = HashSet::from([0]);
-        let start_position = 0;
-        let mut position = start_position;
-        let mut next_position: usize;
-        let mut accepted = false;
-        let accepted_via_null: bool;
-
-        let mut candidate = MatchCandidate::new();
-        candidate.start = start_position;
+    /// Attempts to consume the input character at `position` from every thread of
+    /// `curr_threads`, building the threads for the following position in `new_threads`.
+    fn do_transition<O: Observer>(
+        &self,
+        curr_threads: &ThreadList,
+        new_threads: &mut ThreadList,
+        cache: &mut Cache,
+        observer: &mut O,
+        input: &str,
+        position: usize,
+    ) {
+        new_threads.set.clear();
+        let input_char = input[position..]
+            .chars()
+            .next()
+            .expect("a character at the position");
+        let new_position = position + input_char.len_utf8();
+        let class = self.class_of(input_char);
+
+        for idx in 0..curr_threads.set.len() {
+            let thread_slots = curr_threads.slots(idx);
+            // A thread that started after the current match can no longer beat it
+            if self.match_kind == MatchKind::LeftmostLongest
+                && cache.has_match
+                && thread_slots[0] > cache.matched[0]
+            {
+                continue;
+            }
 
-        (curr_states, accepted_via_null) =
-            self.collapse_null_transitions(curr_states, position, matcher);
+            let state_label = curr_threads.set.get(idx);
+            if O::ACTIVE {
+                self.observe_rules(observer, state_label, input_char, position);
+            }
+            let cut = self
+                .consuming_targets(state_label, input_char, class)
+                .any(|next| {
+                    if O::ACTIVE {
+                        observer.character_consumed(state_label, input_char, position, next);
+                    }
+                    cache.scratch.copy_from_slice(thread_slots);
+                    self.add_thread(new_threads, cache, observer, next, input, new_position)
+                });
+            if cut {
+                break;
+            }
+        }
+    }
 
-        if accepted_via_null {
-            candidate.end = Some(start_position);
+    /// Report the verdict of each consuming rule of the state on the character.
+    fn observe_rules<O: Observer>(
+        &self,
+        observer: &mut O,
+        state_label: usize,
+        input_char: char,
+        position: usize,
+    ) {
+        let state = &self.states[state_label];
+        let consuming = state
+            .transitions
+            .iter()
+            .filter(|(rule, _)| !rule.is_zero_width());
+        // A single falsy rule rejects the whole short-circuit state
+        let rejected = state.short_circuit()
+            && !consuming
+                .clone()
+                .all(|(rule, _)| GexMachine::evaluate_rule(rule, &input_char));
+        for (rule, _) in consuming {
+            let held = !rejected && GexMachine::evaluate_rule(rule, &input_char);
+            observer.rule_tested(state_label, rule, position, held);
         }
+    }
 
-        // accepted = accepted || accepted_via_null;
+    /// Run the machine over the input from `at`, starting a new thread at each position until a
+    /// match is found, leaving the slots of the winning thread in `cache.matched`. The search
+    /// gives up, having found nothing, if the interrupt fires.
+    fn run_machine<O: Observer>(
+        &self,
+        cache: &mut Cache,
+        observer: &mut O,
+        input: &str,
+        at: usize,
+        slot_count: usize,
+        interrupt: Option<&Interrupt>,
+    ) -> Outcome<bool> {
+        cache.reset(self.size(), slot_count);
+        // The lists are taken out of the cache while they are read from and written to
+        let mut curr_threads = std::mem::replace(&mut cache.curr, ThreadList::empty());
+        let mut new_threads = std::mem::replace(&mut cache.next, ThreadList::empty());
+        let mut position = at;
+        let mut steps: usize = 0;
+        let mut interrupted = false;
+
+        let found = 'search: {
+            if let Some(prefilter) = &self.prefilter {
+                if !prefilter.could_match(input, at) {
+                    break 'search false;
+                }
+            }
+
+            loop {
+                steps += 1;
+                if steps.is_multiple_of(INTERRUPT_CHECK_POSITIONS)
+                    && interrupt.is_some_and(Interrupt::is_interrupted)
+                {
+                    interrupted = true;
+                    break 'search false;
+                }
+                if !cache.has_match {
+                    if curr_threads.set.is_empty() {
+                        if let Some(prefilter) = self.prefilter.as_ref().filter(|p| p.is_prefix()) {
+                            match prefilter.find_candidate(input, position) {
+                                Some(candidate) => position = candidate,
+                                None => break 'search false,
+                            }
+                        }
+                    }
+                    // A new thread starting here has the lowest priority of all
+                    cache.scratch.fill(None);
+                    cache.scratch[0] = Some(position);
+                    if O::ACTIVE {
+                        observer.thread_started(position);
+                    }
+                    self.add_thread(
+                        &mut curr_threads,
+                        cache,
+                        observer,
+                        Next::Target(0),
+                        input,
+                        position,
+                    );
+                }
 
-        for input_char in input[start_position..].chars() {
-            (curr_states, accepted, next_position) =
-                self.do_transition(&curr_states, &input_char, matcher, position, accepted);
+                let input_char = match input[position..].chars().next() {
+                    Some(input_char) => input_char,
+                    None => break,
+                };
+                let new_position = position + input_char.len_utf8();
 
-            if next_position > position {
-                position = next_position;
-                if accepted {
-                    candidate.end = Some(position);
+                if curr_threads.set.is_empty() {
+                    if cache.has_match {
+                        break;
+                    }
+                    position = new_position;
+                    continue;
                 }
-            }
 
-            if curr_states.len() == 0 {
-                break;
+                self.do_transition(
+                    &curr_threads,
+                    &mut new_threads,
+                    cache,
+                    observer,
+                    input,
+                    position,
+                );
+                swap(&mut curr_threads, &mut new_threads);
+                position = new_position;
             }
-        }
+            cache.has_match
+        };
 
-        if candidate.end.is_some() {
-            Some(Match::from_candidate(&candidate))
-        } else {
-            None
+        cache.curr = curr_threads;
+        cache.next = new_threads;
+        match interrupted {
+            true => Outcome::Cancelled(false),
+            false => Outcome::Complete(found),
         }
     }
 
-    fn find_first_match<T>(
+    /// `find_at`, reusing the scratch space in `cache` instead of allocating.
+    pub fn find_at_cached(&self, cache: &mut Cache, input: &str, at: usize) -> Option<Match> {
+        self.find_at_until_cached(cache, input, at, None)
+            .into_inner()
+    }
+
+    /// `find_at_cached`, giving up once the interrupt, when there is one, fires.
+    pub fn find_at_until_cached(
         &self,
-        matcher: &mut GexMatcher,
+        cache: &mut Cache,
         input: &str,
-        extract_match: impl Fn(Match, &mut GexMatcher) -> T,
-        apply_correction: impl Fn((usize, T)) -> T,
-    ) -> Option<T> {
-        // Allow for the case of an empty string match before iterating through remaining chars
-        once((0, '\0'))
-            .chain(input.char_indices())
-            .map(|(idx, _)| {
-                self.run_machine(&input[idx..], matcher)
-                    .map(|found| (idx, extract_match(found, matcher)))
+        at: usize,
+        interrupt: Option<&Interrupt>,
+    ) -> Outcome<Option<Match>> {
+        let found = |cache: &Cache| {
+            Some(Match {
+                start: cache.matched[0]?,
+                end: cache.matched[1]?,
             })
-            .find(Option::is_some)
-            .flatten()
-            .map(apply_correction)
+        };
+        match self.run_machine(cache, &mut (), input, at, 2, interrupt) {
+            Outcome::Complete(true) => Outcome::Complete(found(cache)),
+            Outcome::Complete(false) => Outcome::Complete(None),
+            Outcome::Cancelled(_) => Outcome::Cancelled(None),
+            Outcome::GaveUp(_, error) => Outcome::GaveUp(None, error),
+        }
+    }
+
+    /// `captures_at`, reusing the scratch space in `cache`; only the returned captures are
+    /// allocated.
+    pub fn captures_at_cached(
+        &self,
+        cache: &mut Cache,
+        input: &str,
+        at: usize,
+    ) -> Option<Captures> {
+        self.run_machine(cache, &mut (), input, at, self.slot_count(), None)
+            .into_inner()
+            .then(|| self.captures_from_slots(&cache.matched))
+    }
+
+    /// `captures_at`, reporting each step of the search to the observer.
+    pub fn captures_at_observed<O: Observer>(
+        &self,
+        input: &str,
+        at: usize,
+        observer: &mut O,
+    ) -> Option<Captures> {
+        let mut cache = self.create_cache();
+        self.run_machine(&mut cache, observer, input, at, self.slot_count(), None)
+            .into_inner()
+            .then(|| self.captures_from_slots(&cache.matched))
     }
 }
 
 impl Matcher for GexMachine {
     fn find_at(&self, input: &str, at: usize) -> Option<Match> {
-        let mut matcher = GexMatcher { captures: None };
-        let start_input = &input[at..];
-
-        let on_found = |found, _: &mut GexMatcher| found;
-        let correction = |(offset, found): (usize, Match)| found.shift(offset + at);
-
-        self.find_first_match(&mut matcher, start_input, on_found, correction)
-
-        // TODO: test this at a different location than the start of the string w/ a zero or more
+        self.find_at_cached(&mut self.create_cache(), input, at)
     }
 
-    fn captures_at(&self, input: &str, at: usize) -> Option<HashMap<u16, Match>> {
-        let mut matcher = GexMatcher {
-            captures: Some(HashMap::new()),
-        };
-        let start_input = &input[at..];
-
-        let on_found = |found, matcher: &mut GexMatcher| {
-            let mut captures = matcher.unwrap_captures();
-            captures.insert(0, found);
-            captures
-        };
-
-        let correction = |(offset, mut captures): (usize, HashMap<u16, Match>)| {
-            for (_, capture) in captures.iter_mut() {
-                *capture = capture.shift(offset + at);
-            }
-            captures
-        };
-
-        self.find_first_match(&mut matcher, start_input, on_found, correction)
+    fn captures_at(&self, input: &str, at: usize) -> Option<Captures> {
+        self.captures_at_cached(&mut self.create_cache(), input, at)
     }
 }
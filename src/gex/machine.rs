@@ -1,5 +1,9 @@
-use crate::gex::features::{FlagMasks, FlagShifts, GexFeatures};
+use crate::gex::classes::{DenseTable, DEFAULT_MAX_DENSE_PAIRS};
+use crate::gex::prefilter::Prefilter;
+use crate::matcher::{GroupNames, MatchKind};
 use std::collections::HashMap;
+use std::iter::once;
+use std::sync::Arc;
 
 // NOTE: this actually forces us to use UTF-8
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -9,9 +13,56 @@ pub enum Rule {
     IsWord(bool),
     IsDigit(bool),
     IsWhitespace(bool),
+    /// Zero-width test for a word boundary (or, when false, its absence) at the position.
+    WordBoundary(bool),
+    /// Consumes the text last captured by the numbered group; only the backtracker can follow it.
+    Backreference(u16),
+    /// Zero-width test of the machine's lookaround at this index.
+    Look(usize),
+    /// Zero-width markers around an atomic group. Once the backtracker passes the close of a
+    /// group, it drops every other way the group's body could have matched; automata can't.
+    AtomicOpen,
+    AtomicClose,
+    /// Matches the numbered group's pattern again, or the whole pattern for group 0, returning
+    /// here once it reaches the Accept; only the backtracker can follow it.
+    Call(u16),
+    /// Zero-width instruction recording the position in a capture slot: the start of group `n`
+    /// in slot `2n` and its end in slot `2n + 1`.
+    Save(usize),
     Null,
 }
 
+impl Rule {
+    /// Whether the rule is followed without consuming input.
+    pub fn is_zero_width(&self) -> bool {
+        matches!(
+            self,
+            Rule::Null
+                | Rule::Save(_)
+                | Rule::WordBoundary(_)
+                | Rule::Look(_)
+                | Rule::AtomicOpen
+                | Rule::AtomicClose
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum LookKind {
+    Ahead(bool),
+    Behind(bool),
+}
+
+/// A lookaround assertion, holding the machine for its body.
+///
+/// Lookbehind bodies are stored reversed, so they can be run backwards from the position being
+/// tested.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Look {
+    pub kind: LookKind,
+    pub machine: GexMachine,
+}
+
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Next {
     Target(usize),
@@ -20,37 +71,35 @@ pub enum Next {
 
 pub type Transition = (Rule, Next);
 
-/// Extensible state struct.
-/// Goal is to add ability to mark states as the beginning of a group etc, right now just a dumb
-/// state machine state.
+/// A state of the machine: its transitions, in priority order.
+///
+/// Anything that happens on the way through a state, such as capturing a group boundary, is a
+/// zero-width transition of its own rather than a mark on the state.
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct State {
     pub transitions: Vec<Transition>,
     /// `true` indicates that a single falsy rule in the transitions should cause all other
     /// potential rules in this state to evaluate as falsy.
-    pub flags: u64,
+    short_circuit: bool,
 }
 
 impl State {
     pub fn from_transitions(transitions: Vec<Transition>) -> Self {
         State {
             transitions,
-            flags: 0x0,
+            short_circuit: false,
         }
     }
 
     pub fn short_circuit_from_transitions(transitions: Vec<Transition>) -> Self {
         State {
             transitions,
-            flags: FlagMasks::ShortCircuit as u64,
+            short_circuit: true,
         }
     }
 
     pub fn accept_state() -> Self {
-        State {
-            transitions: vec![(Rule::Null, Next::Accept)],
-            flags: 0x0,
-        }
+        State::from_transitions(vec![(Rule::Null, Next::Accept)])
     }
 
     pub fn push(&mut self, transition: Transition) {
@@ -58,27 +107,40 @@ impl State {
     }
 
     pub fn short_circuit(&self) -> bool {
-        (self.flags & FlagMasks::ShortCircuit as u64) != 0
+        self.short_circuit
     }
+}
 
-    pub fn group_number(&self) -> u16 {
-        (self.flags >> FlagShifts::CapturingGroup as usize) as u16
-    }
+/// How far to move the parts of a machine being merged into another: its states, its
+/// lookarounds, and its capture slots.
+#[derive(Debug, Clone, Copy)]
+struct Shift {
+    states: usize,
+    looks: usize,
+    slots: usize,
+}
 
-    pub fn close_group(&self) -> u8 {
-        ((self.flags & FlagMasks::CloseGroup as u64) >> FlagShifts::CloseGroup as u64) as u8
+impl Shift {
+    fn states(states: usize) -> Self {
+        Shift {
+            states,
+            looks: 0,
+            slots: 0,
+        }
     }
 }
 
-fn states_shifter(shift: usize) -> impl Fn(State) -> State {
+fn states_shifter(shift: Shift) -> impl Fn(State) -> State {
     move |mut state: State| {
-        for idx in 0..state.transitions.len() {
-            let old_value = state.transitions[idx];
-            state.transitions[idx] = if let (rule, Next::Target(next)) = old_value {
-                (rule, Next::Target(next + shift))
-            } else {
-                old_value
-            };
+        for (rule, next) in state.transitions.iter_mut() {
+            if let Next::Target(target) = next {
+                *target += shift.states;
+            }
+            match rule {
+                Rule::Look(look_idx) => *look_idx += shift.looks,
+                Rule::Save(slot) => *slot += shift.slots,
+                _ => {}
+            }
         }
         state
     }
@@ -89,11 +151,30 @@ fn states_shifter(shift: usize) -> impl Fn(State) -> State {
 pub struct GexMachine {
     /// Each state is a vector of unicode ranges and the state they map to
     pub states: Vec<State>,
-    pub(super) features: GexFeatures,
-    max_group_index: u16,
+    pub(super) group_count: usize,
+    /// Literal scan used to skip ahead to candidate match positions; only ever set on a
+    /// finished machine, since combining machines changes the language it describes.
+    pub(super) prefilter: Option<Prefilter>,
+    /// Which match wins when several start at the leftmost position.
+    pub(super) match_kind: MatchKind,
+    /// Lookarounds referred to by `Rule::Look`.
+    pub(super) looks: Vec<Look>,
+    /// Entry state of each group called by `Rule::Call`, other than the whole pattern; only
+    /// ever set on a finished machine, since the called copies sit after its Accept state.
+    pub(super) subroutines: HashMap<u16, usize>,
+    /// Transitions tabulated by character class; like the prefilter, only ever set on a
+    /// finished machine.
+    pub(super) dense: Option<Box<DenseTable>>,
+    /// Numbers of the named groups, shared with the captures of each match; only ever set on a
+    /// finished machine, and only when it has named groups.
+    pub(super) group_names: Option<Arc<GroupNames>>,
 }
 
-// TODO: implement find w/ explain -> might be hard with this implementation
+impl Default for GexMachine {
+    fn default() -> Self {
+        GexMachine::with_capacity(2)
+    }
+}
 
 /// NFA implementation for solving regex.
 /// Supports operations to build composite machines via concatenation and alternation.
@@ -101,8 +182,13 @@ impl GexMachine {
     pub fn from_states(states: Vec<State>) -> Self {
         GexMachine {
             states,
-            features: GexFeatures::new(),
-            max_group_index: 0,
+            group_count: 0,
+            prefilter: None,
+            match_kind: MatchKind::default(),
+            looks: Vec::new(),
+            subroutines: HashMap::new(),
+            dense: None,
+            group_names: None,
         }
     }
     /// Create NFA with the given states vec capacity.
@@ -115,45 +201,143 @@ impl GexMachine {
         GexMachine::from_states(states)
     }
 
-    pub fn default() -> Self {
-        // TODO: when making a machine from a pattern, have a reasonable guess at the size of the
-        // NFA
-        GexMachine::with_capacity(1_000_000)
-    }
-
     pub fn size(&self) -> usize {
         self.states.len()
     }
 
-    // TODO: the problem is in here; the close group item is getting shifted to the incorrect
-    // location (one state short)
-    fn add_shifted_flags(
-        &mut self,
-        other_state_flags: HashMap<usize, Vec<u64>>,
-        old_accept_idx: usize,
-        maintain_root: bool,
-    ) {
-        let group_shift = self.max_group_index;
-        for (state_idx, flags_vec) in other_state_flags.into_iter() {
-            let new_idx = if maintain_root && state_idx == 0 {
-                state_idx
-            } else {
-                state_idx + old_accept_idx
+    /// Number of states, counting those of the lookarounds' machines.
+    pub fn total_size(&self) -> usize {
+        self.size()
+            + self
+                .looks
+                .iter()
+                .map(|look| look.machine.total_size())
+                .sum::<usize>()
+    }
+
+    /// Attach a prefilter to the finished machine.
+    ///
+    /// The caller is responsible for the prefilter being sound for the machine's language.
+    pub fn with_prefilter(mut self, prefilter: Option<Prefilter>) -> Self {
+        self.prefilter = prefilter;
+        self
+    }
+
+    pub fn prefilter(&self) -> Option<&Prefilter> {
+        self.prefilter.as_ref()
+    }
+
+    /// Tabulate the finished machine's transitions by character class, so searches look each
+    /// character up once instead of testing it against every rule.
+    ///
+    /// The table is left out when it would pass `DEFAULT_MAX_DENSE_PAIRS`.
+    pub fn with_dense_table(self) -> Self {
+        self.with_dense_table_within(DEFAULT_MAX_DENSE_PAIRS)
+    }
+
+    /// Like `with_dense_table`, leaving the table out when it would hold more than `max_pairs`
+    /// (state, class) pairs.
+    pub fn with_dense_table_within(mut self, max_pairs: usize) -> Self {
+        self.dense = DenseTable::new(&self.states, max_pairs).map(Box::new);
+        self
+    }
+
+    pub fn dense_table(&self) -> Option<&DenseTable> {
+        self.dense.as_deref()
+    }
+
+    pub fn with_match_kind(mut self, match_kind: MatchKind) -> Self {
+        self.match_kind = match_kind;
+        self
+    }
+
+    pub fn with_group_names(mut self, names: GroupNames) -> Self {
+        self.group_names = (!names.is_empty()).then(|| Arc::new(names));
+        self
+    }
+
+    /// Number of each named group, by name.
+    pub fn group_names(&self) -> Option<&GroupNames> {
+        self.group_names.as_deref()
+    }
+
+    pub fn match_kind(&self) -> MatchKind {
+        self.match_kind
+    }
+
+    /// Whether any transition refers back to captured text, which no automaton can match.
+    pub fn has_backreferences(&self) -> bool {
+        self.states.iter().any(|state| {
+            state
+                .transitions
+                .iter()
+                .any(|(rule, _)| matches!(rule, Rule::Backreference(_)))
+        })
+    }
+
+    /// Whether the machine has atomic groups, whose matches only a backtracking search can find.
+    pub fn has_atomic_groups(&self) -> bool {
+        self.states.iter().any(|state| {
+            state
+                .transitions
+                .iter()
+                .any(|(rule, _)| *rule == Rule::AtomicOpen)
+        })
+    }
+
+    /// Whether the machine tests lookarounds, which need the text on either side of a position.
+    pub fn has_lookarounds(&self) -> bool {
+        !self.looks.is_empty()
+    }
+
+    /// Whether the machine calls a group, or the whole pattern, recursively.
+    pub fn has_calls(&self) -> bool {
+        self.states.iter().any(|state| {
+            state
+                .transitions
+                .iter()
+                .any(|(rule, _)| matches!(rule, Rule::Call(_)))
+        })
+    }
+
+    /// Whether the machine has features that only the backtracker honors.
+    pub fn needs_backtracking(&self) -> bool {
+        self.has_backreferences() || self.has_atomic_groups() || self.has_calls()
+    }
+
+    /// Append a copy of each called group's machine, reached only through `Rule::Call`.
+    ///
+    /// Groups nested in a copy capture into their own slots while the call runs, and a call
+    /// puts every group back as it was once it returns.
+    pub fn with_subroutines(mut self, bodies: Vec<(u16, GexMachine)>) -> Self {
+        for (group_number, body) in bodies {
+            let entry = self.size();
+            // A group's body numbers the groups nested in it from 1, rather than after the group
+            let shift = Shift {
+                states: entry,
+                looks: self.looks.len(),
+                slots: 2 * group_number as usize,
             };
-            let mut shifted_flags = Some(
-                flags_vec
-                    .into_iter()
-                    .filter(|flags| GexFeatures::group_number(*flags) != 0)
-                    .map(|flags| GexFeatures::increment_group_number(flags, group_shift))
-                    .collect::<Vec<u64>>(),
-            );
-
-            self.features
-                .state_flags
-                .entry(new_idx)
-                .and_modify(|entry| entry.extend(shifted_flags.take().unwrap()))
-                .or_insert_with(|| shifted_flags.take().unwrap());
+            self.looks.extend(body.looks);
+            self.states
+                .extend(body.states.into_iter().map(states_shifter(shift)));
+            self.subroutines.insert(group_number, entry);
         }
+        self.dense = None;
+        self
+    }
+
+    /// The state a call to the group starts from.
+    pub fn subroutine_entry(&self, group_number: u16) -> Option<usize> {
+        match group_number {
+            0 => Some(0),
+            _ => self.subroutines.get(&group_number).copied(),
+        }
+    }
+
+    /// Number of capturing groups, not counting the implicit whole-match group 0.
+    pub fn group_count(&self) -> usize {
+        self.group_count
     }
 
     /// Concatenate the current NFA with another.
@@ -164,13 +348,19 @@ impl GexMachine {
         // IMPORTANT Assumption: the last state always contains a singular Accept
         self.states.pop();
 
-        let new_states = other.states.into_iter().map(states_shifter(old_accept_idx));
-
-        self.states.extend(new_states);
-
-        self.add_shifted_flags(other.features.state_flags, old_accept_idx, false);
+        // The other machine's groups are numbered after the receiver's
+        let shift = Shift {
+            states: old_accept_idx,
+            looks: self.looks.len(),
+            slots: 2 * self.group_count,
+        };
+        self.looks.extend(other.looks);
+        self.states
+            .extend(other.states.into_iter().map(states_shifter(shift)));
 
-        self.max_group_index += other.max_group_index;
+        self.group_count += other.group_count;
+        self.prefilter = None;
+        self.dense = None;
 
         self
     }
@@ -200,18 +390,20 @@ impl GexMachine {
 
         old_accept.1 = Next::Target(new_accept_idx);
 
-        let new_states = other.states.into_iter().map(states_shifter(other_start));
-
-        self.states.extend(new_states);
-
-        self.add_shifted_flags(other.features.state_flags, other_start, false);
+        let shift = Shift {
+            states: other_start,
+            looks: self.looks.len(),
+            slots: 2 * self.group_count,
+        };
+        self.looks.extend(other.looks);
+        self.states
+            .extend(other.states.into_iter().map(states_shifter(shift)));
 
-        self.max_group_index += other.max_group_index;
+        self.group_count += other.group_count;
+        self.prefilter = None;
+        self.dense = None;
 
-        // Maintain separate penultimate state for RHS, allows distinct flags to be maintained
-        // NOTE: for future optimization, it might be possible to resolve this by instead shifting
-        // the GroupOpen flags forward if they are on a null transition, then the close flags can
-        // share a state and not collide
+        // Both sides lead to a shared accept state
         self.states
             .last_mut()
             .unwrap()
@@ -224,37 +416,38 @@ impl GexMachine {
         self
     }
 
-    // TODO: implement non-capturing groups
+    /// Capture the machine as a group, numbered before any group inside it.
+    ///
+    /// A new start state saves the group's start on the way in, and the old accept state saves
+    /// its end on the way out.
     pub fn group(mut self) -> Self {
-        self.max_group_index += 1;
-        let new_group_number = 1 << FlagShifts::CapturingGroup as u64;
-        let last_idx = self.states.len() - 1;
-
-        for flags_vec in self.features.state_flags.values_mut() {
-            for flags in flags_vec.iter_mut() {
-                *flags = GexFeatures::increment_group_number(*flags, 1);
-            }
-        }
-
-        let start_flag = new_group_number;
-        let end_flag = new_group_number | FlagMasks::CloseGroup as u64;
-
-        self.features
-            .state_flags
-            .entry(0)
-            .and_modify(|flags| {
-                flags.push(start_flag);
-            })
-            .or_insert(vec![start_flag]);
-
-        self.features
-            .state_flags
-            .entry(last_idx)
-            .and_modify(|flags| {
-                flags.push(end_flag);
-            })
-            .or_insert(vec![end_flag]);
+        let old_accept_idx = self.size();
+        let states = std::mem::take(&mut self.states);
+        self.states = once(State::from_transitions(vec![(
+            Rule::Save(2),
+            Next::Target(1),
+        )]))
+        .chain(states.into_iter().map(states_shifter(Shift {
+            states: 1,
+            looks: 0,
+            slots: 2,
+        })))
+        .collect();
+
+        self.states[old_accept_idx] =
+            State::from_transitions(vec![(Rule::Save(3), Next::Target(old_accept_idx + 1))]);
+        self.states.push(State::accept_state());
+        self.group_count += 1;
+        self
+    }
 
+    /// Move every state up by one behind a new start state, so that loops back to the body's
+    /// start never reach the transitions added to the new start state.
+    fn with_fresh_start(mut self) -> Self {
+        let states = std::mem::take(&mut self.states);
+        self.states = once(State::from_transitions(vec![(Rule::Null, Next::Target(1))]))
+            .chain(states.into_iter().map(states_shifter(Shift::states(1))))
+            .collect();
         self
     }
 
@@ -264,9 +457,13 @@ impl GexMachine {
         self
     }
 
+    /// Loop from the end of the body back to its start, just past the fresh start state.
     fn accept_repeats(mut self) -> Self {
         let new_accept_idx = self.size();
-        self.states[new_accept_idx - 1].push((Rule::Null, Next::Target(0)));
+        // Repeating is tried before leaving, which makes the quantifier greedy
+        self.states[new_accept_idx - 1]
+            .transitions
+            .insert(0, (Rule::Null, Next::Target(1)));
         self
     }
 
@@ -278,19 +475,214 @@ impl GexMachine {
             }
         }
         self.states.push(State::accept_state());
+        self.prefilter = None;
+        self.dense = None;
         self
     }
 
     pub fn zero_or_more(self) -> Self {
-        self.accept_zero().accept_repeats().finalize_quantifier()
+        self.with_fresh_start()
+            .accept_zero()
+            .accept_repeats()
+            .finalize_quantifier()
     }
 
     pub fn one_or_more(self) -> Self {
-        self.accept_repeats().finalize_quantifier()
+        self.with_fresh_start()
+            .accept_repeats()
+            .finalize_quantifier()
     }
 
     pub fn zero_or_one(self) -> Self {
-        self.accept_zero().finalize_quantifier()
+        self.with_fresh_start().accept_zero().finalize_quantifier()
+    }
+
+    /// Alternate the machines under one start state, keeping apart the states they accept from.
+    ///
+    /// Returns the union along with, for each machine in order, its final state: the one that
+    /// leads to the Accept, which a scan reaches only when that machine matches. Groups are not
+    /// renumbered, since nothing searches the union for captures, so it counts as many groups as
+    /// the machine with the most.
+    pub fn union(machines: Vec<GexMachine>) -> (GexMachine, Vec<usize>) {
+        let mut union = GexMachine::from_states(vec![State::from_transitions(vec![])]);
+        let mut finals = Vec::with_capacity(machines.len());
+        for machine in machines {
+            let entry = union.size();
+            union.states[0].push((Rule::Null, Next::Target(entry)));
+            union.group_count = union.group_count.max(machine.group_count);
+            let shift = Shift {
+                states: entry,
+                looks: union.looks.len(),
+                slots: 0,
+            };
+            union.looks.extend(machine.looks);
+            union
+                .states
+                .extend(machine.states.into_iter().map(states_shifter(shift)));
+            finals.push(union.size() - 1);
+        }
+        // Keep the accept state last, as the combinators expect
+        union.states.push(State::accept_state());
+        (union, finals)
+    }
+
+    /// Repeat the machine at least `min` times, and at most `max` times when bounded.
+    ///
+    /// Copies are laid out one after the other rather than nested, and each optional copy is
+    /// entered through a state that may instead leave straight for the shared accept state, so
+    /// the machine grows linearly with the count. A body that is a single consuming state, like
+    /// `[a-z]`, is copied as that state alone, and its optional copies leave for the accept
+    /// themselves, once consuming another character has been tried, so `[a-z]{1,1000}` takes a
+    /// state per count. Groups keep their numbers in every copy.
+    pub fn repeat(self, min: usize, max: Option<usize>) -> Self {
+        let optional = max.map_or(0, |max| max - min);
+        let mandatory = match max {
+            Some(_) => min,
+            // The last copy of an unbounded repetition loops instead
+            None => min.saturating_sub(1),
+        };
+        let looped = max.is_none().then(|| match min {
+            0 => self.clone().zero_or_more(),
+            _ => self.clone().one_or_more(),
+        });
+        let looks = self.looks.clone();
+        let group_count = self.group_count;
+        let copy = self.into_copy();
+        let looped = looped.map(GexMachine::into_copy);
+
+        let looped_size = looped.as_ref().map_or(0, Vec::len);
+        let single_state = copy.len() == 1;
+        let entries = if single_state { 0 } else { optional };
+        let accept_idx = 1 + (mandatory + optional) * copy.len() + entries + looped_size;
+        let mut states = Vec::with_capacity(accept_idx + 1);
+        states.push(State::from_transitions(vec![(Rule::Null, Next::Target(1))]));
+        let append = |states: &mut Vec<State>, copy: &[State]| {
+            let entry = states.len();
+            let exit = entry + copy.len();
+            states.extend(copy.iter().cloned().map(|mut state| {
+                for (_, next) in state.transitions.iter_mut() {
+                    *next = match next {
+                        Next::Target(target) => Next::Target(*target + entry),
+                        Next::Accept => Next::Target(exit),
+                    };
+                }
+                state
+            }));
+        };
+
+        for _ in 0..mandatory {
+            append(&mut states, &copy);
+        }
+        for _ in 0..optional {
+            if single_state {
+                append(&mut states, &copy);
+                let state = states.last_mut().expect("The copy was just appended");
+                state.push((Rule::Null, Next::Target(accept_idx)));
+                continue;
+            }
+            // Entering the copy is tried before leaving, which makes the repetition greedy
+            let entry = states.len() + 1;
+            states.push(State::from_transitions(vec![
+                (Rule::Null, Next::Target(entry)),
+                (Rule::Null, Next::Target(accept_idx)),
+            ]));
+            append(&mut states, &copy);
+        }
+        if let Some(looped) = looped {
+            append(&mut states, &looped);
+        }
+        states.push(State::accept_state());
+
+        let mut repeated = GexMachine::from_states(states);
+        repeated.looks = looks;
+        repeated.group_count = group_count;
+        repeated
+    }
+
+    /// The states of one copy of the machine for `repeat`, entered at the first and left through
+    /// `Next::Accept`.
+    fn into_copy(mut self) -> Vec<State> {
+        let single_state = match self.states.as_slice() {
+            [start, state, _] => {
+                start.transitions == [(Rule::Null, Next::Target(1))]
+                    && state
+                        .transitions
+                        .iter()
+                        .all(|(rule, next)| !rule.is_zero_width() && *next == Next::Target(2))
+            }
+            _ => false,
+        };
+        if single_state {
+            let mut state = self.states.swap_remove(1);
+            for (_, next) in state.transitions.iter_mut() {
+                *next = Next::Accept;
+            }
+            return vec![state];
+        }
+        self.states
+    }
+
+    /// Build the machine for the reversed pattern: it accepts exactly the reversals of the
+    /// strings this machine accepts.
+    ///
+    /// Every transition is flipped, the old accepting states become the targets of the new start
+    /// state and the old start state leads to the new accept. Short-circuit states are rebuilt as
+    /// separate states so their rules are still evaluated together. Capture groups and the
+    /// prefilter describe the forward pattern only, so they are not carried over, while
+    /// lookarounds test a position rather than consume text and carry over unchanged.
+    pub fn reverse(&self) -> GexMachine {
+        // Old state `idx` becomes `idx + 1`, leaving 0 free for the new start state
+        let shifted = |idx: usize| idx + 1;
+        let reversed_from = |next: &Next| match next {
+            Next::Target(next) => shifted(*next),
+            Next::Accept => 0,
+        };
+
+        let mut states: Vec<State> = (0..shifted(self.size()))
+            .map(|_| State::from_transitions(vec![]))
+            .collect();
+        let mut conjunctions: Vec<State> = Vec::new();
+
+        for (state_idx, state) in self.states.iter().enumerate() {
+            if !state.short_circuit() {
+                for (rule, next) in state.transitions.iter() {
+                    states[reversed_from(next)].push((*rule, Next::Target(shifted(state_idx))));
+                }
+                continue;
+            }
+
+            let mut targets: Vec<Next> = Vec::new();
+            for (_, next) in state.transitions.iter() {
+                if !targets.contains(next) {
+                    targets.push(*next);
+                }
+            }
+            for target in targets {
+                let conjunction_idx = shifted(self.size()) + conjunctions.len();
+                conjunctions.push(State::short_circuit_from_transitions(
+                    state
+                        .transitions
+                        .iter()
+                        .filter(|(_, next)| *next == target)
+                        .map(|(rule, _)| (*rule, Next::Target(shifted(state_idx))))
+                        .collect(),
+                ));
+                states[reversed_from(&target)].push((Rule::Null, Next::Target(conjunction_idx)));
+            }
+        }
+
+        // Keep the accept state last, as the combinators expect
+        let accept_idx = shifted(self.size()) + conjunctions.len();
+        states[shifted(0)].push((Rule::Null, Next::Target(accept_idx)));
+        states.extend(conjunctions);
+        states.push(State::accept_state());
+
+        let mut reversed = GexMachine::from_states(states);
+        reversed.looks = self.looks.clone();
+        if self.dense.is_some() {
+            reversed = reversed.with_dense_table();
+        }
+        reversed
     }
 }
 
@@ -410,6 +802,29 @@ mod tests {
         assert_full_match(&gex_machine, "baaaabcabbbb");
     }
 
+    #[test]
+    fn test_group_saves() {
+        let inner = machine_for_character('b').group();
+        let gex_machine = machine_for_character('a').group().cons(inner.group());
+
+        let saves: Vec<usize> = gex_machine
+            .states
+            .iter()
+            .flat_map(|state| state.transitions.iter())
+            .filter_map(|(rule, _)| match rule {
+                Rule::Save(slot) => Some(*slot),
+                _ => None,
+            })
+            .collect();
+        // Groups are numbered by their opening, with the outer group of `((b))` before the inner
+        assert_eq!(saves, vec![2, 3, 4, 6, 7, 5]);
+        assert_eq!(gex_machine.group_count(), 3);
+        assert_eq!(
+            gex_machine.captures("ab").map(|captures| captures.len()),
+            Some(4)
+        );
+    }
+
     #[test]
     fn test_state_short_circuit() {
         let state = State::short_circuit_from_transitions(vec![]);
@@ -1,7 +1,15 @@
-pub(self) mod features;
+pub mod cache;
+pub mod classes;
+pub mod explain;
+mod export;
 pub mod gmatcher;
 mod machine;
+pub mod prefilter;
+mod reverse;
+mod serialize;
 pub mod simple_machines;
 
 /// The machine is top-level gex API, so exposing it here.
+pub use export::GraphFormat;
 pub use machine::*;
+pub use serialize::DecodeError;
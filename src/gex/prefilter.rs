@@ -0,0 +1,126 @@
+/// A literal scan run ahead of the automaton.
+///
+/// Prefilters never decide a match on their own; they only rule out positions (or whole inputs)
+/// where the machine could not possibly match, so the machine runs less often.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Prefilter {
+    /// Every match starts with this literal.
+    Prefix(String),
+    /// Every match starts with one of these literals.
+    Prefixes(Prefixes),
+    /// Every match contains this literal somewhere.
+    Inner(String),
+}
+
+/// Set of prefix literals along with a lookup table of their leading bytes.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Prefixes {
+    literals: Vec<String>,
+    first_bytes: Vec<bool>,
+}
+
+impl Prefixes {
+    pub fn new(literals: Vec<String>) -> Self {
+        let mut first_bytes = vec![false; 256];
+        for literal in literals.iter() {
+            if let Some(&byte) = literal.as_bytes().first() {
+                first_bytes[byte as usize] = true;
+            }
+        }
+        Prefixes {
+            literals,
+            first_bytes,
+        }
+    }
+
+    pub fn literals(&self) -> &[String] {
+        &self.literals
+    }
+
+    fn find(&self, haystack: &str, at: usize) -> Option<usize> {
+        let bytes = haystack.as_bytes();
+        (at..bytes.len()).find(|&idx| {
+            self.first_bytes[bytes[idx] as usize]
+                && self
+                    .literals
+                    .iter()
+                    .any(|literal| bytes[idx..].starts_with(literal.as_bytes()))
+        })
+    }
+}
+
+impl Prefilter {
+    /// Prefilter for a set of prefix literals, picking the single-literal scan when possible.
+    pub fn from_prefixes(mut literals: Vec<String>) -> Option<Self> {
+        if literals.is_empty() || literals.iter().any(String::is_empty) {
+            return None;
+        }
+        literals.sort();
+        literals.dedup();
+        if literals.len() == 1 {
+            literals.pop().map(Prefilter::Prefix)
+        } else {
+            Some(Prefilter::Prefixes(Prefixes::new(literals)))
+        }
+    }
+
+    /// Whether the prefilter can point at the exact positions where matches may start.
+    ///
+    /// When `false` the prefilter can only reject whole inputs.
+    pub fn is_prefix(&self) -> bool {
+        !matches!(self, Prefilter::Inner(_))
+    }
+
+    /// Finds the first position at or after `at` where a match could begin.
+    pub fn find_candidate(&self, haystack: &str, at: usize) -> Option<usize> {
+        match self {
+            Prefilter::Prefix(literal) => haystack[at..].find(literal.as_str()).map(|idx| idx + at),
+            Prefilter::Prefixes(prefixes) => prefixes.find(haystack, at),
+            Prefilter::Inner(literal) => haystack[at..].find(literal.as_str()).map(|_| at),
+        }
+    }
+
+    /// Whether any match could exist in the haystack starting from `at`.
+    pub fn could_match(&self, haystack: &str, at: usize) -> bool {
+        self.find_candidate(haystack, at).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_prefix_candidates() {
+        let prefilter = Prefilter::from_prefixes(vec!["fn ".to_string()]).unwrap();
+
+        assert_eq!(prefilter, Prefilter::Prefix("fn ".to_string()));
+        assert_eq!(prefilter.find_candidate("pub fn main", 0), Some(4));
+        assert_eq!(prefilter.find_candidate("pub fn main", 5), None);
+    }
+
+    #[test]
+    fn test_multiple_prefix_candidates() {
+        let prefilter =
+            Prefilter::from_prefixes(vec!["TODO(".to_string(), "ERROR:".to_string()]).unwrap();
+
+        assert_eq!(prefilter.find_candidate("x ERROR: TODO(", 0), Some(2));
+        assert_eq!(prefilter.find_candidate("x ERROR: TODO(", 3), Some(9));
+        assert_eq!(prefilter.find_candidate("x ERR TODO", 0), None);
+    }
+
+    #[test]
+    fn test_empty_prefix_disables_prefilter() {
+        assert!(Prefilter::from_prefixes(vec!["a".to_string(), "".to_string()]).is_none());
+        assert!(Prefilter::from_prefixes(vec![]).is_none());
+    }
+
+    #[test]
+    fn test_inner_rejects_whole_input() {
+        let prefilter = Prefilter::Inner("needle".to_string());
+
+        assert!(prefilter.could_match("haystack with a needle", 0));
+        assert!(!prefilter.could_match("haystack with a needle", 17));
+        assert_eq!(prefilter.find_candidate("a needle", 1), Some(1));
+    }
+}
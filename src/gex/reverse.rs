@@ -0,0 +1,465 @@
+use crate::gex::cache::Cache;
+use crate::gex::machine::{GexMachine, Next};
+use crate::matcher::{Match, MatchKind};
+use std::mem::swap;
+
+/// Separates, in a list of states, the threads that started at different positions.
+///
+/// Threads are kept in start order, so everything after a mark started later than everything
+/// before it, without the list having to record where any of them started.
+const MARK: usize = usize::MAX;
+
+/// Working set of states for a position-free scan.
+#[derive(Debug, Clone)]
+pub(crate) struct StateList {
+    states: Vec<usize>,
+    seen: Vec<bool>,
+    stack: Vec<usize>,
+}
+
+impl StateList {
+    pub(crate) fn new(size: usize) -> Self {
+        StateList {
+            states: Vec::new(),
+            seen: vec![false; size],
+            stack: Vec::new(),
+        }
+    }
+
+    fn has_states(&self) -> bool {
+        self.states.iter().any(|&state_label| state_label != MARK)
+    }
+
+    fn push_mark(&mut self) {
+        if self.states.last().is_some_and(|&last| last != MARK) {
+            self.states.push(MARK);
+        }
+    }
+
+    fn clear(&mut self) {
+        for &state_label in self
+            .states
+            .iter()
+            .filter(|&&state_label| state_label != MARK)
+        {
+            self.seen[state_label] = false;
+        }
+        self.states.clear();
+    }
+
+    /// Empty the list for a machine with `size` states, growing it only when needed.
+    fn reset(&mut self, size: usize) {
+        self.clear();
+        if size > self.seen.len() {
+            self.seen.resize(size, false);
+        }
+    }
+}
+
+/// Searches that run the machine without tracking where matches start, recovering the start
+/// afterwards by running the reversed machine backwards from the end of the match.
+impl GexMachine {
+    /// Follow the zero-width transitions that hold at `position` from `state_label`, adding every
+    /// state reached to the list.
+    ///
+    /// Returns whether the Accept was reached. With `stop_at_accept`, states of lower priority
+    /// than the path to the Accept are left out.
+    fn add_closure(
+        &self,
+        state_label: usize,
+        list: &mut StateList,
+        input: &str,
+        position: usize,
+        stop_at_accept: bool,
+    ) -> bool {
+        let mut accept = false;
+        list.stack.push(state_label);
+
+        while let Some(current) = list.stack.pop() {
+            if current == MARK {
+                accept = true;
+                if stop_at_accept {
+                    list.stack.clear();
+                }
+                continue;
+            }
+            if list.seen[current] {
+                continue;
+            }
+            list.seen[current] = true;
+            list.states.push(current);
+
+            // Reversed so the first transition is explored first
+            for (rule, next) in self.states[current].transitions.iter().rev() {
+                if !rule.is_zero_width() || !self.evaluate_assertion(rule, input, position) {
+                    continue;
+                }
+                // The Accept is stacked as a mark so it is reached in priority order
+                match next {
+                    Next::Target(target) => list.stack.push(*target),
+                    Next::Accept => list.stack.push(MARK),
+                }
+            }
+        }
+        accept
+    }
+
+    /// Consume a character from every state of `current`, filling `next` with the closure, at
+    /// `position`, of the states reached.
+    ///
+    /// With a `kind`, threads that can no longer win once a thread accepts are dropped: those
+    /// that started later under leftmost-longest, and every lower priority thread under
+    /// leftmost-first. Returns whether the Accept was reached.
+    fn step_states(
+        &self,
+        current: &StateList,
+        next: &mut StateList,
+        input: &str,
+        input_char: char,
+        position: usize,
+        kind: Option<MatchKind>,
+    ) -> bool {
+        let mut accepted = false;
+        let stop_at_accept = kind == Some(MatchKind::LeftmostFirst);
+        next.clear();
+
+        let class = self.class_of(input_char);
+
+        for &state_label in current.states.iter() {
+            if state_label == MARK {
+                if accepted && kind.is_some() {
+                    break;
+                }
+                next.push_mark();
+                continue;
+            }
+            if accepted && stop_at_accept {
+                break;
+            }
+
+            for target in self.consuming_targets(state_label, input_char, class) {
+                match target {
+                    Next::Target(target) => {
+                        accepted |= self.add_closure(target, next, input, position, stop_at_accept)
+                    }
+                    Next::Accept => accepted = true,
+                }
+                if accepted && stop_at_accept {
+                    break;
+                }
+            }
+        }
+        accepted
+    }
+
+    /// Scan forward for the end of the leftmost match starting at or after `at`.
+    ///
+    /// Threads are grouped by start position; once a group accepts, the groups that started
+    /// later are dropped, while the accepting group keeps running to find the end its match
+    /// kind prefers.
+    fn forward_end(&self, cache: &mut Cache, input: &str, at: usize) -> Option<usize> {
+        let stop_at_accept = self.match_kind == MatchKind::LeftmostFirst;
+        let (mut current, mut next) = (&mut cache.scan_curr, &mut cache.scan_next);
+        current.reset(self.size());
+        next.reset(self.size());
+        let mut end = None;
+        let mut position = at;
+
+        if let Some(prefilter) = &self.prefilter {
+            if !prefilter.could_match(input, at) {
+                return None;
+            }
+        }
+
+        loop {
+            if end.is_none() {
+                if !current.has_states() {
+                    if let Some(prefilter) = self.prefilter.as_ref().filter(|p| p.is_prefix()) {
+                        position = prefilter.find_candidate(input, position)?;
+                    }
+                }
+                // Start a new, lowest priority, group of threads at this position
+                current.push_mark();
+                if self.add_closure(0, current, input, position, stop_at_accept) {
+                    end = Some(position);
+                }
+            }
+
+            let input_char = match input[position..].chars().next() {
+                Some(input_char) => input_char,
+                None => break,
+            };
+
+            if !current.has_states() {
+                if end.is_some() {
+                    break;
+                }
+                position += input_char.len_utf8();
+                continue;
+            }
+
+            position += input_char.len_utf8();
+            let accepted = self.step_states(
+                current,
+                next,
+                input,
+                input_char,
+                position,
+                Some(self.match_kind),
+            );
+            if accepted {
+                end = Some(position);
+            }
+            swap(&mut current, &mut next);
+        }
+
+        end
+    }
+
+    /// Scan a union of machines forward from `at`, setting `matched` for every machine with a
+    /// match starting at or after `at`, given each machine's final state in `finals`.
+    ///
+    /// A new thread starts at every position and none are ever dropped, since each machine
+    /// needs only one match, so the scan stops early only once every machine has matched.
+    pub(crate) fn accepting_finals(
+        &self,
+        cache: &mut Cache,
+        input: &str,
+        at: usize,
+        finals: &[usize],
+        matched: &mut [bool],
+    ) {
+        let (mut current, mut next) = (&mut cache.scan_curr, &mut cache.scan_next);
+        current.reset(self.size());
+        next.reset(self.size());
+        let mut remaining = matched.iter().filter(|&&matched| !matched).count();
+        let mut position = at;
+
+        loop {
+            self.add_closure(0, current, input, position, false);
+            for &state_label in current.states.iter() {
+                if let Ok(idx) = finals.binary_search(&state_label) {
+                    if !matched[idx] {
+                        matched[idx] = true;
+                        remaining -= 1;
+                    }
+                }
+            }
+            if remaining == 0 {
+                return;
+            }
+
+            let input_char = match input[position..].chars().next() {
+                Some(input_char) => input_char,
+                None => return,
+            };
+            position += input_char.len_utf8();
+            self.step_states(current, next, input, input_char, position, None);
+            swap(&mut current, &mut next);
+        }
+    }
+
+    /// Run the reversed machine backwards from `end` to find the leftmost start, no earlier than
+    /// `at`, of a match ending there.
+    fn reverse_start(
+        &self,
+        cache: &mut Cache,
+        input: &str,
+        at: usize,
+        end: usize,
+    ) -> Option<usize> {
+        let (mut current, mut next) = (&mut cache.scan_curr, &mut cache.scan_next);
+        current.reset(self.size());
+        next.reset(self.size());
+        let mut start = None;
+
+        if self.add_closure(0, current, input, end, false) {
+            start = Some(end);
+        }
+
+        for (idx, input_char) in input[at..end].char_indices().rev() {
+            if !current.has_states() {
+                break;
+            }
+            if self.step_states(current, next, input, input_char, at + idx, None) {
+                start = Some(at + idx);
+            }
+            swap(&mut current, &mut next);
+        }
+
+        start
+    }
+
+    /// Whether the machine matches some text starting at `position`, or with `backwards`, some
+    /// text ending there when run over the input in reverse, as a reversed machine would be.
+    pub(super) fn matches_anchored(&self, input: &str, position: usize, backwards: bool) -> bool {
+        let mut current = StateList::new(self.size());
+        let mut next = StateList::new(self.size());
+
+        if self.add_closure(0, &mut current, input, position, true) {
+            return true;
+        }
+
+        if backwards {
+            for (idx, input_char) in input[..position].char_indices().rev() {
+                if !current.has_states() {
+                    break;
+                }
+                if self.step_states(&current, &mut next, input, input_char, idx, None) {
+                    return true;
+                }
+                swap(&mut current, &mut next);
+            }
+        } else {
+            for (idx, input_char) in input[position..].char_indices() {
+                if !current.has_states() {
+                    break;
+                }
+                let new_position = position + idx + input_char.len_utf8();
+                if self.step_states(&current, &mut next, input, input_char, new_position, None) {
+                    return true;
+                }
+                swap(&mut current, &mut next);
+            }
+        }
+        false
+    }
+
+    /// Find the leftmost match with two scans that never track start positions: a forward scan
+    /// for where the match ends, then a backward scan of `reverse` (the machine built by
+    /// `GexMachine::reverse`) for where it starts.
+    ///
+    /// Every match ending there starts at or after the leftmost match, so the longest backward
+    /// run lands on its start under either match kind.
+    pub fn find_with_reverse(&self, reverse: &GexMachine, input: &str, at: usize) -> Option<Match> {
+        self.find_with_reverse_cached(reverse, &mut self.create_cache(), input, at)
+    }
+
+    /// `find_with_reverse`, reusing the scratch space in `cache` instead of allocating.
+    pub fn find_with_reverse_cached(
+        &self,
+        reverse: &GexMachine,
+        cache: &mut Cache,
+        input: &str,
+        at: usize,
+    ) -> Option<Match> {
+        let end = self.forward_end(cache, input, at)?;
+        reverse
+            .reverse_start(cache, input, at, end)
+            .map(|start| Match { start, end })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::compile_machine;
+    use crate::gex::GexMachine;
+    use crate::matcher::Match;
+    use crate::railroad::Ast;
+    use crate::tokenize::tokenize;
+
+    fn machine_for(pattern: &str) -> GexMachine {
+        compile_machine(
+            &Ast::from_tokens(tokenize(pattern).unwrap()).unwrap(),
+            pattern,
+        )
+        .unwrap()
+    }
+
+    fn find_reversed(pattern: &str, input: &str, at: usize) -> Option<Match> {
+        let machine = machine_for(pattern);
+        machine.find_with_reverse(&machine.reverse(), input, at)
+    }
+
+    #[test]
+    fn test_reverse_machine_accepts_reversed_strings() {
+        let machine = machine_for(r"ab+c").reverse();
+
+        assert_eq!(
+            machine.find_with_reverse(&machine.reverse(), "xcbbbay", 0),
+            Some(Match { start: 1, end: 6 })
+        );
+        assert_eq!(
+            machine.find_with_reverse(&machine.reverse(), "abbbc", 0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_match_start_found_backwards() {
+        assert_eq!(
+            find_reversed(r"b+c", "aabbbcd", 0),
+            Some(Match { start: 2, end: 6 })
+        );
+        assert_eq!(
+            find_reversed(r"abcd|c", "xabcd", 0),
+            Some(Match { start: 1, end: 5 })
+        );
+        assert_eq!(
+            find_reversed(r"x*", "abc", 1),
+            Some(Match { start: 1, end: 1 })
+        );
+        assert_eq!(
+            find_reversed(r"[^a]+", "aabca", 0),
+            Some(Match { start: 2, end: 4 })
+        );
+        assert_eq!(
+            find_reversed(r"\w+", "--héllo--", 0),
+            Some(Match { start: 2, end: 8 })
+        );
+        assert_eq!(find_reversed(r"a(b|c)", "xyz", 0), None);
+    }
+
+    #[test]
+    fn test_leftmost_longest_over_earliest_end() {
+        assert_eq!(
+            find_reversed(r"a.*d|bc", "abcd", 0),
+            Some(Match { start: 0, end: 4 })
+        );
+        assert_eq!(
+            find_reversed(r".*d", "my mod in rust", 0),
+            Some(Match { start: 0, end: 6 })
+        );
+        assert_eq!(
+            find_reversed(r"a|ab", "xxab", 1),
+            Some(Match { start: 2, end: 4 })
+        );
+    }
+
+    #[test]
+    fn test_word_boundaries_in_both_directions() {
+        assert_eq!(
+            find_reversed(r"\b\w+\b", "--héllo--", 0),
+            Some(Match { start: 2, end: 8 })
+        );
+        assert_eq!(
+            find_reversed(r"\bcat\b", "concat cat", 0),
+            Some(Match { start: 7, end: 10 })
+        );
+        assert_eq!(
+            find_reversed(r"a\B\w*", "ab a", 0),
+            Some(Match { start: 0, end: 2 })
+        );
+    }
+
+    #[test]
+    fn test_lookarounds_in_both_directions() {
+        assert_eq!(
+            find_reversed(r"(?<=\$)\d+(?=\.)", "$1 and $20.", 0),
+            Some(Match { start: 8, end: 10 })
+        );
+        assert_eq!(
+            find_reversed(r"(?<![ab])b+", "abbb cbb", 0),
+            Some(Match { start: 6, end: 8 })
+        );
+    }
+
+    #[test]
+    fn test_prefiltered_reverse_search() {
+        assert_eq!(
+            find_reversed(r"ERROR: \w+", "ok ERROR: full", 0),
+            Some(Match { start: 3, end: 14 })
+        );
+        assert_eq!(find_reversed(r"ERROR: \w+", "ok ERROR: full", 4), None);
+    }
+}
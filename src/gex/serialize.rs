@@ -0,0 +1,811 @@
+use crate::compile::{Engine, Gex};
+use crate::engines::aho_corasick::AhoCorasick;
+use crate::engines::shift_and::{ShiftAnd, ShiftAndParts};
+use crate::gex::machine::{GexMachine, Look, LookKind, Next, Rule, State};
+use crate::gex::prefilter::{Prefilter, Prefixes};
+use crate::matcher::MatchKind;
+use std::collections::HashMap;
+use std::{error, fmt, io};
+
+/// Bytes every serialized machine starts with.
+const MAGIC: &[u8; 4] = b"GEXM";
+
+/// Bytes every serialized compiled pattern, saved along with the engine searching it, starts
+/// with.
+const GEX_MAGIC: &[u8; 4] = b"GEXP";
+
+/// Version of the layout below; bump it whenever the layout changes, so machines saved by
+/// another version are refused rather than misread.
+pub const FORMAT_VERSION: u16 = 2;
+
+/// Deepest nesting of lookarounds a serialized machine may have.
+const MAX_LOOK_DEPTH: usize = 64;
+
+/// Why bytes could not be loaded as a machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bytes don't start like a serialized machine.
+    UnknownFormat,
+    /// The machine was saved by a version of the format this one can't read.
+    UnsupportedVersion(u16),
+    /// The bytes end before the machine does.
+    Truncated,
+    /// The bytes decode, but not to a machine that can be searched safely.
+    Corrupt(String),
+}
+
+impl error::Error for DecodeError {}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnknownFormat => write!(f, "Unknown Format: not a serialized machine"),
+            DecodeError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported Version: {} (expected {})",
+                version, FORMAT_VERSION
+            ),
+            DecodeError::Truncated => write!(f, "Truncated: the machine ends early"),
+            DecodeError::Corrupt(msg) => write!(f, "Corrupt Machine: {}", msg),
+        }
+    }
+}
+
+impl From<DecodeError> for io::Error {
+    fn from(err: DecodeError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+type Result<T> = std::result::Result<T, DecodeError>;
+
+fn corrupt<T>(msg: impl Into<String>) -> Result<T> {
+    Err(DecodeError::Corrupt(msg.into()))
+}
+
+/// Serialization of finished machines, so patterns compiled once can be loaded on later runs.
+///
+/// The layout is little-endian and versioned. The class table is not stored, since it is
+/// derived from the states; a machine that had one gets it rebuilt on load.
+impl GexMachine {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer(Vec::new());
+        writer.header(MAGIC);
+        writer.machine(self);
+        writer.0
+    }
+
+    /// Load a machine saved by `to_bytes`, checking it is one the searches can run safely.
+    pub fn from_bytes(bytes: &[u8]) -> Result<GexMachine> {
+        let mut reader = Reader { bytes, position: 0 };
+        reader.header(MAGIC)?;
+        let machine = reader.machine(0)?;
+        reader.finish()?;
+        Ok(machine)
+    }
+}
+
+/// Serialization of compiled patterns, saving which engine searches them along with it, so
+/// patterns searched by the automata load as they were compiled.
+///
+/// Limits on searching, like `Gex::with_search_cache_bytes`, aren't saved; a loaded pattern has
+/// the defaults until they are set again.
+impl Gex {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer(Vec::new());
+        writer.header(GEX_MAGIC);
+        match self.engine() {
+            Engine::Nfa { machine, .. } | Engine::Backtrack(machine) => {
+                writer.u8(0);
+                writer.machine(machine);
+            }
+            Engine::AhoCorasick(automaton) => {
+                writer.u8(1);
+                writer.match_kind(automaton.kind());
+                let patterns = automaton.patterns();
+                writer.usize(patterns.len());
+                for pattern in patterns.iter() {
+                    writer.str(pattern);
+                }
+            }
+            Engine::ShiftAnd(automaton) => {
+                writer.u8(2);
+                let parts = automaton.to_parts();
+                writer.match_kind(parts.kind);
+                writer.usize(parts.classes.len());
+                for (class, follow) in parts.classes.iter().zip(parts.follow) {
+                    writer.state(class);
+                    writer.u64(follow);
+                }
+                writer.u64(parts.first);
+                writer.u64(parts.last);
+                writer.bool(parts.nullable);
+                writer.usize(parts.max_length);
+            }
+        }
+        writer.0
+    }
+
+    /// Load a pattern saved by `to_bytes`, checking its engine is one the searches can run
+    /// safely.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Gex> {
+        let mut reader = Reader { bytes, position: 0 };
+        reader.header(GEX_MAGIC)?;
+        let gex = match reader.u8()? {
+            // The machine is searched by backtracking or not as it was compiled, since that
+            // follows from the machine itself
+            0 => Gex::from(reader.machine(0)?),
+            1 => {
+                let kind = reader.match_kind()?;
+                let pattern_count = reader.count()?;
+                let patterns = (0..pattern_count)
+                    .map(|_| reader.str())
+                    .collect::<Result<Vec<_>>>()?;
+                Gex::from(AhoCorasick::new(&patterns, kind))
+            }
+            2 => {
+                let kind = reader.match_kind()?;
+                let position_count = reader.count()?;
+                let mut classes = Vec::with_capacity(position_count);
+                let mut follow = Vec::with_capacity(position_count);
+                for _ in 0..position_count {
+                    let class = reader.state()?;
+                    for (rule, _) in class.transitions.iter() {
+                        check_code_points(rule)?;
+                    }
+                    classes.push(class);
+                    follow.push(reader.u64()?);
+                }
+                let parts = ShiftAndParts {
+                    classes,
+                    follow,
+                    first: reader.u64()?,
+                    last: reader.u64()?,
+                    nullable: reader.bool()?,
+                    max_length: reader.usize()?,
+                    kind,
+                };
+                match ShiftAnd::from_parts(parts) {
+                    Some(automaton) => Gex::from(automaton),
+                    None => return corrupt("automaton refers to positions it doesn't have"),
+                }
+            }
+            other => return corrupt(format!("unknown engine {}", other)),
+        };
+        reader.finish()?;
+        Ok(gex)
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn header(&mut self, magic: &[u8; 4]) {
+        self.0.extend_from_slice(magic);
+        self.u16(FORMAT_VERSION);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    fn str(&mut self, value: &str) {
+        self.usize(value.len());
+        self.0.extend_from_slice(value.as_bytes());
+    }
+
+    fn match_kind(&mut self, kind: MatchKind) {
+        self.u8(match kind {
+            MatchKind::LeftmostFirst => 0,
+            MatchKind::LeftmostLongest => 1,
+        });
+    }
+
+    fn machine(&mut self, machine: &GexMachine) {
+        self.match_kind(machine.match_kind);
+        self.usize(machine.group_count);
+
+        self.usize(machine.states.len());
+        for state in machine.states.iter() {
+            self.state(state);
+        }
+
+        self.usize(machine.looks.len());
+        for look in machine.looks.iter() {
+            let (tag, positive) = match look.kind {
+                LookKind::Ahead(positive) => (0, positive),
+                LookKind::Behind(positive) => (1, positive),
+            };
+            self.u8(tag);
+            self.bool(positive);
+            self.machine(&look.machine);
+        }
+
+        // Sorted, so the same machine always serializes to the same bytes
+        let mut subroutines: Vec<_> = machine.subroutines.iter().collect();
+        subroutines.sort();
+        self.usize(subroutines.len());
+        for (&group_number, &entry) in subroutines {
+            self.u16(group_number);
+            self.usize(entry);
+        }
+
+        let mut names: Vec<_> = machine.group_names().into_iter().flatten().collect();
+        names.sort();
+        self.usize(names.len());
+        for (name, &group_number) in names {
+            self.str(name);
+            self.u16(group_number);
+        }
+
+        match &machine.prefilter {
+            None => self.u8(0),
+            Some(Prefilter::Prefix(literal)) => {
+                self.u8(1);
+                self.str(literal);
+            }
+            Some(Prefilter::Prefixes(prefixes)) => {
+                self.u8(2);
+                self.usize(prefixes.literals().len());
+                for literal in prefixes.literals() {
+                    self.str(literal);
+                }
+            }
+            Some(Prefilter::Inner(literal)) => {
+                self.u8(3);
+                self.str(literal);
+            }
+        }
+        self.bool(machine.dense.is_some());
+    }
+
+    fn state(&mut self, state: &State) {
+        self.bool(state.short_circuit());
+        self.usize(state.transitions.len());
+        for (rule, next) in state.transitions.iter() {
+            self.rule(rule);
+            match next {
+                Next::Target(target) => {
+                    self.u8(0);
+                    self.usize(*target);
+                }
+                Next::Accept => self.u8(1),
+            }
+        }
+    }
+
+    fn rule(&mut self, rule: &Rule) {
+        match *rule {
+            Rule::Range(start, end, positive) => {
+                self.u8(0);
+                self.u32(start);
+                self.u32(end);
+                self.bool(positive);
+            }
+            Rule::Not(value) => {
+                self.u8(1);
+                self.u32(value);
+            }
+            Rule::IsWord(positive) => {
+                self.u8(2);
+                self.bool(positive);
+            }
+            Rule::IsDigit(positive) => {
+                self.u8(3);
+                self.bool(positive);
+            }
+            Rule::IsWhitespace(positive) => {
+                self.u8(4);
+                self.bool(positive);
+            }
+            Rule::WordBoundary(positive) => {
+                self.u8(5);
+                self.bool(positive);
+            }
+            Rule::Backreference(group_number) => {
+                self.u8(6);
+                self.u16(group_number);
+            }
+            Rule::Look(look_idx) => {
+                self.u8(7);
+                self.usize(look_idx);
+            }
+            Rule::AtomicOpen => self.u8(8),
+            Rule::AtomicClose => self.u8(9),
+            Rule::Call(group_number) => {
+                self.u8(10);
+                self.u16(group_number);
+            }
+            Rule::Save(slot) => {
+                self.u8(11);
+                self.usize(slot);
+            }
+            Rule::Null => self.u8(12),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn header(&mut self, magic: &[u8; 4]) -> Result<()> {
+        if self.take(magic.len()).ok() != Some(magic.as_slice()) {
+            return Err(DecodeError::UnknownFormat);
+        }
+        let version = self.u16()?;
+        if version != FORMAT_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<()> {
+        if self.position != self.bytes.len() {
+            return corrupt("trailing bytes after the machine");
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or(DecodeError::Truncated)?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => corrupt(format!("{} is not a boolean", other)),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        let value = self.u64()?;
+        usize::try_from(value).or_else(|_| corrupt(format!("{} is too large", value)))
+    }
+
+    /// Length of a list, which can't exceed the bytes left since each item takes at least one.
+    fn count(&mut self) -> Result<usize> {
+        let count = self.usize()?;
+        if count > self.bytes.len() - self.position {
+            return Err(DecodeError::Truncated);
+        }
+        Ok(count)
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let length = self.count()?;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec()).or_else(|_| corrupt("literal is not UTF-8"))
+    }
+
+    fn machine(&mut self, depth: usize) -> Result<GexMachine> {
+        if depth > MAX_LOOK_DEPTH {
+            return corrupt("lookarounds nested too deeply");
+        }
+        let match_kind = self.match_kind()?;
+        let group_count = self.usize()?;
+
+        let state_count = self.count()?;
+        let mut states = Vec::with_capacity(state_count);
+        for _ in 0..state_count {
+            states.push(self.state()?);
+        }
+
+        let look_count = self.count()?;
+        let mut looks = Vec::with_capacity(look_count);
+        for _ in 0..look_count {
+            let kind = match (self.u8()?, self.bool()?) {
+                (0, positive) => LookKind::Ahead(positive),
+                (1, positive) => LookKind::Behind(positive),
+                (other, _) => return corrupt(format!("unknown lookaround kind {}", other)),
+            };
+            let machine = self.machine(depth + 1)?;
+            looks.push(Look { kind, machine });
+        }
+
+        let subroutine_count = self.count()?;
+        let mut subroutines = HashMap::with_capacity(subroutine_count);
+        for _ in 0..subroutine_count {
+            let group_number = self.u16()?;
+            let entry = self.usize()?;
+            subroutines.insert(group_number, entry);
+        }
+
+        let name_count = self.count()?;
+        let mut names = HashMap::with_capacity(name_count);
+        for _ in 0..name_count {
+            let name = self.str()?;
+            names.insert(name, self.u16()?);
+        }
+
+        let prefilter = match self.u8()? {
+            0 => None,
+            1 => Some(Prefilter::Prefix(self.str()?)),
+            2 => {
+                let literal_count = self.count()?;
+                let literals = (0..literal_count)
+                    .map(|_| self.str())
+                    .collect::<Result<Vec<_>>>()?;
+                Some(Prefilter::Prefixes(Prefixes::new(literals)))
+            }
+            3 => Some(Prefilter::Inner(self.str()?)),
+            other => return corrupt(format!("unknown prefilter kind {}", other)),
+        };
+        let dense = self.bool()?;
+
+        let mut machine = GexMachine::from_states(states)
+            .with_match_kind(match_kind)
+            .with_prefilter(prefilter)
+            .with_group_names(names);
+        machine.group_count = group_count;
+        machine.looks = looks;
+        machine.subroutines = subroutines;
+        validate(&machine)?;
+        Ok(match dense {
+            true => machine.with_dense_table(),
+            false => machine,
+        })
+    }
+
+    fn match_kind(&mut self) -> Result<MatchKind> {
+        match self.u8()? {
+            0 => Ok(MatchKind::LeftmostFirst),
+            1 => Ok(MatchKind::LeftmostLongest),
+            other => corrupt(format!("unknown match kind {}", other)),
+        }
+    }
+
+    fn state(&mut self) -> Result<State> {
+        let short_circuit = self.bool()?;
+        let transition_count = self.count()?;
+        let mut transitions = Vec::with_capacity(transition_count);
+        for _ in 0..transition_count {
+            let rule = self.rule()?;
+            let next = match self.u8()? {
+                0 => Next::Target(self.usize()?),
+                1 => Next::Accept,
+                other => return corrupt(format!("unknown transition target kind {}", other)),
+            };
+            transitions.push((rule, next));
+        }
+        Ok(match short_circuit {
+            true => State::short_circuit_from_transitions(transitions),
+            false => State::from_transitions(transitions),
+        })
+    }
+
+    fn rule(&mut self) -> Result<Rule> {
+        Ok(match self.u8()? {
+            0 => Rule::Range(self.u32()?, self.u32()?, self.bool()?),
+            1 => Rule::Not(self.u32()?),
+            2 => Rule::IsWord(self.bool()?),
+            3 => Rule::IsDigit(self.bool()?),
+            4 => Rule::IsWhitespace(self.bool()?),
+            5 => Rule::WordBoundary(self.bool()?),
+            6 => Rule::Backreference(self.u16()?),
+            7 => Rule::Look(self.usize()?),
+            8 => Rule::AtomicOpen,
+            9 => Rule::AtomicClose,
+            10 => Rule::Call(self.u16()?),
+            11 => Rule::Save(self.usize()?),
+            12 => Rule::Null,
+            other => return corrupt(format!("unknown rule {}", other)),
+        })
+    }
+}
+
+/// Check that everything the machine's transitions refer to exists, so searching it can't
+/// index out of bounds.
+fn validate(machine: &GexMachine) -> Result<()> {
+    if machine.states.is_empty() {
+        return corrupt("machine has no states");
+    }
+    let group_count = machine.group_count;
+    if group_count > u16::MAX as usize {
+        return corrupt(format!(
+            "{} groups is more than a pattern can have",
+            group_count
+        ));
+    }
+
+    for (&group_number, &entry) in machine.subroutines.iter() {
+        if group_number == 0 || group_number as usize > group_count {
+            return corrupt(format!("subroutine for missing group {}", group_number));
+        }
+        if entry >= machine.size() {
+            return corrupt(format!("subroutine entry {} is not a state", entry));
+        }
+    }
+
+    for (name, &group_number) in machine.group_names().into_iter().flatten() {
+        if group_number == 0 || group_number as usize > group_count {
+            return corrupt(format!(
+                "name {:?} for missing group {}",
+                name, group_number
+            ));
+        }
+    }
+
+    for (rule, next) in machine
+        .states
+        .iter()
+        .flat_map(|state| state.transitions.iter())
+    {
+        if let Next::Target(target) = next {
+            if *target >= machine.size() {
+                return corrupt(format!("transition to missing state {}", target));
+            }
+        }
+        check_code_points(rule)?;
+        match *rule {
+            Rule::Backreference(group_number) if group_number as usize > group_count => {
+                return corrupt(format!("backreference to missing group {}", group_number));
+            }
+            Rule::Call(group_number) if machine.subroutine_entry(group_number).is_none() => {
+                return corrupt(format!("call to missing group {}", group_number));
+            }
+            Rule::Look(look_idx) if look_idx >= machine.looks.len() => {
+                return corrupt(format!("missing lookaround {}", look_idx));
+            }
+            Rule::Save(slot) if slot >= 2 * (group_count + 1) => {
+                return corrupt(format!("save to missing slot {}", slot));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Check the characters a rule tests for are ones a `char` can hold.
+fn check_code_points(rule: &Rule) -> Result<()> {
+    match *rule {
+        Rule::Range(start, end, _) if start > end || end > char::MAX as u32 => {
+            corrupt(format!("invalid range {}..={}", start, end))
+        }
+        Rule::Not(value) if value > char::MAX as u32 => {
+            corrupt(format!("invalid code point {}", value))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::{compile, compile_with, CompileOptions};
+    use crate::matcher::{Match, Matcher};
+
+    fn compiled_machine(pattern: &str) -> GexMachine {
+        compile(pattern).unwrap().machine().unwrap().clone()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let patterns = [
+            r"(\w+)@(\w+)\.com",
+            r"(?<=x)y\b|(?!z)\d+",
+            r"(a|b)\1",
+            r"(?>a+)b",
+            r"(\((?:[^()]|(?1))*\))",
+            r"fn [a-z]{2,4}",
+            r"(?<user>\w+)@(?<host>\w+)",
+        ];
+        let inputs = [
+            "mail me@example.com",
+            "xy 12 z3",
+            "abba",
+            "aab",
+            "f((a)b)",
+            "fn main",
+        ];
+        for pattern in patterns {
+            let machine = compiled_machine(pattern);
+            let loaded = GexMachine::from_bytes(&machine.to_bytes()).unwrap();
+            assert_eq!(loaded, machine, "{pattern}");
+            assert_eq!(loaded.to_bytes(), machine.to_bytes(), "{pattern}");
+
+            let (original, loaded) = (Gex::from(machine), Gex::from(loaded));
+            for input in inputs {
+                assert_eq!(
+                    loaded.captures(input),
+                    original.captures(input),
+                    "{pattern}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_engines_round_trip() {
+        let cases = [
+            (r"a\.b|cd|ab", "aho-corasick", "xab cd a.b"),
+            (r"a|aa|b", "aho-corasick", "caab"),
+            (r"\d\d-[a-z]?x", "shift-and", "ref 12-x and 34-yx"),
+            (r"(\w+)@(\w+)", "nfa", "mail me@home"),
+            (r"(a|b)\1", "backtrack", "abba"),
+        ];
+        for kind in [MatchKind::LeftmostFirst, MatchKind::LeftmostLongest] {
+            let options = CompileOptions {
+                match_kind: kind,
+                ..CompileOptions::default()
+            };
+            for (pattern, engine, input) in cases {
+                let gex = compile_with(pattern, &options).unwrap();
+                let loaded = Gex::from_bytes(&gex.to_bytes()).unwrap();
+                assert_eq!(loaded.engine_name(), gex.engine_name(), "{pattern}");
+                assert_eq!(loaded.to_bytes(), gex.to_bytes(), "{pattern}");
+                assert_eq!(loaded.match_kind(), kind);
+                assert_eq!(loaded.captures(input), gex.captures(input), "{pattern}");
+                if kind == MatchKind::LeftmostLongest {
+                    assert_eq!(gex.engine_name(), engine, "{pattern}");
+                }
+            }
+        }
+
+        // Machines and whole patterns aren't mistaken for one another
+        let gex = compile(r"(a+)b").unwrap();
+        assert_eq!(
+            GexMachine::from_bytes(&gex.to_bytes()),
+            Err(DecodeError::UnknownFormat)
+        );
+        assert!(matches!(
+            Gex::from_bytes(&gex.machine().unwrap().to_bytes()),
+            Err(DecodeError::UnknownFormat)
+        ));
+    }
+
+    #[test]
+    fn test_incompatible_files() {
+        let bytes = compiled_machine(r"(a+)b").to_bytes();
+
+        assert_eq!(
+            GexMachine::from_bytes(b"not a machine"),
+            Err(DecodeError::UnknownFormat)
+        );
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            GexMachine::from_bytes(&newer),
+            Err(DecodeError::UnsupportedVersion(FORMAT_VERSION + 1))
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            GexMachine::from_bytes(&trailing),
+            Err(DecodeError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_corrupt_files_are_refused() {
+        let bytes = compiled_machine(r"(?<=x)(a|\1b)+\b").to_bytes();
+
+        for length in 0..bytes.len() {
+            assert!(GexMachine::from_bytes(&bytes[..length]).is_err());
+        }
+        // Whatever a flipped byte decodes to, neither loading nor searching it may panic or hang
+        let search = |gex: Gex| {
+            for input in ["", "xab", "xaab aab b", "12-x 34-yx"] {
+                let _ = (gex.find(input), gex.captures(input));
+            }
+        };
+        for idx in 0..bytes.len() {
+            for flip in [0x01, 0x80, 0xff] {
+                let mut damaged = bytes.clone();
+                damaged[idx] ^= flip;
+                if let Ok(machine) = GexMachine::from_bytes(&damaged) {
+                    search(Gex::from(machine));
+                }
+            }
+        }
+        for pattern in [r"(?<=x)(a|b)+\b", r"ab|xa|b", r"\d\d-[a-z]?x"] {
+            let bytes = compile(pattern).unwrap().to_bytes();
+            for idx in 0..bytes.len() {
+                for flip in [0x01, 0x80, 0xff] {
+                    let mut damaged = bytes.clone();
+                    damaged[idx] ^= flip;
+                    if let Ok(gex) = Gex::from_bytes(&damaged) {
+                        search(gex);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_zero_width_cycles() {
+        // Compiled machines have them too, like the loop of `(?:\b)*`, so they are searched
+        // rather than refused, by either engine
+        for opener in [Rule::Null, Rule::AtomicOpen] {
+            let closer = match opener {
+                Rule::AtomicOpen => Rule::AtomicClose,
+                _ => Rule::Null,
+            };
+            let machine = GexMachine::from_states(vec![
+                State::from_transitions(vec![
+                    (Rule::Null, Next::Target(0)),
+                    (Rule::WordBoundary(true), Next::Target(0)),
+                    (opener, Next::Target(1)),
+                ]),
+                State::from_transitions(vec![(Rule::Range(97, 97, true), Next::Target(2))]),
+                State::from_transitions(vec![(closer, Next::Accept)]),
+            ]);
+            let gex = Gex::from(GexMachine::from_bytes(&machine.to_bytes()).unwrap());
+            assert_eq!(gex.find("ba"), Some(Match { start: 1, end: 2 }));
+            assert_eq!(gex.find("bb"), None);
+        }
+    }
+
+    #[test]
+    fn test_dangling_references() {
+        let machine = GexMachine::from_states(vec![
+            State::from_transitions(vec![(Rule::Save(2), Next::Target(1))]),
+            State::accept_state(),
+        ]);
+        assert!(matches!(
+            GexMachine::from_bytes(&machine.to_bytes()),
+            Err(DecodeError::Corrupt(_))
+        ));
+
+        let machine = GexMachine::from_states(vec![
+            State::from_transitions(vec![(Rule::Null, Next::Target(2))]),
+            State::accept_state(),
+        ]);
+        assert!(matches!(
+            GexMachine::from_bytes(&machine.to_bytes()),
+            Err(DecodeError::Corrupt(_))
+        ));
+        let machine =
+            GexMachine::default().with_group_names(HashMap::from([("name".to_string(), 1)]));
+        assert!(matches!(
+            GexMachine::from_bytes(&machine.to_bytes()),
+            Err(DecodeError::Corrupt(_))
+        ));
+    }
+}
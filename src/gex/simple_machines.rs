@@ -1,4 +1,4 @@
-use crate::gex::machine::{GexMachine, Next, Rule, State, Transition};
+use crate::gex::machine::{GexMachine, Look, LookKind, Next, Rule, State, Transition};
 use crate::tokenize::Token;
 
 pub fn machine_for(token: Token, input: &str) -> GexMachine {
@@ -24,6 +24,46 @@ pub fn machine_for_character(character: char) -> GexMachine {
     ])
 }
 
+/// Machine for a single rule that doesn't consume a character class, such as an assertion.
+fn single_rule_machine(rule: Rule) -> GexMachine {
+    GexMachine::from_states(vec![
+        State::from_transitions(vec![(Rule::Null, Next::Target(1))]),
+        State::from_transitions(vec![(rule, Next::Target(2))]),
+        State::accept_state(),
+    ])
+}
+
+pub fn word_boundary_machine(positive: bool) -> GexMachine {
+    single_rule_machine(Rule::WordBoundary(positive))
+}
+
+pub fn backreference_machine(group_number: u16) -> GexMachine {
+    single_rule_machine(Rule::Backreference(group_number))
+}
+
+/// Machine asserting that `body` matches ahead of, or behind, the current position.
+pub fn lookaround_machine(kind: LookKind, body: GexMachine) -> GexMachine {
+    let machine = match kind {
+        LookKind::Ahead(_) => body,
+        LookKind::Behind(_) => body.reverse(),
+    }
+    .with_dense_table();
+    let mut lookaround = single_rule_machine(Rule::Look(0));
+    lookaround.looks.push(Look { kind, machine });
+    lookaround
+}
+
+pub fn call_machine(group_number: u16) -> GexMachine {
+    single_rule_machine(Rule::Call(group_number))
+}
+
+/// Machine matching `body` as an atomic group.
+pub fn atomic_machine(body: GexMachine) -> GexMachine {
+    single_rule_machine(Rule::AtomicOpen)
+        .cons(body)
+        .cons(single_rule_machine(Rule::AtomicClose))
+}
+
 pub fn wildcard_machine() -> GexMachine {
     GexMachine::from_states(vec![
         State::from_transitions(vec![(Rule::Null, Next::Target(1))]),
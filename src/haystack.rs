@@ -0,0 +1,195 @@
+use crate::matcher::Match;
+use std::collections::VecDeque;
+use std::ops::Range;
+
+/// Text held as successive slices rather than one string, such as the chunks of a rope or a
+/// list of buffers.
+///
+/// Offsets into a haystack, and those of the matches found in it, count bytes from the start of
+/// its first slice, as if the slices were one string.
+pub trait Haystack {
+    /// The slices of the text, in order.
+    fn chunks(&self) -> impl Iterator<Item = &str>;
+
+    /// The whole text as one string, for searches that can't work a slice at a time.
+    fn to_text(&self) -> String {
+        self.chunks().collect()
+    }
+
+    /// Copy of the text in the range, widened or narrowed at either end to the nearest
+    /// character boundary inside it and clamped to the text, along with where the copy starts.
+    fn slice(&self, range: Range<usize>) -> (usize, String) {
+        let mut bytes = Vec::with_capacity(range.len());
+        let mut offset = 0;
+        for chunk in self.chunks() {
+            let chunk_range = offset..offset + chunk.len();
+            offset += chunk.len();
+            if chunk_range.end <= range.start {
+                continue;
+            }
+            if chunk_range.start >= range.end {
+                break;
+            }
+            let start = range.start.max(chunk_range.start) - chunk_range.start;
+            let end = range.end.min(chunk_range.end) - chunk_range.start;
+            bytes.extend_from_slice(&chunk.as_bytes()[start..end]);
+        }
+
+        // Drop the pieces of characters cut by the range
+        let is_continuation = |byte: &u8| byte & 0b1100_0000 == 0b1000_0000;
+        let leading = bytes
+            .iter()
+            .take_while(|byte| is_continuation(byte))
+            .count();
+        let mut text = bytes.split_off(leading);
+        while std::str::from_utf8(&text).is_err() {
+            text.pop();
+        }
+        (
+            range.start + leading,
+            String::from_utf8(text).expect("trimmed to whole characters"),
+        )
+    }
+}
+
+impl Haystack for str {
+    fn chunks(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self)
+    }
+}
+
+impl Haystack for String {
+    fn chunks(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.as_str())
+    }
+}
+
+impl<S: AsRef<str>> Haystack for [S] {
+    fn chunks(&self) -> impl Iterator<Item = &str> {
+        self.iter().map(AsRef::as_ref)
+    }
+}
+
+impl<S: AsRef<str>> Haystack for Vec<S> {
+    fn chunks(&self) -> impl Iterator<Item = &str> {
+        self.iter().map(AsRef::as_ref)
+    }
+}
+
+impl<S: AsRef<str>> Haystack for VecDeque<S> {
+    fn chunks(&self) -> impl Iterator<Item = &str> {
+        self.iter().map(AsRef::as_ref)
+    }
+}
+
+/// Call `matched` with the matches in the haystack from `at`, until it returns false, for a
+/// search whose matches are at most `max_match_bytes` long and need no text around them.
+///
+/// `find_at` searches a window of the haystack's text, which holds no more than the slice being
+/// searched and the last `max_match_bytes` of the text before it, since no match that could
+/// still be found starts any earlier.
+pub(crate) fn try_windowed_matches<H, S, F, E>(
+    haystack: &H,
+    at: usize,
+    max_match_bytes: usize,
+    find_at: S,
+    mut matched: F,
+) -> Result<(), E>
+where
+    H: Haystack + ?Sized,
+    S: Fn(&str, usize) -> Option<Match>,
+    F: FnMut(Match) -> Result<bool, E>,
+{
+    let mut window = String::new();
+    // Offset in the haystack of the start of the window
+    let mut window_start = 0;
+    let mut search_from = at;
+    let mut chunks = haystack.chunks().peekable();
+
+    while let Some(chunk) = chunks.next() {
+        if window.is_empty() && window_start + chunk.len() <= search_from {
+            window_start += chunk.len();
+            continue;
+        }
+        window.push_str(chunk);
+        let at_end = chunks.peek().is_none();
+
+        let keep_from = loop {
+            let relative_from = search_from - window_start;
+            if relative_from > window.len() {
+                break window.len();
+            }
+            let found = match find_at(&window, relative_from) {
+                Some(found) => found,
+                // A match may yet start in the last `max_match_bytes` of the window
+                None => break relative_from.max(window.len().saturating_sub(max_match_bytes)),
+            };
+            // Every match that could start before this one fits in the window
+            if !at_end && found.start + max_match_bytes > window.len() {
+                break found.start;
+            }
+            if !matched(found.shift(window_start))? {
+                return Ok(());
+            }
+            search_from = window_start + found.end;
+            if found.start == found.end {
+                search_from += window[found.end..].chars().next().map_or(1, char::len_utf8);
+            }
+        };
+
+        let mut keep_from = keep_from.min(window.len());
+        while !window.is_char_boundary(keep_from) {
+            keep_from -= 1;
+        }
+        window.drain(..keep_from);
+        window_start += keep_from;
+        search_from = search_from.max(window_start);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slices() {
+        let rope = vec!["hé", "llo ", "", "wörld"];
+        assert_eq!(rope.to_text(), "héllo wörld");
+        assert_eq!(rope.slice(1..9), (1, "éllo w".to_string()));
+        // Ranges that cut characters are narrowed to the whole characters inside them
+        assert_eq!(rope.slice(2..9), (3, "llo w".to_string()));
+        assert_eq!(rope.slice(7..9), (7, "w".to_string()));
+        assert_eq!(rope.slice(10..100), (10, "rld".to_string()));
+        assert_eq!("abc".slice(0..2), (0, "ab".to_string()));
+    }
+
+    #[test]
+    fn test_windowed_matches() {
+        let rope = ["xab", "cx", "abc", "x", "a", "bc"];
+        let text = rope.to_text();
+        let find_at = |input: &str, at: usize| {
+            input[at..].find("abc").map(|start| Match {
+                start: at + start,
+                end: at + start + 3,
+            })
+        };
+
+        let mut found = Vec::new();
+        try_windowed_matches(&rope[..], 0, 3, find_at, |matched| {
+            found.push(matched.substr(&text).to_string());
+            found.push(format!("{}", matched.start));
+            Ok::<bool, ()>(true)
+        })
+        .unwrap();
+        assert_eq!(found, vec!["abc", "1", "abc", "5", "abc", "9"]);
+
+        let mut first = None;
+        try_windowed_matches(&rope[..], 2, 3, find_at, |matched| {
+            first = Some(matched);
+            Ok::<bool, ()>(false)
+        })
+        .unwrap();
+        assert_eq!(first, Some(Match { start: 5, end: 8 }));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::engines::backtrack::BacktrackError;
+use crate::haystack::Haystack;
+use crate::matcher::{Match, Matcher};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Bytes of input searched between checks of an interrupt.
+pub(crate) const CHECK_INTERVAL: usize = 64 * 1024;
+
+/// Flag asking searches to stop.
+///
+/// Clones share the flag, so one can be handed to a search while another is kept to cancel it
+/// from elsewhere, such as another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// When a search should give up: once its token is cancelled, or once its deadline passes.
+///
+/// The default never interrupts.
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt {
+    token: Option<CancellationToken>,
+    deadline: Option<Instant>,
+}
+
+impl Interrupt {
+    pub fn new() -> Self {
+        Interrupt::default()
+    }
+
+    pub fn with_token(mut self, token: CancellationToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Give up once the time has passed, counting from now.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.token
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// What a search that may be interrupted found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome<T> {
+    /// The search ran to the end of the input.
+    Complete(T),
+    /// The search was interrupted, with what it found before then.
+    Cancelled(T),
+    /// The search gave up on its own, such as a backtracking search past its step limit, with
+    /// what it found before then and why.
+    GaveUp(T, BacktrackError),
+}
+
+impl<T> Outcome<T> {
+    pub fn is_complete(&self) -> bool {
+        matches!(self, Outcome::Complete(_))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, Outcome::Cancelled(_))
+    }
+
+    /// Why the search gave up, if it did.
+    pub fn error(&self) -> Option<BacktrackError> {
+        match self {
+            Outcome::GaveUp(_, error) => Some(*error),
+            Outcome::Complete(_) | Outcome::Cancelled(_) => None,
+        }
+    }
+
+    /// What was found, whether or not the search was interrupted or gave up.
+    pub fn into_inner(self) -> T {
+        match self {
+            Outcome::Complete(found) | Outcome::Cancelled(found) | Outcome::GaveUp(found, _) => {
+                found
+            }
+        }
+    }
+}
+
+/// Every match from `at`, checking the interrupt before each search for the next one.
+pub(crate) fn find_all_between_matches<M: Matcher + ?Sized>(
+    matcher: &M,
+    input: &str,
+    at: usize,
+    interrupt: &Interrupt,
+) -> Outcome<Vec<Match>> {
+    find_all_with(input, at, interrupt, |at| {
+        Outcome::Complete(matcher.find_at(input, at))
+    })
+}
+
+/// Every match `find` turns up from `at`, moving on as `Matcher::try_find_iter_at` does and
+/// checking the interrupt before each search; `find` may also give up on a search itself, once
+/// the interrupt fires or for reasons of its own.
+pub(crate) fn find_all_with<F>(
+    input: &str,
+    at: usize,
+    interrupt: &Interrupt,
+    mut find: F,
+) -> Outcome<Vec<Match>>
+where
+    F: FnMut(usize) -> Outcome<Option<Match>>,
+{
+    let mut found = Vec::new();
+    let mut next_at = at;
+    loop {
+        if interrupt.is_interrupted() {
+            return Outcome::Cancelled(found);
+        }
+        let next = match find(next_at) {
+            Outcome::Complete(Some(next)) => next,
+            Outcome::Complete(None) => return Outcome::Complete(found),
+            Outcome::Cancelled(_) => return Outcome::Cancelled(found),
+            Outcome::GaveUp(_, error) => return Outcome::GaveUp(found, error),
+        };
+        found.push(next);
+
+        if next.start == next.end {
+            // zero-width match, move one character forward
+            match input[next.end..].chars().next() {
+                Some(next_char) => next_at = next.end + next_char.len_utf8(),
+                None => return Outcome::Complete(found),
+            }
+        } else {
+            next_at = next.end;
+        }
+    }
+}
+
+/// The input as a haystack of slices of about `CHECK_INTERVAL` bytes, which ends early once
+/// the interrupt fires.
+///
+/// A search over it stops soon after the interrupt, even if no match turns up; the matches it
+/// reports from then on may have been cut short by the early end, so they should be dropped.
+pub(crate) struct Slices<'a> {
+    input: &'a str,
+    interrupt: &'a Interrupt,
+    interrupted: Cell<bool>,
+}
+
+impl<'a> Slices<'a> {
+    pub(crate) fn new(input: &'a str, interrupt: &'a Interrupt) -> Self {
+        Slices {
+            input,
+            interrupt,
+            interrupted: Cell::new(false),
+        }
+    }
+
+    /// Whether the haystack ended early because of the interrupt.
+    pub(crate) fn interrupted(&self) -> bool {
+        self.interrupted.get()
+    }
+}
+
+impl Haystack for Slices<'_> {
+    fn chunks(&self) -> impl Iterator<Item = &str> {
+        let mut start = 0;
+        std::iter::from_fn(move || {
+            if start >= self.input.len() {
+                return None;
+            }
+            if self.interrupt.is_interrupted() {
+                self.interrupted.set(true);
+                return None;
+            }
+            let mut end = (start + CHECK_INTERVAL).min(self.input.len());
+            while !self.input.is_char_boundary(end) {
+                end += 1;
+            }
+            let slice = &self.input[start..end];
+            start = end;
+            Some(slice)
+        })
+    }
+}
+
+/// Every match from `at`, searching the input a slice at a time and checking the interrupt
+/// between slices.
+pub(crate) fn find_all_in_slices<M: Matcher + ?Sized>(
+    matcher: &M,
+    input: &str,
+    at: usize,
+    interrupt: &Interrupt,
+) -> Outcome<Vec<Match>> {
+    let slices = Slices::new(input, interrupt);
+    let mut found = Vec::new();
+    let _ = matcher.try_find_iter_in_at(&slices, at, |next| {
+        if slices.interrupted() {
+            return Ok::<bool, ()>(false);
+        }
+        found.push(next);
+        Ok(true)
+    });
+    match slices.interrupted() {
+        true => Outcome::Cancelled(found),
+        false => Outcome::Complete(found),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupts() {
+        assert!(!Interrupt::new().is_interrupted());
+        assert!(Interrupt::new()
+            .with_deadline(Instant::now())
+            .is_interrupted());
+        assert!(!Interrupt::new()
+            .with_timeout(Duration::from_secs(3600))
+            .is_interrupted());
+
+        let token = CancellationToken::new();
+        let interrupt = Interrupt::new().with_token(token.clone());
+        assert!(!interrupt.is_interrupted());
+        token.cancel();
+        assert!(interrupt.is_interrupted());
+    }
+
+    #[test]
+    fn test_slices_end_once_interrupted() {
+        let input = "é".repeat(2 * CHECK_INTERVAL);
+        let token = CancellationToken::new();
+        let interrupt = Interrupt::new().with_token(token.clone());
+        let slices = Slices::new(&input, &interrupt);
+
+        let mut chunks = slices.chunks();
+        let first = chunks.next().unwrap();
+        assert_eq!(first.len(), CHECK_INTERVAL);
+        assert!(chunks.next().is_some());
+        token.cancel();
+        assert_eq!(chunks.next(), None);
+        assert!(slices.interrupted());
+
+        let slices = Slices::new(&input, &interrupt);
+        assert_eq!(slices.chunks().count(), 0);
+        let never = Interrupt::new();
+        let slices = Slices::new(&input, &never);
+        assert_eq!(slices.chunks().map(str::len).sum::<usize>(), input.len());
+        assert!(!slices.interrupted());
+    }
+}
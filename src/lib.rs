@@ -2,8 +2,13 @@
 // work though
 // pub mod regex;
 pub mod compile;
+pub mod engines;
 pub mod gex;
+pub mod haystack;
+pub mod interrupt;
 pub mod matcher;
 pub mod operators;
+mod pool;
 pub mod railroad;
+pub mod replace;
 pub mod tokenize;
@@ -1,13 +1,18 @@
-use saltgrep::compile::compile;
-use saltgrep::matcher::{Match, Matcher};
+use saltgrep::compile::{compile, compile_nfa, CompileOptions};
+use saltgrep::gex::GraphFormat;
+use saltgrep::interrupt::Interrupt;
+use saltgrep::matcher::Matcher;
 use std::env::args_os;
 use std::ffi::OsString;
-use std::fs;
+use std::fs::File;
 use std::io;
 use std::io::Write;
+use std::io::{BufRead, BufReader};
+use std::process;
+use std::time::Duration;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
-const application_name: &str = "saltgrep";
+const APPLICATION_NAME: &str = "saltgrep";
 
 // TODO: help etc; input flags parser
 // pattern.to_str().ok_or_else(|| {
@@ -18,8 +23,56 @@ const application_name: &str = "saltgrep";
 //         InvalidPatternError { original: escape_os(pattern), valid_up_to }
 //     })
 
+const DUMP_NFA_FLAG: &str = "--dump-nfa=";
+const TIMEOUT_FLAG: &str = "--timeout=";
+const USAGE: &str = "usage: saltgrep [--timeout=SECONDS] PATTERN FILE\n       \
+                     saltgrep --dump-nfa=FORMAT PATTERN";
+
 pub fn main() -> Result<(), io::Error> {
-    let args: Vec<OsString> = args_os().collect();
+    let mut args: Vec<OsString> = args_os().collect();
+
+    // `--dump-nfa=dot PATTERN` prints the pattern's NFA as a graph instead of searching
+    let dump_format = match args
+        .get(1)
+        .and_then(|arg| arg.to_str())
+        .and_then(|arg| arg.strip_prefix(DUMP_NFA_FLAG))
+    {
+        Some(format) => Some(format.parse::<GraphFormat>().map_err(io::Error::other)?),
+        None => None,
+    };
+    if dump_format.is_some() {
+        args.remove(1);
+    }
+
+    // `--timeout=SECONDS PATTERN FILE` gives up on the search once the time has passed
+    let timeout = match args
+        .get(1)
+        .and_then(|arg| arg.to_str())
+        .and_then(|arg| arg.strip_prefix(TIMEOUT_FLAG))
+    {
+        Some(seconds) => Some(
+            seconds
+                .parse::<f64>()
+                .ok()
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                .ok_or_else(|| io::Error::other(format!("Bad timeout {:?}", seconds)))?,
+        ),
+        None => None,
+    };
+    if timeout.is_some() {
+        args.remove(1);
+    }
+    let interrupt = match timeout {
+        Some(timeout) => Interrupt::new().with_timeout(timeout),
+        None => Interrupt::new(),
+    };
+
+    // A graph needs just the pattern; a search needs the file too
+    let expected_args = if dump_format.is_some() { 2 } else { 3 };
+    if args.len() != expected_args {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
 
     let pattern_os_string = &args[1];
     let pattern = pattern_os_string.to_str().ok_or_else(|| {
@@ -27,16 +80,18 @@ pub fn main() -> Result<(), io::Error> {
             .to_string_lossy()
             .find('\u{FFFD}')
             .expect("a Unicode replacement codepoint for invalid UTF-8");
-        io::Error::new(
-            io::ErrorKind::Other,
-            format!("Bad unicode pattern at {}", valid_up_to),
-        )
+        io::Error::other(format!("Bad unicode pattern at {}", valid_up_to))
     })?;
+
+    if let Some(format) = dump_format {
+        let machine = compile_nfa(pattern, &CompileOptions::default())?;
+        print!("{}", machine.to_graph(format));
+        return Ok(());
+    }
     let file_path = &args[2];
 
-    // let contents = fs::read_to_string(file_path).expect("File failed to read");
-    let contents = fs::read_to_string(file_path).expect("saltgrep:");
-    // println!("lib: {}", contents);
+    // Lines are read as they are searched, so the file is never held in memory all at once
+    let contents = BufReader::new(File::open(file_path).expect(APPLICATION_NAME));
 
     let searcher = compile(pattern)?;
     // println!(
@@ -55,24 +110,50 @@ pub fn main() -> Result<(), io::Error> {
     let mut stdout = StandardStream::stdout(ColorChoice::Auto);
     // ... write to stdout
 
-    contents
-        .lines()
-        .map(|line| {
-            let mut curr_at = 0;
-            searcher.try_find_iter_at(line, curr_at, |found| {
-                write!(&mut stdout, "{}", &line[curr_at..found.start])?;
-                stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
-                write!(&mut stdout, "{}", found.substr(line))?;
-                stdout.reset()?;
-                curr_at = found.end;
-                Ok::<bool, io::Error>(true)
-            })?;
-            if curr_at != line.len() {
-                write!(&mut stdout, "{}", &line[curr_at..line.len()])?;
-            }
-            writeln!(&mut stdout, "")
-        })
-        .count();
+    for (line_number, line) in contents.lines().enumerate() {
+        let line = line?;
+        let line = line.as_str();
+        if interrupt.is_interrupted() {
+            return Err(timed_out(line_number));
+        }
+        let outcome = searcher.find_all_until(line, 0, &interrupt);
+        let cancelled = outcome.is_cancelled();
+        let gave_up = outcome.error();
+
+        let mut curr_at = 0;
+        for found in outcome.into_inner() {
+            write!(&mut stdout, "{}", &line[curr_at..found.start])?;
+            stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
+            write!(&mut stdout, "{}", found.substr(line))?;
+            stdout.reset()?;
+            curr_at = found.end;
+        }
+        if curr_at != line.len() {
+            write!(&mut stdout, "{}", &line[curr_at..line.len()])?;
+        }
+        writeln!(&mut stdout)?;
+
+        // What was found before the time ran out has been printed; the rest is left unsearched
+        if cancelled {
+            return Err(timed_out(line_number + 1));
+        }
+        // Matches may remain past where the search gave up, so the line can't be passed over
+        if let Some(error) = gave_up {
+            return Err(io::Error::other(format!(
+                "search gave up on line {}: {}",
+                line_number + 1,
+                error
+            )));
+        }
+    }
 
     Ok(())
 }
+
+/// Error for a search that ran out of time once the given number of lines had been searched.
+fn timed_out(lines_searched: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("search timed out after line {}", lines_searched),
+    )
+}
@@ -1,6 +1,11 @@
+use crate::haystack::Haystack;
+use crate::interrupt::{find_all_between_matches, Interrupt, Outcome};
+use crate::replace::{replacen, Replacer};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::sync::Arc;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct Match {
     pub start: usize,
     pub end: usize,
@@ -19,6 +24,122 @@ impl Match {
     }
 }
 
+/// Group number of each named group of a pattern.
+pub type GroupNames = HashMap<String, u16>;
+
+/// Where a match, and each group of its pattern, matched.
+///
+/// Group 0 is the whole match, and the pattern's groups follow in the order they open. A group
+/// that exists but took no part in the match, like the `(b)` of `(a)|(b)` matching "a", has no
+/// match, while asking for a group past the last gets nothing at all.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Captures {
+    /// Start and end of each group in turn, both None for a group that took no part.
+    slots: Vec<Option<usize>>,
+    names: Option<Arc<GroupNames>>,
+}
+
+impl Captures {
+    /// Captures from the slots of a finished search, two for every group, both of a group left
+    /// unset or not forming a span when the group took no part.
+    pub(crate) fn new(mut slots: Vec<Option<usize>>, names: Option<Arc<GroupNames>>) -> Self {
+        for pair in slots.chunks_mut(2) {
+            if !matches!(*pair, [Some(start), Some(end)] if start <= end) {
+                pair.fill(None);
+            }
+        }
+        Captures { slots, names }
+    }
+
+    /// Captures of a pattern without groups, from its match.
+    pub(crate) fn from_match(found: Match) -> Self {
+        Captures::new(vec![Some(found.start), Some(found.end)], None)
+    }
+
+    /// Where the group matched, if it took part in the match.
+    pub fn get(&self, group_number: usize) -> Option<Match> {
+        let start_slot = group_number.checked_mul(2)?;
+        match self.slots.get(start_slot..start_slot.checked_add(2)?)? {
+            [Some(start), Some(end)] => Some(Match {
+                start: *start,
+                end: *end,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Where the named group, like `(?<year>\d+)`, matched, if it took part in the match.
+    pub fn name(&self, name: &str) -> Option<Match> {
+        self.get(self.name_index(name)?)
+    }
+
+    /// Group number of the named group, if the pattern has a group of that name.
+    pub fn name_index(&self, name: &str) -> Option<usize> {
+        self.names
+            .as_ref()?
+            .get(name)
+            .map(|group_number| *group_number as usize)
+    }
+
+    /// The whole match.
+    pub fn whole(&self) -> Match {
+        self.get(0).expect("Group 0 is the match")
+    }
+
+    /// Number of groups, counting the whole match as group 0, whether or not they took part.
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    /// Whether there are no groups, which never holds since the whole match is group 0.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Where each group matched, in group number order, from group 0.
+    pub fn iter(&self) -> impl Iterator<Item = Option<Match>> + '_ {
+        (0..self.len()).map(|group_number| self.get(group_number))
+    }
+
+    /// Text the group matched in the haystack the captures came from.
+    pub fn substr<'h>(&self, group_number: usize, haystack: &'h str) -> Option<&'h str> {
+        self.get(group_number).map(|span| span.substr(haystack))
+    }
+
+    /// Text the named group matched in the haystack the captures came from.
+    pub fn name_substr<'h>(&self, name: &str, haystack: &'h str) -> Option<&'h str> {
+        self.name(name).map(|span| span.substr(haystack))
+    }
+
+    /// Text each group matched, in group number order, from group 0.
+    pub fn substrs<'a, 'h: 'a>(
+        &'a self,
+        haystack: &'h str,
+    ) -> impl Iterator<Item = Option<&'h str>> + 'a {
+        self.iter()
+            .map(move |span| span.map(|span| span.substr(haystack)))
+    }
+
+    /// The captures moved later by `shift` bytes, for captures found in a slice of the
+    /// haystack.
+    pub(crate) fn shift(mut self, shift: usize) -> Self {
+        for slot in self.slots.iter_mut().flatten() {
+            *slot += shift;
+        }
+        self
+    }
+}
+
+/// Which of several matches starting at the leftmost position wins.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum MatchKind {
+    /// The first alternative (in pattern order) that matches is preferred, as in Perl/PCRE.
+    LeftmostFirst,
+    /// The longest match is preferred, as in POSIX.
+    #[default]
+    LeftmostLongest,
+}
+
 pub trait Matcher {
     fn find_at(&self, input: &str, at: usize) -> Option<Match>;
 
@@ -26,9 +147,9 @@ pub trait Matcher {
         self.find_at(input, 0)
     }
 
-    fn captures_at(&self, input: &str, at: usize) -> Option<HashMap<u16, Match>>;
+    fn captures_at(&self, input: &str, at: usize) -> Option<Captures>;
 
-    fn captures(&self, input: &str) -> Option<HashMap<u16, Match>> {
+    fn captures(&self, input: &str) -> Option<Captures> {
         self.captures_at(input, 0)
     }
 
@@ -46,8 +167,11 @@ pub trait Matcher {
             };
 
             if found.start == found.end {
-                // zero-width match, move one space forward
-                last_end = found.end + 1;
+                // zero-width match, move one character forward
+                match input[found.end..].chars().next() {
+                    Some(next) => last_end = found.end + next.len_utf8(),
+                    None => return matched(found).map(|_| ()),
+                }
             } else {
                 last_end = found.end;
             }
@@ -59,15 +183,152 @@ pub trait Matcher {
             }
         }
     }
+
+    /// Every match from `at`, as found by `try_find_iter_at`, unless the interrupt fires first.
+    ///
+    /// The interrupt is checked between matches, or every so often when the matcher can search
+    /// a slice of the input at a time. Once it fires, the matches found so far are returned as
+    /// cancelled.
+    fn find_all_until(&self, input: &str, at: usize, interrupt: &Interrupt) -> Outcome<Vec<Match>> {
+        find_all_between_matches(self, input, at, interrupt)
+    }
+
+    /// The haystack with its first match replaced, by a template like `"$2-$1"` or by what a
+    /// closure makes of the match's captures; see `Replacer`.
+    fn replace<'h, R: Replacer>(&self, haystack: &'h str, replacer: R) -> Cow<'h, str> {
+        self.replacen(haystack, 1, replacer)
+    }
+
+    /// The haystack with every match, as found by `try_find_iter_at`, replaced.
+    fn replace_all<'h, R: Replacer>(&self, haystack: &'h str, replacer: R) -> Cow<'h, str> {
+        self.replacen(haystack, 0, replacer)
+    }
+
+    /// The haystack with its first `limit` matches replaced, or all of them when `limit` is 0.
+    ///
+    /// The haystack is returned as it is, without copying, when nothing matches.
+    fn replacen<'h, R: Replacer>(
+        &self,
+        haystack: &'h str,
+        limit: usize,
+        replacer: R,
+    ) -> Cow<'h, str> {
+        replacen(self, haystack, limit, replacer)
+    }
+
+    /// Like `find_at`, over text held in chunks, with offsets into the whole haystack.
+    ///
+    /// Unless the matcher can search a chunk at a time, the chunks are copied into one string.
+    fn find_in_at<H: Haystack + ?Sized>(&self, haystack: &H, at: usize) -> Option<Match> {
+        let mut first = None;
+        self.try_find_iter_in_at(haystack, at, |found| {
+            first = Some(found);
+            Ok::<bool, ()>(false)
+        })
+        .ok()?;
+        first
+    }
+
+    fn find_in<H: Haystack + ?Sized>(&self, haystack: &H) -> Option<Match> {
+        self.find_in_at(haystack, 0)
+    }
+
+    /// Like `captures_at`, over text held in chunks, with offsets into the whole haystack.
+    fn captures_in_at<H: Haystack + ?Sized>(&self, haystack: &H, at: usize) -> Option<Captures> {
+        self.captures_at(&haystack.to_text(), at)
+    }
+
+    fn captures_in<H: Haystack + ?Sized>(&self, haystack: &H) -> Option<Captures> {
+        self.captures_in_at(haystack, 0)
+    }
+
+    /// Like `try_find_iter_at`, over text held in chunks, with offsets into the whole haystack.
+    fn try_find_iter_in_at<H, F, E>(&self, haystack: &H, at: usize, matched: F) -> Result<(), E>
+    where
+        H: Haystack + ?Sized,
+        F: FnMut(Match) -> Result<bool, E>,
+    {
+        self.try_find_iter_at(&haystack.to_text(), at, matched)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::compile::compile;
     #[test]
     fn expected_substr() {
         let match_result = Match { start: 3, end: 7 };
 
         assert_eq!(match_result.substr("abcdefghi"), "defg");
     }
+
+    #[test]
+    fn test_captures() {
+        let names = Arc::new(GroupNames::from([
+            ("first".to_string(), 1),
+            ("second".to_string(), 2),
+        ]));
+        // The second group took no part, and the third's slots don't form a span
+        let slots = vec![
+            Some(2),
+            Some(5),
+            Some(2),
+            Some(3),
+            None,
+            None,
+            Some(4),
+            Some(3),
+        ];
+        let captures = Captures::new(slots, Some(names)).shift(1);
+        let haystack = "__abcdef";
+
+        assert_eq!(captures.len(), 4);
+        assert_eq!(captures.whole(), Match { start: 3, end: 6 });
+        assert_eq!(captures.get(1), Some(Match { start: 3, end: 4 }));
+        assert_eq!(
+            (captures.get(2), captures.get(3), captures.get(4)),
+            (None, None, None)
+        );
+        assert_eq!(captures.name("first"), captures.get(1));
+        assert_eq!(captures.name("second"), None);
+        assert_eq!(captures.name("third"), None);
+        assert_eq!(captures.name_index("second"), Some(2));
+        assert_eq!(captures.name_index("third"), None);
+        // Group numbers whose slots would lie past the end of memory
+        assert_eq!(captures.get(usize::MAX / 2 + 1), None);
+        assert_eq!(captures.get(usize::MAX / 2), None);
+        assert_eq!(captures.get(usize::MAX), None);
+        assert_eq!(captures.name_substr("first", haystack), Some("b"));
+        assert_eq!(
+            captures.substrs(haystack).collect::<Vec<_>>(),
+            [Some("bcd"), Some("b"), None, None]
+        );
+
+        let unnamed = Captures::from_match(Match { start: 0, end: 2 });
+        assert_eq!(unnamed.iter().collect::<Vec<_>>(), [unnamed.get(0)]);
+        assert_eq!(unnamed.name("first"), None);
+    }
+
+    #[test]
+    fn test_find_iter_steps_past_empty_matches() {
+        let all = |pattern: &str, input: &str| {
+            let matcher = compile(pattern).unwrap();
+            let mut found = Vec::new();
+            matcher
+                .try_find_iter_at(input, 0, |m| {
+                    found.push((m.start, m.end));
+                    Ok::<bool, ()>(true)
+                })
+                .unwrap();
+            found
+        };
+
+        // An empty match moves the search on by a whole character, never into the middle of one
+        assert_eq!(all("x*", "éx"), [(0, 0), (2, 3), (3, 3)]);
+        assert_eq!(all("b*", "日本"), [(0, 0), (3, 3), (6, 6)]);
+        // An empty match at the end of the input is the last, reported once
+        assert_eq!(all("b*", ""), [(0, 0)]);
+        assert_eq!(all("a*", "baa"), [(0, 0), (1, 3), (3, 3)]);
+    }
 }
@@ -0,0 +1,178 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+/// Fewest stacks values are spread over, so threads rarely reach for the same one; machines
+/// that run more threads at once get a stack per thread.
+const MIN_STRIPES: usize = 16;
+
+static NEXT_THREAD_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Small number unique to each thread, picking the stack its values come from and go back to.
+    static THREAD_ID: usize = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Values, such as search scratch space, handed out to one thread at a time and taken back
+/// for reuse.
+///
+/// Each thread takes from and returns to its own stack, so threads rarely wait on each other,
+/// and then only for a push or pop by another thread sharing the stack. A new value is made
+/// only when the stack is empty, and every value is kept once returned, so the pool holds as
+/// many values as were ever out at once. After warming up, a thread never makes another.
+pub(crate) struct Pool<T> {
+    stacks: Box<[Mutex<Vec<T>>]>,
+}
+
+impl<T> Pool<T> {
+    pub(crate) fn new() -> Self {
+        Pool {
+            stacks: (0..stripes()).map(|_| Mutex::new(Vec::new())).collect(),
+        }
+    }
+
+    /// A value from the current thread's stack, or a new one from `create` if there is none to
+    /// reuse. It goes back on the stack once the guard is dropped.
+    pub(crate) fn get<F: FnOnce() -> T>(&self, create: F) -> PoolGuard<'_, T> {
+        let stripe = THREAD_ID.with(|id| *id) % self.stacks.len();
+        let reused = self.stack(stripe).pop();
+        PoolGuard {
+            pool: self,
+            stripe,
+            value: Some(reused.unwrap_or_else(create)),
+        }
+    }
+
+    fn put(&self, stripe: usize, value: T) {
+        self.stack(stripe).push(value);
+    }
+
+    /// The stack, locked; it's only ever held for a push or pop, which leave it whole even if
+    /// they panic.
+    fn stack(&self, stripe: usize) -> MutexGuard<'_, Vec<T>> {
+        self.stacks[stripe]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Number of stacks for a new pool, one for each thread the machine runs at once.
+fn stripes() -> usize {
+    thread::available_parallelism()
+        .map_or(MIN_STRIPES, usize::from)
+        .max(MIN_STRIPES)
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Pool::new()
+    }
+}
+
+/// Clones start out empty; the values are only scratch space, so nothing is lost.
+impl<T> Clone for Pool<T> {
+    fn clone(&self) -> Self {
+        Pool::new()
+    }
+}
+
+impl<T> fmt::Debug for Pool<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pool").finish_non_exhaustive()
+    }
+}
+
+/// A value taken from a `Pool`, which returns it there when dropped.
+pub(crate) struct PoolGuard<'a, T> {
+    pool: &'a Pool<T>,
+    stripe: usize,
+    /// Always set, until the value is handed back.
+    value: Option<T>,
+}
+
+impl<T> Deref for PoolGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value.as_ref().expect("A pooled value until dropped")
+    }
+}
+
+impl<T> DerefMut for PoolGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value.as_mut().expect("A pooled value until dropped")
+    }
+}
+
+impl<T> Drop for PoolGuard<'_, T> {
+    fn drop(&mut self) {
+        if let Some(value) = self.value.take() {
+            self.pool.put(self.stripe, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_reused_by_their_thread() {
+        let pool = Pool::new();
+        let created = AtomicUsize::new(0);
+        let create = || created.fetch_add(1, Ordering::Relaxed);
+
+        let first = *pool.get(create);
+        assert_eq!(*pool.get(create), first);
+        {
+            // Only one value sits in the pool, so a second one out at the same time is new
+            let _held = pool.get(create);
+            let _other = pool.get(create);
+        }
+        assert_eq!(created.load(Ordering::Relaxed), 2);
+
+        // A value is never out to two threads at once
+        let shared = Pool::new();
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let shared = &shared;
+                scope.spawn(move || {
+                    for _ in 0..1000 {
+                        let mut value = shared.get(|| thread);
+                        *value = thread;
+                        std::thread::yield_now();
+                        assert_eq!(*value, thread);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_warmed_up_threads_make_no_more_values() {
+        // More threads than stacks, so some stacks are shared
+        let threads = 4 * stripes();
+        let pool = Pool::new();
+        let created = AtomicUsize::new(0);
+        let warmed_up = std::sync::Barrier::new(threads);
+
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    let create = || created.fetch_add(1, Ordering::Relaxed);
+                    // Every thread holds a value at once, so each makes its own
+                    let held = pool.get(create);
+                    warmed_up.wait();
+                    drop(held);
+                    for _ in 0..200 {
+                        let _value = pool.get(create);
+                        std::thread::yield_now();
+                    }
+                });
+            }
+        });
+        assert_eq!(created.load(Ordering::Relaxed), threads);
+    }
+}
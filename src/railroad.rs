@@ -1,5 +1,5 @@
 use crate::operators::{Arity, Operator};
-use crate::tokenize::{LiteralType, QuantifierType, Token, TokenType};
+use crate::tokenize::{GroupType, LiteralType, QuantifierType, Token, TokenType};
 use std::fmt;
 
 type Result<T> = std::result::Result<T, SyntaxError>;
@@ -34,7 +34,8 @@ pub struct AstRef(usize);
 pub enum AstNode {
     Alternation(AstRef, AstRef),
     Cons(AstRef, AstRef),
-    Group(AstRef),
+    /// A group, along with the token that opened it.
+    Group(GroupType, Token, AstRef),
     Quantifier(QuantifierType, AstRef),
     Literal(LiteralType, Token),
 }
@@ -47,8 +48,8 @@ pub enum AstNode {
 #[derive(Debug, PartialEq)]
 pub struct Ast(pub Vec<AstNode>);
 
-impl ToString for Ast {
-    fn to_string(&self) -> String {
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut pretty = String::with_capacity(self.0.len() * 1.2 as usize);
         let length = self.0.len();
         for (idx, node) in self.0.iter().enumerate() {
@@ -57,11 +58,17 @@ impl ToString for Ast {
                 AstNode::Cons(_, _) => {
                     pretty.push('J');
                 }
-                AstNode::Group(_) => pretty.push('G'),
+                AstNode::Group(..) => pretty.push('G'),
                 AstNode::Quantifier(qtype, _) => match qtype {
                     QuantifierType::ZeroOrMore => pretty.push('*'),
                     QuantifierType::OneOrMore => pretty.push('+'),
                     QuantifierType::ZeroOrOne => pretty.push('?'),
+                    QuantifierType::Counted(min, Some(max)) => {
+                        pretty.push_str(format!("{{{},{}}}", min, max).as_str())
+                    }
+                    QuantifierType::Counted(min, None) => {
+                        pretty.push_str(format!("{{{},}}", min).as_str())
+                    }
                 },
                 // TODO implement character classes
                 AstNode::Literal(_, token) => {
@@ -72,7 +79,7 @@ impl ToString for Ast {
                 pretty.push(' ');
             }
         }
-        pretty
+        write!(f, "{}", pretty)
     }
 }
 
@@ -83,7 +90,7 @@ impl Ast {
     }
 
     pub fn get(&self, node_ref: AstRef) -> &AstNode {
-        &self.0[node_ref.0 as usize]
+        &self.0[node_ref.0]
     }
 
     pub fn size(&self) -> usize {
@@ -91,7 +98,7 @@ impl Ast {
     }
 
     pub fn pprint(&self) {
-        println!("{}", self.to_string());
+        println!("{}", self);
     }
 
     pub fn from_tokens(tokens: Vec<Token>) -> Result<Ast> {
@@ -108,7 +115,7 @@ impl Ast {
                     out_stack.push(ast.add(AstNode::Literal(literal_type, token)));
                 }
                 // when a group opens, push to operators
-                TokenType::OpenGroup => {
+                TokenType::OpenGroup(_) => {
                     op_stack.push(token);
                     if let Some(Token {
                         kind: TokenType::CloseGroup,
@@ -117,7 +124,7 @@ impl Ast {
                     {
                         out_stack.push(ast.add(AstNode::Literal(
                             LiteralType::Character,
-                            Token::empty_string(token.start() + 1),
+                            Token::empty_string(token.end()),
                         )));
                     }
                 }
@@ -136,6 +143,17 @@ impl Ast {
                     ));
                     out_stack.push(new_ref);
                 }
+                // A possessive quantifier is an atomic group around the quantified operand
+                TokenType::Possessive => {
+                    let new_ref = ast.add(AstNode::Group(
+                        GroupType::Atomic,
+                        token,
+                        out_stack.pop().unwrap_or_else(|| {
+                            panic!("No operand found for {:?} at {}", token.kind, token.start())
+                        }),
+                    ));
+                    out_stack.push(new_ref);
+                }
                 // Handle all other operations
                 _ => {
                     while let Some(previous_op) = op_stack.last() {
@@ -200,8 +218,7 @@ fn get_operator_node(op_token: Token, out_stack: &mut Vec<AstRef>) -> AstNode {
             let arg = get_unary_operands(out_stack, op_token.start());
             match op_token.kind {
                 TokenType::Quantifier(qtype) => AstNode::Quantifier(qtype, arg),
-                TokenType::CloseGroup => AstNode::Group(arg),
-                TokenType::OpenGroup => panic!("Unclosed OpenGroup token encountered"),
+                TokenType::OpenGroup(_) => panic!("Unclosed OpenGroup token encountered"),
                 _ => panic!(
                     "Unknown Unary Operator {:?} at {}",
                     op_token.kind,
@@ -224,18 +241,18 @@ fn add_group(
     op_stack: &mut Vec<Token>,
     out_stack: &mut Vec<AstRef>,
 ) {
-    loop {
+    let (group_type, open_token) = loop {
         let op_token = op_stack
             .pop()
             .unwrap_or_else(|| panic!("Unmatched group closure at {}", group_pos));
-        if let TokenType::OpenGroup = op_token.kind {
-            break;
+        if let TokenType::OpenGroup(group_type) = op_token.kind {
+            break (group_type, op_token);
         }
         let new_ref = ast.add(get_operator_node(op_token, out_stack));
         out_stack.push(new_ref);
-    }
+    };
     let group_contents = out_stack.pop().expect("Group must have contents");
-    let new_ref = ast.add(AstNode::Group(group_contents));
+    let new_ref = ast.add(AstNode::Group(group_type, open_token, group_contents));
     out_stack.push(new_ref);
 }
 
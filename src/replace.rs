@@ -0,0 +1,242 @@
+use crate::matcher::{Captures, Matcher};
+use std::borrow::Cow;
+
+/// What each match is replaced with by `Matcher::replace` and its kin.
+///
+/// Template strings, like `"$2-$1"`, are expanded by `Captures::expand`. Closures are called with
+/// the captures of each match, and can return anything that is a string.
+pub trait Replacer {
+    /// Append the replacement for the match to `out`.
+    fn append(&mut self, captures: &Captures, haystack: &str, out: &mut String);
+
+    /// The replacement, when it is the same for every match, so matches can be replaced without
+    /// finding their captures.
+    fn literal(&mut self) -> Option<Cow<'_, str>> {
+        None
+    }
+}
+
+impl Replacer for &str {
+    fn append(&mut self, captures: &Captures, haystack: &str, out: &mut String) {
+        captures.expand(self, haystack, out);
+    }
+
+    fn literal(&mut self) -> Option<Cow<'_, str>> {
+        (!self.contains('$')).then_some(Cow::Borrowed(*self))
+    }
+}
+
+impl Replacer for String {
+    fn append(&mut self, captures: &Captures, haystack: &str, out: &mut String) {
+        self.as_str().append(captures, haystack, out);
+    }
+
+    fn literal(&mut self) -> Option<Cow<'_, str>> {
+        (!self.contains('$')).then_some(Cow::Borrowed(self.as_str()))
+    }
+}
+
+impl Replacer for &String {
+    fn append(&mut self, captures: &Captures, haystack: &str, out: &mut String) {
+        self.as_str().append(captures, haystack, out);
+    }
+
+    fn literal(&mut self) -> Option<Cow<'_, str>> {
+        (!self.contains('$')).then_some(Cow::Borrowed(self.as_str()))
+    }
+}
+
+impl<F, T> Replacer for F
+where
+    F: FnMut(&Captures) -> T,
+    T: AsRef<str>,
+{
+    fn append(&mut self, captures: &Captures, _haystack: &str, out: &mut String) {
+        out.push_str(self(captures).as_ref());
+    }
+}
+
+impl Captures {
+    /// Append the template to `out`, with each reference to a group replaced by the text the
+    /// group matched in the haystack.
+    ///
+    /// `$1` refers to a group by number, taking every digit that follows, while `${1}` and
+    /// `${name}` are closed by the brace, so `${1}0` is group 1 followed by a zero. `$$` is a
+    /// dollar sign. Groups that don't exist or took no part in the match are replaced by
+    /// nothing, and a `$` that starts no reference is left as it is.
+    pub fn expand(&self, template: &str, haystack: &str, out: &mut String) {
+        let mut rest = template;
+        while let Some(dollar) = rest.find('$') {
+            out.push_str(&rest[..dollar]);
+            rest = &rest[dollar + 1..];
+
+            let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+            let braced = rest
+                .strip_prefix('{')
+                .and_then(|braced| braced.find('}').map(|close| &braced[..close]));
+            let (group, taken) = match (digits, braced) {
+                (0, Some(reference)) if !reference.is_empty() => (reference, reference.len() + 2),
+                (0, _) if rest.starts_with('$') => {
+                    out.push('$');
+                    rest = &rest[1..];
+                    continue;
+                }
+                (0, _) => {
+                    out.push('$');
+                    continue;
+                }
+                (digits, _) => (&rest[..digits], digits),
+            };
+            rest = &rest[taken..];
+
+            let text = match group.parse::<usize>() {
+                Ok(group_number) => self.substr(group_number, haystack),
+                Err(_) => self.name_substr(group, haystack),
+            };
+            out.push_str(text.unwrap_or(""));
+        }
+        out.push_str(rest);
+    }
+}
+
+/// The haystack with the first `limit` matches from the start, or every match when `limit` is
+/// 0, replaced; the haystack itself when nothing matches.
+///
+/// Matches are found as `Matcher::try_find_iter_at` finds them, so a zero-width match is
+/// replaced wherever it turns up, and the search moves on past the character after it.
+pub(crate) fn replacen<'h, M, R>(
+    matcher: &M,
+    haystack: &'h str,
+    limit: usize,
+    mut replacer: R,
+) -> Cow<'h, str>
+where
+    M: Matcher + ?Sized,
+    R: Replacer,
+{
+    let mut out = String::new();
+    // End of the last match, up to which the haystack is in `out`
+    let mut copied = 0;
+    let mut replaced = 0;
+
+    if let Some(literal) = replacer.literal() {
+        let _ = matcher.try_find_iter_at(haystack, 0, |found| {
+            out.push_str(&haystack[copied..found.start]);
+            out.push_str(&literal);
+            copied = found.end;
+            replaced += 1;
+            Ok::<bool, ()>(limit == 0 || replaced < limit)
+        });
+    } else {
+        let mut at = 0;
+        while let Some(captures) = matcher.captures_at(haystack, at) {
+            let found = captures.whole();
+            out.push_str(&haystack[copied..found.start]);
+            replacer.append(&captures, haystack, &mut out);
+            copied = found.end;
+            replaced += 1;
+            if replaced == limit {
+                break;
+            }
+
+            if found.start == found.end {
+                // zero-width match, move one character forward
+                match haystack[found.end..].chars().next() {
+                    Some(next) => at = found.end + next.len_utf8(),
+                    None => break,
+                }
+            } else {
+                at = found.end;
+            }
+        }
+    }
+
+    if replaced == 0 {
+        return Cow::Borrowed(haystack);
+    }
+    out.push_str(&haystack[copied..]);
+    Cow::Owned(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::compile;
+
+    fn expand(pattern: &str, input: &str, template: &str) -> String {
+        let captures = compile(pattern).unwrap().captures(input).unwrap();
+        let mut out = String::new();
+        captures.expand(template, input, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_templates() {
+        let pattern = r"(?<user>\w+)@(?<host>\w+)";
+        let input = "mail me@home";
+
+        assert_eq!(expand(pattern, input, "$2 for $1"), "home for me");
+        assert_eq!(expand(pattern, input, "${host}/${user}"), "home/me");
+        assert_eq!(expand(pattern, input, "${1}0 $10"), "me0 ");
+        assert_eq!(
+            expand(pattern, input, "$0: $$1 costs $"),
+            "me@home: $1 costs $"
+        );
+        assert_eq!(expand(pattern, input, "$x ${} ${host"), "$x ${} ${host");
+        assert_eq!(expand(pattern, input, "[${nothing}$3]"), "[]");
+        // Group numbers far past the last group, even past what a usize holds, are nothing too
+        assert_eq!(expand(pattern, input, "<$9223372036854775808>"), "<>");
+        assert_eq!(expand(pattern, input, "<${18446744073709551616}>"), "<>");
+        assert_eq!(expand(r"(a)|(b)", "b", "<$1|$2>"), "<|b>");
+        assert_eq!(expand(r"([^x]+)", "xéé", "$1ü"), "ééü");
+    }
+
+    #[test]
+    fn test_replacements() {
+        let gex = compile(r"(\d+)-(\d+)").unwrap();
+        let input = "1-2, 30-40 and 5-6";
+
+        assert_eq!(gex.replace(input, "$2-$1"), "2-1, 30-40 and 5-6");
+        assert_eq!(gex.replacen(input, 2, "$2-$1"), "2-1, 40-30 and 5-6");
+        assert_eq!(gex.replace_all(input, "$2-$1"), "2-1, 40-30 and 6-5");
+        assert_eq!(gex.replace_all(input, "#"), "#, # and #");
+        assert_eq!(
+            gex.replace_all(input, String::from("<$0>")),
+            "<1-2>, <30-40> and <5-6>"
+        );
+
+        let sum = |captures: &Captures| {
+            let number = |group| {
+                captures
+                    .substr(group, input)
+                    .unwrap()
+                    .parse::<u32>()
+                    .unwrap()
+            };
+            (number(1) + number(2)).to_string()
+        };
+        assert_eq!(gex.replace_all(input, sum), "3, 70 and 11");
+
+        let untouched = gex.replace_all("no ranges", "$1");
+        assert!(matches!(untouched, Cow::Borrowed("no ranges")));
+    }
+
+    #[test]
+    fn test_zero_width_replacements() {
+        // Every position between characters, including both ends, whatever the characters' widths
+        for template in ["-", "-$0"] {
+            assert_eq!(
+                compile("x*").unwrap().replace_all("añ😀", template),
+                "-a-ñ-😀-"
+            );
+            assert_eq!(compile("y?").unwrap().replace_all("aé", template), "-a-é-");
+        }
+        assert_eq!(
+            compile(r"\b").unwrap().replace_all("ab çd", "|"),
+            "|ab| |çd|"
+        );
+        assert_eq!(compile("a*").unwrap().replace_all("baaé", "-"), "-b--é-");
+        assert_eq!(compile("x*").unwrap().replacen("ab", 2, "-"), "-a-b");
+        assert_eq!(compile("x*").unwrap().replace_all("", "-"), "-");
+    }
+}
@@ -12,6 +12,9 @@ pub enum TokenizeError {
     EmptyCharacterSet(usize),
     UnterminatedCharacterSet(usize),
     UnterminatedEscape(usize),
+    InvalidGroup(usize),
+    InvalidBackreference(usize),
+    InvalidRepetition(usize),
 }
 
 impl fmt::Display for TokenizeError {
@@ -26,6 +29,15 @@ impl fmt::Display for TokenizeError {
             TokenizeError::UnterminatedEscape(position) => {
                 write!(f, "Unterminated escape character at {}", position)
             }
+            TokenizeError::InvalidGroup(position) => {
+                write!(f, "Invalid group syntax at {}", position)
+            }
+            TokenizeError::InvalidBackreference(position) => {
+                write!(f, "Invalid backreference at {}", position)
+            }
+            TokenizeError::InvalidRepetition(position) => {
+                write!(f, "Invalid repetition bounds at {}", position)
+            }
         }
     }
 }
@@ -35,6 +47,8 @@ pub enum QuantifierType {
     ZeroOrMore,
     OneOrMore,
     ZeroOrOne,
+    /// `{m}`, `{m,}` or `{m,n}`: at least `m` times, and at most `n` times when bounded.
+    Counted(u32, Option<u32>),
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -43,6 +57,12 @@ pub enum LiteralType {
     Character,
     EscapedCharacter,
     CharacterClass(CharacterClassType, bool),
+    /// `\b`, or `\B` when false.
+    WordBoundary(bool),
+    /// `\1` by number or `\k<name>` by name.
+    Backreference,
+    /// `(?1)` calling a group by number, or `(?R)` recursing into the whole pattern.
+    Subroutine,
     EmptyString,
 }
 
@@ -55,13 +75,36 @@ pub enum CharacterClassType {
     Word,
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum GroupType {
+    Capturing,
+    /// `(?<name>...)`, captured like any other group and also reachable by name.
+    Named,
+    /// `(?:...)`
+    NonCapturing,
+    /// `(?=...)`, or `(?!...)` when false.
+    Lookahead(bool),
+    /// `(?<=...)`, or `(?<!...)` when false.
+    Lookbehind(bool),
+    /// `(?>...)`, which never backtracks into its body once it has matched.
+    Atomic,
+}
+
+impl GroupType {
+    pub fn is_capturing(&self) -> bool {
+        matches!(self, GroupType::Capturing | GroupType::Named)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TokenType {
     Quantifier(QuantifierType),
+    /// A `+` directly after a quantifier, making it possessive: `*+`, `++` or `?+`.
+    Possessive,
     Alternation,
     Cons,
     Literal(LiteralType),
-    OpenGroup,
+    OpenGroup(GroupType),
     CloseGroup,
 }
 
@@ -79,10 +122,13 @@ pub struct Token {
 }
 
 fn should_join_literals(token: &Token) -> bool {
-    match &token.kind {
-        TokenType::CloseGroup | TokenType::Quantifier(_) | TokenType::Literal(_) => true,
-        _ => false,
-    }
+    matches!(
+        &token.kind,
+        TokenType::CloseGroup
+            | TokenType::Quantifier(_)
+            | TokenType::Possessive
+            | TokenType::Literal(_)
+    )
 }
 
 impl Token {
@@ -107,7 +153,7 @@ impl Token {
     }
 
     fn open_group(position: usize) -> Self {
-        Token::create(TokenType::OpenGroup, position)
+        Token::create(TokenType::OpenGroup(GroupType::Capturing), position)
     }
 
     fn close_group(position: usize) -> Self {
@@ -134,16 +180,26 @@ impl Token {
         self.position.1
     }
 
+    /// The name of a named group, given the open group token.
+    pub fn group_name<'a>(&self, input: &'a str) -> Option<&'a str> {
+        match self.kind {
+            TokenType::OpenGroup(GroupType::Named) => input[self.input_range()]
+                .strip_prefix("(?<")
+                .and_then(|rest| rest.strip_suffix('>')),
+            _ => None,
+        }
+    }
+
     fn get_precedence(&self) -> i8 {
         -(match &self.kind {
             // Token::LeftSquareBracket | Token::RightSquareBracket => 3,
             // TODO: OpenGroup might not need to be here; aka should not get popped from op stack
             // if lower priority operator is encountered
             TokenType::CloseGroup => 4,
-            TokenType::Quantifier(_) => 5,
+            TokenType::Quantifier(_) | TokenType::Possessive => 5,
             TokenType::Cons => 6,
             TokenType::Alternation => 8,
-            TokenType::OpenGroup => 10,
+            TokenType::OpenGroup(_) => 10,
             TokenType::Literal(_) => 0,
         })
     }
@@ -160,7 +216,9 @@ impl Token {
 impl Operator for Token {
     fn arity(&self) -> Arity {
         match self.kind {
-            TokenType::Quantifier(_) | TokenType::CloseGroup => Arity::Unary,
+            TokenType::Quantifier(_) | TokenType::Possessive | TokenType::CloseGroup => {
+                Arity::Unary
+            }
             TokenType::Cons | TokenType::Alternation => Arity::Binary,
             _ => Arity::NoOp,
         }
@@ -195,9 +253,7 @@ fn munch_character_class(remaining_chars: &mut Peekable<Chars>, position: usize)
         next_position += '^'.len_utf8();
     }
 
-    let mut num_chars_in_class = 0;
-
-    for remaining_char in remaining_chars {
+    for (num_chars_in_class, remaining_char) in remaining_chars.enumerate() {
         if remaining_char == ']' && previous_char != '\\' {
             if num_chars_in_class == 0 {
                 return Err(TokenizeError::EmptyCharacterSet(position)); //("Empty character set at {}", position);
@@ -214,7 +270,6 @@ fn munch_character_class(remaining_chars: &mut Peekable<Chars>, position: usize)
 
             return Ok(token);
         }
-        num_chars_in_class += 1;
         next_position += remaining_char.len_utf8();
         previous_char = remaining_char;
     }
@@ -262,12 +317,173 @@ fn munch_character_class_escape(
         _ => None,
     };
 
-    if let Some(_) = character_class_escape_token {
+    if character_class_escape_token.is_some() {
         remaining_chars.next();
     }
     Ok(character_class_escape_token)
 }
 
+/// Consume the `<name>` following `(?` or `\k`, returning its length in bytes.
+fn munch_name(remaining_chars: &mut Peekable<Chars>) -> Option<usize> {
+    remaining_chars.next_if_eq(&'<')?;
+    let mut length = '<'.len_utf8();
+    while let Some(character) =
+        remaining_chars.next_if(|character| character.is_alphanumeric() || *character == '_')
+    {
+        // Names can't start with a digit, so they are never confused with group numbers
+        if length == '<'.len_utf8() && character.is_numeric() {
+            return None;
+        }
+        length += character.len_utf8();
+    }
+    if length == '<'.len_utf8() {
+        return None;
+    }
+    remaining_chars.next_if_eq(&'>')?;
+    Some(length + '>'.len_utf8())
+}
+
+/// Consume the `R)` or `1)` of a call following `(?`, returning its length in bytes.
+fn munch_subroutine(remaining_chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut lookahead = remaining_chars.clone();
+    let mut length = 0;
+    if lookahead.next_if_eq(&'R').is_some() {
+        length += 'R'.len_utf8();
+    } else {
+        while lookahead.next_if(char::is_ascii_digit).is_some() {
+            length += 1;
+        }
+    }
+    if length == 0 {
+        return None;
+    }
+    lookahead.next_if_eq(&')')?;
+    *remaining_chars = lookahead;
+    Some(length + ')'.len_utf8())
+}
+
+/// Consume the digits of a repetition count, returning the count and its length in bytes. Counts
+/// too large for a `u32` saturate, for the compiler to reject.
+fn munch_count(remaining_chars: &mut Peekable<Chars>) -> Option<(u32, usize)> {
+    let mut digits = String::new();
+    while let Some(digit) = remaining_chars.next_if(char::is_ascii_digit) {
+        digits.push(digit);
+    }
+    (!digits.is_empty()).then(|| (digits.parse::<u32>().unwrap_or(u32::MAX), digits.len()))
+}
+
+/// Consume the `m}`, `m,}` or `m,n}` of a counted repetition following `{`, returning its bounds
+/// and length in bytes.
+fn munch_repetition(remaining_chars: &mut Peekable<Chars>) -> Option<(QuantifierType, usize)> {
+    let mut lookahead = remaining_chars.clone();
+    let (min, mut length) = munch_count(&mut lookahead)?;
+    let max = if lookahead.next_if_eq(&',').is_some() {
+        length += ','.len_utf8();
+        munch_count(&mut lookahead).map(|(max, max_length)| {
+            length += max_length;
+            max
+        })
+    } else {
+        Some(min)
+    };
+    lookahead.next_if_eq(&'}')?;
+    *remaining_chars = lookahead;
+    Some((QuantifierType::Counted(min, max), length + '}'.len_utf8()))
+}
+
+fn munch_open_group(remaining_chars: &mut Peekable<Chars>, position: usize) -> Result<Token> {
+    if remaining_chars.next_if_eq(&'?').is_none() {
+        return Ok(Token::open_group(position));
+    }
+    let prefix_length = '('.len_utf8() + '?'.len_utf8();
+    // `length` is the number of bytes following `(?`
+    let group_of = |group_type: GroupType, length: usize| {
+        Token::create_long(
+            TokenType::OpenGroup(group_type),
+            position,
+            position + prefix_length + length,
+        )
+    };
+
+    if let Some(call_length) = munch_subroutine(remaining_chars) {
+        return Ok(Token::create_long(
+            TokenType::Literal(LiteralType::Subroutine),
+            position,
+            position + prefix_length + call_length,
+        ));
+    }
+
+    let mut lookahead = remaining_chars.clone();
+    let group_type = match (lookahead.next(), lookahead.next()) {
+        (Some(':'), _) => Some((GroupType::NonCapturing, 1)),
+        (Some('='), _) => Some((GroupType::Lookahead(true), 1)),
+        (Some('!'), _) => Some((GroupType::Lookahead(false), 1)),
+        (Some('>'), _) => Some((GroupType::Atomic, 1)),
+        (Some('<'), Some('=')) => Some((GroupType::Lookbehind(true), 2)),
+        (Some('<'), Some('!')) => Some((GroupType::Lookbehind(false), 2)),
+        _ => None,
+    };
+    if let Some((group_type, length)) = group_type {
+        remaining_chars.nth(length - 1);
+        return Ok(group_of(group_type, length));
+    }
+
+    munch_name(remaining_chars)
+        .map(|name_length| group_of(GroupType::Named, name_length))
+        .ok_or(TokenizeError::InvalidGroup(position))
+}
+
+/// Munch the escapes that stand for something other than a character: word boundaries and
+/// backreferences.
+fn munch_special_escape(
+    remaining_chars: &mut Peekable<Chars>,
+    position: usize,
+) -> Result<Option<Token>> {
+    let next_character = *remaining_chars
+        .peek()
+        .ok_or(TokenizeError::UnterminatedEscape(position))?;
+    let escape_length = '\\'.len_utf8() + next_character.len_utf8();
+
+    let token = match next_character {
+        'b' | 'B' => Token::create_long(
+            TokenType::Literal(LiteralType::WordBoundary(next_character == 'b')),
+            position,
+            position + escape_length,
+        ),
+        '1'..='9' => {
+            remaining_chars.next();
+            let mut end_position = position + escape_length;
+            while remaining_chars.next_if(char::is_ascii_digit).is_some() {
+                end_position += 1;
+            }
+            return Ok(Some(Token::create_long(
+                TokenType::Literal(LiteralType::Backreference),
+                position,
+                end_position,
+            )));
+        }
+        'k' => {
+            // Without a name to follow, `\k` is only an escaped `k`
+            let mut lookahead = remaining_chars.clone();
+            lookahead.next();
+            if lookahead.peek() != Some(&'<') {
+                return Ok(None);
+            }
+            remaining_chars.next();
+            let name_length =
+                munch_name(remaining_chars).ok_or(TokenizeError::InvalidBackreference(position))?;
+            return Ok(Some(Token::create_long(
+                TokenType::Literal(LiteralType::Backreference),
+                position,
+                position + escape_length + name_length,
+            )));
+        }
+        _ => return Ok(None),
+    };
+    remaining_chars.next();
+    Ok(Some(token))
+}
+
 fn munch_escape_character(remaining_chars: &mut Peekable<Chars>, position: usize) -> Result<Token> {
     // supports arbitrary escape characters, but also gives me flexibility to add word boundary
     // support in the future, etc. etc.
@@ -301,7 +517,7 @@ fn munch_token(
     match character {
         '(' => {
             insert_cons(tokens);
-            Ok(Token::open_group(position))
+            munch_open_group(remaining_chars, position)
         }
         ')' => Ok(Token::close_group(position)),
         '[' => {
@@ -310,12 +526,37 @@ fn munch_token(
         }
         '|' => Ok(Token::create(TokenType::Alternation, position)),
         '*' => Ok(Token::quantifier(QuantifierType::ZeroOrMore, position)),
-        '+' => Ok(Token::quantifier(QuantifierType::OneOrMore, position)),
+        '+' => match tokens.last() {
+            Some(Token {
+                kind: TokenType::Quantifier(_),
+                ..
+            }) => Ok(Token::create(TokenType::Possessive, position)),
+            _ => Ok(Token::quantifier(QuantifierType::OneOrMore, position)),
+        },
         '?' => Ok(Token::quantifier(QuantifierType::ZeroOrOne, position)),
+        // A brace that doesn't open a counted repetition is an ordinary character
+        '{' => match munch_repetition(remaining_chars) {
+            Some((QuantifierType::Counted(min, Some(max)), _)) if max < min => {
+                Err(TokenizeError::InvalidRepetition(position))
+            }
+            Some((kind, length)) => Ok(Token::create_long(
+                TokenType::Quantifier(kind),
+                position,
+                position + '{'.len_utf8() + length,
+            )),
+            None => {
+                insert_cons(tokens);
+                Ok(Token::create(
+                    TokenType::Literal(LiteralType::Character),
+                    position,
+                ))
+            }
+        },
         '\\' => {
             insert_cons(tokens);
             munch_character_class_escape(remaining_chars, position)
                 .transpose()
+                .or_else(|| munch_special_escape(remaining_chars, position).transpose())
                 .unwrap_or_else(|| munch_escape_character(remaining_chars, position))
         }
         '.' => {
@@ -383,6 +624,157 @@ mod tests {
         assert_eq!(tokenize(r"abc\"), Err(TokenizeError::UnterminatedEscape(3)));
     }
 
+    #[test]
+    fn test_backreferences_and_boundaries() {
+        assert_eq!(
+            vec![
+                Token::create_long(TokenType::Literal(LiteralType::WordBoundary(true)), 0, 2),
+                Token::cons(2),
+                Token::create_long(TokenType::OpenGroup(GroupType::Named), 2, 10),
+                Token::create(TokenType::Literal(LiteralType::Character), 10),
+                Token::close_group(11),
+                Token::cons(12),
+                Token::create_long(TokenType::Literal(LiteralType::Backreference), 12, 15),
+                Token::cons(15),
+                Token::create_long(TokenType::Literal(LiteralType::Backreference), 15, 23),
+                Token::cons(23),
+                Token::create_long(TokenType::Literal(LiteralType::WordBoundary(false)), 23, 25),
+            ],
+            tokenize(r"\b(?<word>a)\12\k<word>\B").unwrap()
+        );
+        assert_eq!(
+            tokenize(r"(?<word>a)")
+                .unwrap()
+                .first()
+                .and_then(|token| token.group_name(r"(?<word>a)")),
+            Some("word")
+        );
+    }
+
+    #[test]
+    fn test_group_types() {
+        let group_types: Vec<GroupType> = tokenize(r"(a)(?<n>a)(?:a)(?=a)(?!a)(?<=a)(?<!a)")
+            .unwrap()
+            .into_iter()
+            .filter_map(|token| match token.kind {
+                TokenType::OpenGroup(group_type) => Some(group_type),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(
+            group_types,
+            vec![
+                GroupType::Capturing,
+                GroupType::Named,
+                GroupType::NonCapturing,
+                GroupType::Lookahead(true),
+                GroupType::Lookahead(false),
+                GroupType::Lookbehind(true),
+                GroupType::Lookbehind(false),
+            ]
+        );
+        assert_eq!(
+            tokenize(r"(?<!a)b").unwrap()[0],
+            Token::create_long(TokenType::OpenGroup(GroupType::Lookbehind(false)), 0, 4)
+        );
+    }
+
+    #[test]
+    fn test_possessive_quantifiers_and_atomic_groups() {
+        assert_eq!(
+            tokenize(r"a*+(?>b)").unwrap(),
+            vec![
+                Token::create(TokenType::Literal(LiteralType::Character), 0),
+                Token::quantifier(QuantifierType::ZeroOrMore, 1),
+                Token::create(TokenType::Possessive, 2),
+                Token::cons(3),
+                Token::create_long(TokenType::OpenGroup(GroupType::Atomic), 3, 6),
+                Token::create(TokenType::Literal(LiteralType::Character), 6),
+                Token::close_group(7),
+            ]
+        );
+        // Only a `+` directly after a quantifier makes it possessive
+        assert_eq!(
+            tokenize(r"a+").unwrap()[1],
+            Token::quantifier(QuantifierType::OneOrMore, 1)
+        );
+        assert_eq!(
+            tokenize(r"a?++").unwrap()[3],
+            Token::quantifier(QuantifierType::OneOrMore, 3)
+        );
+    }
+
+    #[test]
+    fn test_counted_repetition() {
+        assert_eq!(
+            tokenize(r"a{2}b{3,}c{0,12}").unwrap(),
+            vec![
+                Token::create(TokenType::Literal(LiteralType::Character), 0),
+                Token::create_long(
+                    TokenType::Quantifier(QuantifierType::Counted(2, Some(2))),
+                    1,
+                    4
+                ),
+                Token::cons(4),
+                Token::create(TokenType::Literal(LiteralType::Character), 4),
+                Token::create_long(
+                    TokenType::Quantifier(QuantifierType::Counted(3, None)),
+                    5,
+                    9
+                ),
+                Token::cons(9),
+                Token::create(TokenType::Literal(LiteralType::Character), 9),
+                Token::create_long(
+                    TokenType::Quantifier(QuantifierType::Counted(0, Some(12))),
+                    10,
+                    16
+                ),
+            ]
+        );
+        // Braces that don't hold bounds are characters
+        assert_eq!(
+            tokenize(r"a{,2}").unwrap()[2],
+            Token::create(TokenType::Literal(LiteralType::Character), 1)
+        );
+        assert_eq!(tokenize(r"{x}").unwrap().len(), 5);
+        assert_eq!(
+            tokenize(r"a{99999999999}").unwrap()[1].kind,
+            TokenType::Quantifier(QuantifierType::Counted(u32::MAX, Some(u32::MAX)))
+        );
+        assert_eq!(
+            tokenize(r"ab{3,2}"),
+            Err(TokenizeError::InvalidRepetition(2))
+        );
+    }
+
+    #[test]
+    fn test_subroutine_calls() {
+        assert_eq!(
+            tokenize(r"a(?R)(?12)").unwrap(),
+            vec![
+                Token::create(TokenType::Literal(LiteralType::Character), 0),
+                Token::cons(1),
+                Token::create_long(TokenType::Literal(LiteralType::Subroutine), 1, 5),
+                Token::cons(5),
+                Token::create_long(TokenType::Literal(LiteralType::Subroutine), 5, 10),
+            ]
+        );
+        assert_eq!(tokenize(r"(?R"), Err(TokenizeError::InvalidGroup(0)));
+        assert_eq!(tokenize(r"(?1a)"), Err(TokenizeError::InvalidGroup(0)));
+    }
+
+    #[test]
+    fn test_invalid_groups_and_backreferences() {
+        assert_eq!(tokenize(r"a(?<1a>b)"), Err(TokenizeError::InvalidGroup(1)));
+        assert_eq!(tokenize(r"a(?<>b)"), Err(TokenizeError::InvalidGroup(1)));
+        assert_eq!(tokenize(r"a(?b)"), Err(TokenizeError::InvalidGroup(1)));
+        assert_eq!(
+            tokenize(r"(a)\k<a"),
+            Err(TokenizeError::InvalidBackreference(3))
+        );
+    }
+
     #[test]
     fn test_tokenize() {
         // pattern: `ab+`